@@ -1,5 +1,9 @@
 use windows::Win32::UI::WindowsAndMessaging::CW_USEDEFAULT;
 
+use std::path::PathBuf;
+
+use super::MergeRule;
+
 pub fn window_width() -> i32 { 800 }
 pub fn window_height() -> i32 { 600 }
 pub fn window_title() -> String { "Stremio Shell".into() }
@@ -15,3 +19,60 @@ pub fn webview_initial_url() -> String {
 }
 pub fn webview_width() -> i32 {800 }
 pub fn webview_height() -> i32 {600}
+pub fn metadata_provider_timeout_ms() -> u64 { 5000 }
+pub fn metadata_preferred_languages() -> Vec<String> { vec!["en".into()] }
+pub fn merge_title() -> MergeRule { MergeRule::PreferredLanguage }
+pub fn merge_poster() -> MergeRule { MergeRule::HighestResolution }
+pub fn merge_genres() -> MergeRule { MergeRule::Union }
+pub fn merge_priority() -> MergeRule { MergeRule::Priority }
+pub fn cache_enabled() -> bool { true }
+pub fn cache_meta_ttl_secs() -> u64 { 7 * 24 * 60 * 60 }
+pub fn cache_catalog_ttl_secs() -> u64 { 6 * 60 * 60 }
+pub fn cache_stale_ttl_secs() -> u64 { 30 * 24 * 60 * 60 }
+pub fn cache_negative_ttl_secs() -> u64 { 30 * 60 }
+pub fn cache_max_size_mb() -> u64 { 64 }
+pub fn ids_anime_list_url() -> String {
+    "https://raw.githubusercontent.com/Fribb/anime-lists/master/anime-list-full.json".into()
+}
+pub fn ids_anime_list_refresh_hours() -> u64 { 24 * 7 }
+pub fn image_cache_max_size_mb() -> u64 { 256 }
+pub fn image_thumbnail_widths() -> Vec<u32> { vec![185, 342, 500, 780] }
+pub fn image_download_timeout_ms() -> u64 { 10_000 }
+pub fn image_max_download_mb() -> u64 { 20 }
+pub fn library_extensions() -> Vec<String> {
+    ["mkv", "mp4", "m4v", "avi", "mov", "wmv", "webm", "ts", "mpg", "mpeg"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+pub fn library_watch() -> bool { true }
+pub fn library_min_confidence() -> f32 { 0.75 }
+pub fn calendar_notify() -> bool { true }
+pub fn calendar_check_interval_mins() -> u64 { 60 }
+pub fn addon_request_timeout_ms() -> u64 { 10_000 }
+pub fn addon_fan_out_timeout_ms() -> u64 { 5_000 }
+pub fn addon_update_interval_mins() -> u64 { 360 }
+pub fn addon_cache_max_entries() -> usize { 500 }
+pub fn sandbox_fuel_per_call() -> u64 { 2_000_000_000 }
+pub fn sandbox_memory_limit_mb() -> u64 { 64 }
+pub fn sandbox_storage_quota_kb() -> u64 { 1024 }
+pub fn sandbox_http_timeout_ms() -> u64 { 10_000 }
+pub fn process_memory_limit_mb() -> u64 { 512 }
+pub fn process_cpu_time_secs() -> u64 { 3600 }
+pub fn process_open_files() -> u64 { 256 }
+pub fn process_max_backoff_secs() -> u64 { 60 }
+pub fn breaker_failure_threshold() -> u32 { 5 }
+pub fn breaker_cooldown_secs() -> u64 { 60 }
+pub fn addon_server_port() -> u16 { 7000 }
+pub fn addon_builtin() -> Vec<String> {
+    vec!["https://v3-cinemeta.strem.io/manifest.json".into()]
+}
+#[cfg(debug_assertions)] // Serve the bundled fixtures in debug mode
+pub fn metadata_fixtures_dir() -> Option<PathBuf> {
+    Some(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures").join("metadata"))
+}
+
+#[cfg(not(debug_assertions))]
+pub fn metadata_fixtures_dir() -> Option<PathBuf> {
+    None
+}
@@ -5,13 +5,23 @@ use std::path::PathBuf;
 
 
 
-mod paths;
+pub mod paths;
 mod defaults;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
     pub window: WindowConfig,
     pub webview: WebViewConfig,
+    #[serde(default)]
+    pub metadata: MetadataConfig,
+    #[serde(default)]
+    pub images: ImageCacheConfig,
+    #[serde(default)]
+    pub library: LibraryConfig,
+    #[serde(default)]
+    pub calendar: CalendarConfig,
+    #[serde(default)]
+    pub addons: AddonsConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -31,6 +41,366 @@ pub struct WebViewConfig {
     pub height: i32,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataConfig {
+    #[serde(default = "defaults::metadata_provider_timeout_ms")]
+    pub provider_timeout_ms: u64,
+    /// Provider ids in descending priority; unlisted providers rank after these
+    /// in registration order.
+    #[serde(default)]
+    pub provider_priority: Vec<String>,
+    #[serde(default = "defaults::metadata_preferred_languages")]
+    pub preferred_languages: Vec<String>,
+    #[serde(default)]
+    pub merge: MergeConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    /// Directory of JSON fixtures served by the fixture provider; see
+    /// [`MetadataConfig::fixtures_dir`] for the default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fixtures_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub ids: IdMappingConfig,
+}
+
+impl Default for MetadataConfig {
+    fn default() -> Self {
+        Self {
+            provider_timeout_ms: defaults::metadata_provider_timeout_ms(),
+            provider_priority: Vec::new(),
+            preferred_languages: defaults::metadata_preferred_languages(),
+            merge: MergeConfig::default(),
+            cache: CacheConfig::default(),
+            fixtures_dir: None,
+            ids: IdMappingConfig::default(),
+        }
+    }
+}
+
+impl MetadataConfig {
+    /// The configured fixture directory or, in debug builds, the one in the
+    /// source tree. Resolved at runtime so that path never gets saved.
+    pub fn fixtures_dir(&self) -> Option<PathBuf> {
+        self.fixtures_dir.clone().or_else(defaults::metadata_fixtures_dir)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdMappingConfig {
+    /// Enables TMDB/TVDB lookups and season lengths for absolute numbering.
+    #[serde(default)]
+    pub tmdb_api_key: Option<String>,
+    /// Kitsu/AniList mapping dataset; empty disables it.
+    #[serde(default = "defaults::ids_anime_list_url")]
+    pub anime_list_url: String,
+    #[serde(default = "defaults::ids_anime_list_refresh_hours")]
+    pub anime_list_refresh_hours: u64,
+}
+
+impl Default for IdMappingConfig {
+    fn default() -> Self {
+        Self {
+            tmdb_api_key: None,
+            anime_list_url: defaults::ids_anime_list_url(),
+            anime_list_refresh_hours: defaults::ids_anime_list_refresh_hours(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheConfig {
+    #[serde(default = "defaults::cache_enabled")]
+    pub enabled: bool,
+    #[serde(default = "defaults::cache_meta_ttl_secs")]
+    pub meta_ttl_secs: u64,
+    #[serde(default = "defaults::cache_catalog_ttl_secs")]
+    pub catalog_ttl_secs: u64,
+    /// How long past its TTL an entry may still be served while it is
+    /// refreshed in the background.
+    #[serde(default = "defaults::cache_stale_ttl_secs")]
+    pub stale_ttl_secs: u64,
+    /// How long an empty answer (an unknown id, a search without results)
+    /// is kept before the providers are asked again.
+    #[serde(default = "defaults::cache_negative_ttl_secs")]
+    pub negative_ttl_secs: u64,
+    #[serde(default = "defaults::cache_max_size_mb")]
+    pub max_size_mb: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: defaults::cache_enabled(),
+            meta_ttl_secs: defaults::cache_meta_ttl_secs(),
+            catalog_ttl_secs: defaults::cache_catalog_ttl_secs(),
+            stale_ttl_secs: defaults::cache_stale_ttl_secs(),
+            negative_ttl_secs: defaults::cache_negative_ttl_secs(),
+            max_size_mb: defaults::cache_max_size_mb(),
+        }
+    }
+}
+
+/// Per-field rule used when several providers return the same item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MergeRule {
+    /// Take the value from the highest-priority provider that has one.
+    Priority,
+    /// Take the value from a provider whose language is preferred.
+    PreferredLanguage,
+    /// Take the value with the largest reported resolution.
+    HighestResolution,
+    /// Combine values from every provider, keeping first-seen order.
+    Union,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeConfig {
+    #[serde(default = "defaults::merge_title")]
+    pub title: MergeRule,
+    #[serde(default = "defaults::merge_poster")]
+    pub poster: MergeRule,
+    #[serde(default = "defaults::merge_genres")]
+    pub genres: MergeRule,
+    #[serde(default = "defaults::merge_priority")]
+    pub description: MergeRule,
+    #[serde(default = "defaults::merge_priority")]
+    pub cast: MergeRule,
+    #[serde(default = "defaults::merge_priority")]
+    pub year: MergeRule,
+}
+
+impl Default for MergeConfig {
+    fn default() -> Self {
+        Self {
+            title: defaults::merge_title(),
+            poster: defaults::merge_poster(),
+            genres: defaults::merge_genres(),
+            description: defaults::merge_priority(),
+            cast: defaults::merge_priority(),
+            year: defaults::merge_priority(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageCacheConfig {
+    #[serde(default = "defaults::image_cache_max_size_mb")]
+    pub max_size_mb: u64,
+    /// Widths that resize requests are snapped to, so each image is stored
+    /// in a handful of sizes at most.
+    #[serde(default = "defaults::image_thumbnail_widths")]
+    pub thumbnail_widths: Vec<u32>,
+    #[serde(default = "defaults::image_download_timeout_ms")]
+    pub download_timeout_ms: u64,
+    /// Larger downloads are abandoned rather than cached.
+    #[serde(default = "defaults::image_max_download_mb")]
+    pub max_download_mb: u64,
+}
+
+impl Default for ImageCacheConfig {
+    fn default() -> Self {
+        Self {
+            max_size_mb: defaults::image_cache_max_size_mb(),
+            thumbnail_widths: defaults::image_thumbnail_widths(),
+            download_timeout_ms: defaults::image_download_timeout_ms(),
+            max_download_mb: defaults::image_max_download_mb(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryConfig {
+    #[serde(default)]
+    pub folders: Vec<PathBuf>,
+    #[serde(default = "defaults::library_extensions")]
+    pub extensions: Vec<String>,
+    #[serde(default = "defaults::library_watch")]
+    pub watch: bool,
+    /// Matches scoring below this are left unmatched.
+    #[serde(default = "defaults::library_min_confidence")]
+    pub min_confidence: f32,
+    /// Write manual match corrections to Kodi NFO files.
+    #[serde(default)]
+    pub write_nfo: bool,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        Self {
+            folders: Vec::new(),
+            extensions: defaults::library_extensions(),
+            watch: defaults::library_watch(),
+            min_confidence: defaults::library_min_confidence(),
+            write_nfo: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalendarConfig {
+    /// Raise an event when an episode of a library series airs.
+    #[serde(default = "defaults::calendar_notify")]
+    pub notify: bool,
+    #[serde(default = "defaults::calendar_check_interval_mins")]
+    pub check_interval_mins: u64,
+}
+
+impl Default for CalendarConfig {
+    fn default() -> Self {
+        Self {
+            notify: defaults::calendar_notify(),
+            check_interval_mins: defaults::calendar_check_interval_mins(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddonsConfig {
+    #[serde(default = "defaults::addon_request_timeout_ms")]
+    pub request_timeout_ms: u64,
+    /// Per-addon deadline when many addons are asked at once.
+    #[serde(default = "defaults::addon_fan_out_timeout_ms")]
+    pub fan_out_timeout_ms: u64,
+    /// How often installed addons' manifests are checked for updates; 0
+    /// disables the checks.
+    #[serde(default = "defaults::addon_update_interval_mins")]
+    pub update_interval_mins: u64,
+    /// Addon responses kept in memory; 0 disables the cache.
+    #[serde(default = "defaults::addon_cache_max_entries")]
+    pub cache_max_entries: usize,
+    /// Transport URLs of addons that are always installed.
+    #[serde(default = "defaults::addon_builtin")]
+    pub builtin: Vec<String>,
+    /// Signed indexes the addon store lists addons from.
+    #[serde(default)]
+    pub repositories: Vec<RepositoryConfig>,
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+    #[serde(default)]
+    pub process: ProcessConfig,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+    #[serde(default)]
+    pub server: AddonServerConfig,
+}
+
+impl Default for AddonsConfig {
+    fn default() -> Self {
+        Self {
+            request_timeout_ms: defaults::addon_request_timeout_ms(),
+            fan_out_timeout_ms: defaults::addon_fan_out_timeout_ms(),
+            update_interval_mins: defaults::addon_update_interval_mins(),
+            cache_max_entries: defaults::addon_cache_max_entries(),
+            builtin: defaults::addon_builtin(),
+            repositories: Vec::new(),
+            sandbox: SandboxConfig::default(),
+            process: ProcessConfig::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            server: AddonServerConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepositoryConfig {
+    /// `http(s)` URL or path of the index; its signature is read from the
+    /// same location with `.sig` appended.
+    pub url: String,
+    /// Hex-encoded Ed25519 key the index must be signed with.
+    pub public_key: String,
+}
+
+/// Limits for WebAssembly addons, applied to every call.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SandboxConfig {
+    #[serde(default = "defaults::sandbox_fuel_per_call")]
+    pub fuel_per_call: u64,
+    #[serde(default = "defaults::sandbox_memory_limit_mb")]
+    pub memory_limit_mb: u64,
+    #[serde(default = "defaults::sandbox_storage_quota_kb")]
+    pub storage_quota_kb: u64,
+    #[serde(default = "defaults::sandbox_http_timeout_ms")]
+    pub http_timeout_ms: u64,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            fuel_per_call: defaults::sandbox_fuel_per_call(),
+            memory_limit_mb: defaults::sandbox_memory_limit_mb(),
+            storage_quota_kb: defaults::sandbox_storage_quota_kb(),
+            http_timeout_ms: defaults::sandbox_http_timeout_ms(),
+        }
+    }
+}
+
+/// Limits for addons run as local processes. A limit of 0 is not applied;
+/// the rlimits only take effect on Linux.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessConfig {
+    #[serde(default = "defaults::process_memory_limit_mb")]
+    pub memory_limit_mb: u64,
+    /// CPU seconds over the process's lifetime; it is killed and restarted
+    /// when they run out.
+    #[serde(default = "defaults::process_cpu_time_secs")]
+    pub cpu_time_secs: u64,
+    #[serde(default = "defaults::process_open_files")]
+    pub open_files: u64,
+    /// Longest wait before restarting a crashed process.
+    #[serde(default = "defaults::process_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+}
+
+impl Default for ProcessConfig {
+    fn default() -> Self {
+        Self {
+            memory_limit_mb: defaults::process_memory_limit_mb(),
+            cpu_time_secs: defaults::process_cpu_time_secs(),
+            open_files: defaults::process_open_files(),
+            max_backoff_secs: defaults::process_max_backoff_secs(),
+        }
+    }
+}
+
+/// When to stop asking a failing addon, and for how long.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Failures in a row that open the circuit; 0 never opens it.
+    #[serde(default = "defaults::breaker_failure_threshold")]
+    pub failure_threshold: u32,
+    #[serde(default = "defaults::breaker_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: defaults::breaker_failure_threshold(),
+            cooldown_secs: defaults::breaker_cooldown_secs(),
+        }
+    }
+}
+
+/// Serves the native addons over the HTTP addon protocol on localhost, so
+/// other apps can install them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddonServerConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "defaults::addon_server_port")]
+    pub port: u16,
+}
+
+impl Default for AddonServerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: defaults::addon_server_port(),
+        }
+    }
+}
+
 pub fn load() -> Result<AppConfig> {
     let config_path = paths::config_file()?;
     info!("Loading config from: {:?}", config_path);
@@ -52,6 +422,11 @@ pub fn load() -> Result<AppConfig> {
                 width: defaults::webview_width(),
                 height: defaults::webview_height(),
             },
+            metadata: MetadataConfig::default(),
+            images: ImageCacheConfig::default(),
+            library: LibraryConfig::default(),
+            calendar: CalendarConfig::default(),
+            addons: AddonsConfig::default(),
         };
         save(&default_config)?;
         Ok(default_config)
@@ -17,4 +17,48 @@ pub fn webview_data_dir() -> Result<PathBuf> {
     let proj_dirs = ProjectDirs::from("", "Stremio", "DesktopShell")
         .context("Couldn't determine data directory")?;
     Ok(proj_dirs.data_dir().join("webview_data"))
+}
+
+pub fn data_dir() -> Result<PathBuf> {
+    let proj_dirs = ProjectDirs::from("", "Stremio", "DesktopShell")
+        .context("Couldn't determine data directory")?;
+    Ok(proj_dirs.data_dir().to_path_buf())
+}
+
+pub fn metadata_cache_file() -> Result<PathBuf> {
+    Ok(data_dir()?.join("metadata_cache.sqlite"))
+}
+
+pub fn id_map_file() -> Result<PathBuf> {
+    Ok(data_dir()?.join("id_map.sqlite"))
+}
+
+pub fn anime_list_file() -> Result<PathBuf> {
+    Ok(data_dir()?.join("anime_list.json"))
+}
+
+pub fn calendar_state_file() -> Result<PathBuf> {
+    Ok(data_dir()?.join("calendar_state.json"))
+}
+
+pub fn addon_collection_file() -> Result<PathBuf> {
+    Ok(data_dir()?.join("addons.json"))
+}
+
+pub fn sandboxed_addons_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("sandboxed_addons"))
+}
+
+pub fn process_addons_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("process_addons"))
+}
+
+pub fn library_matches_file() -> Result<PathBuf> {
+    Ok(data_dir()?.join("library_matches.sqlite"))
+}
+
+pub fn image_cache_dir() -> Result<PathBuf> {
+    let proj_dirs = ProjectDirs::from("", "Stremio", "DesktopShell")
+        .context("Couldn't determine cache directory")?;
+    Ok(proj_dirs.cache_dir().join("images"))
 }
\ No newline at end of file
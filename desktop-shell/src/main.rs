@@ -14,6 +14,20 @@ use windows::Win32::{
 
 fn main() -> Result<()> {
     utils::logging::init_logger()?;
+
+    // `desktop-shell serve-addons [port]` only serves the native addons over
+    // HTTP, without a window.
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("serve-addons") {
+        let config = config::load().context("Config load failed")?;
+        let port = args
+            .next()
+            .map(|port| port.parse::<u16>())
+            .transpose()
+            .context("Invalid port")?;
+        return services::ServiceManager::serve_addons(&config, port);
+    }
+
     log::info!("Starting application initialization");
 
     unsafe {
@@ -26,9 +40,13 @@ fn main() -> Result<()> {
     let hwnd = window::create_window(&config.window)?;
 
     let service_manager = std::sync::Arc::new(
-        services::ServiceManager::init().context("Service manager init failed")?,
+        services::ServiceManager::init(&config).context("Service manager init failed")?,
     );
 
+    service_manager
+        .events()
+        .attach(window::event_poster(hwnd));
+
     log::info!("Window created successfully");
     log::info!("WebView manager creation starting");
     let webview_manager = manager::WebViewManager::create(
@@ -0,0 +1,130 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use futures::future::{BoxFuture, Shared};
+use serde_json::Value;
+
+use super::protocol::CacheHints;
+
+/// A request that callers asking for the same URL share.
+pub type InFlight = Shared<BoxFuture<'static, Result<Value, String>>>;
+
+pub enum Lookup {
+    Fresh(Value),
+    /// Past `cacheMaxAge` but inside `staleRevalidate`; serve it and refresh.
+    Stale(Value),
+    /// Needs fetching. Carries the cached value if it is still inside
+    /// `staleError`, to fall back on if the addon fails.
+    Miss(Option<Value>),
+}
+
+struct Entry {
+    value: Value,
+    fetched: Instant,
+    hints: CacheHints,
+}
+
+impl Entry {
+    fn max_age(&self) -> Duration {
+        Duration::from_secs(self.hints.cache_max_age.unwrap_or(0))
+    }
+
+    /// How long after `fetched` the entry may still be served in some form.
+    fn lifetime(&self) -> Duration {
+        let grace = self
+            .hints
+            .stale_revalidate
+            .unwrap_or(0)
+            .max(self.hints.stale_error.unwrap_or(0));
+        self.max_age() + Duration::from_secs(grace)
+    }
+}
+
+/// In-memory cache of addon responses keyed by request URL, following the
+/// caching hints addons attach to their responses.
+pub struct ResponseCache {
+    entries: Mutex<HashMap<String, Entry>>,
+    in_flight: Mutex<HashMap<String, InFlight>>,
+    max_entries: usize,
+}
+
+impl ResponseCache {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            in_flight: Mutex::new(HashMap::new()),
+            max_entries,
+        }
+    }
+
+    pub fn lookup(&self, url: &str) -> Lookup {
+        let entries = lock(&self.entries);
+        let Some(entry) = entries.get(url) else {
+            return Lookup::Miss(None);
+        };
+        let age = entry.fetched.elapsed();
+        let max_age = entry.max_age();
+        let within = |secs: Option<u64>| age < max_age + Duration::from_secs(secs.unwrap_or(0));
+
+        if age < max_age {
+            Lookup::Fresh(entry.value.clone())
+        } else if within(entry.hints.stale_revalidate) {
+            Lookup::Stale(entry.value.clone())
+        } else if within(entry.hints.stale_error) {
+            Lookup::Miss(Some(entry.value.clone()))
+        } else {
+            Lookup::Miss(None)
+        }
+    }
+
+    /// Stores a response if its hints allow serving it later in any form.
+    pub fn store(&self, url: &str, value: &Value) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let hints: CacheHints = serde_json::from_value(value.clone()).unwrap_or_default();
+        let entry = Entry {
+            value: value.clone(),
+            fetched: Instant::now(),
+            hints,
+        };
+        if entry.lifetime().is_zero() {
+            return;
+        }
+
+        let mut entries = lock(&self.entries);
+        entries.insert(url.to_string(), entry);
+        if entries.len() > self.max_entries {
+            entries.retain(|_, entry| entry.fetched.elapsed() < entry.lifetime());
+        }
+        while entries.len() > self.max_entries {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.fetched)
+                .map(|(url, _)| url.clone());
+            match oldest {
+                Some(url) => entries.remove(&url),
+                None => break,
+            };
+        }
+    }
+
+    /// The request already running for `url`, or the one `start` creates.
+    pub fn in_flight(&self, url: &str, start: impl FnOnce() -> InFlight) -> InFlight {
+        lock(&self.in_flight)
+            .entry(url.to_string())
+            .or_insert_with(start)
+            .clone()
+    }
+
+    pub fn finished(&self, url: &str) {
+        lock(&self.in_flight).remove(url);
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|p| p.into_inner())
+}
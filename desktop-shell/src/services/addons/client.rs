@@ -0,0 +1,199 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::FutureExt;
+use log::{debug, warn};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use url::Url;
+
+use super::cache::{InFlight, Lookup, ResponseCache};
+use super::manifest::Manifest;
+use super::protocol::{
+    CatalogResponse, MetaResponse, ResourceRequest, StreamResponse, SubtitlesResponse,
+};
+use super::validation::parse_manifest;
+
+/// HTTP client for the Stremio addon protocol. Resource responses are
+/// cached as their `cacheMaxAge`, `staleRevalidate` and `staleError` hints
+/// allow.
+pub struct AddonClient {
+    http: reqwest::Client,
+    cache: Arc<ResponseCache>,
+}
+
+impl AddonClient {
+    pub fn new(timeout: Duration, cache_max_entries: usize) -> Result<Self> {
+        Ok(Self {
+            http: reqwest::Client::builder().timeout(timeout).build()?,
+            cache: Arc::new(ResponseCache::new(cache_max_entries)),
+        })
+    }
+
+    /// Fetches and validates a manifest, failing with the validation errors
+    /// if it is not installable.
+    pub async fn manifest(&self, transport_url: &str) -> Result<Manifest> {
+        let raw = self.raw_manifest(transport_url).await?;
+        parse_manifest(transport_url, raw)
+    }
+
+    /// The manifest JSON as served, for reporting on manifests that may not
+    /// parse.
+    pub async fn raw_manifest(&self, transport_url: &str) -> Result<Value> {
+        let url = format!("{}/manifest.json", base_url(transport_url)?);
+        get(&self.http, &url)
+            .await
+            .with_context(|| format!("Failed to load addon manifest from {}", transport_url))
+    }
+
+    pub async fn resource<T: DeserializeOwned>(
+        &self,
+        transport_url: &str,
+        request: &ResourceRequest,
+    ) -> Result<T> {
+        let url = resource_url(transport_url, request)?;
+        let value = self.cached(&url).await?;
+        serde_json::from_value(value).with_context(|| format!("Invalid response from {}", url))
+    }
+
+    pub async fn catalog(
+        &self,
+        transport_url: &str,
+        media_type: &str,
+        id: &str,
+        extra: &[(String, String)],
+    ) -> Result<CatalogResponse> {
+        let mut request = ResourceRequest::new("catalog", media_type, id);
+        request.extra = extra.to_vec();
+        self.resource(transport_url, &request).await
+    }
+
+    pub async fn meta(
+        &self,
+        transport_url: &str,
+        media_type: &str,
+        id: &str,
+    ) -> Result<MetaResponse> {
+        self.resource(transport_url, &ResourceRequest::new("meta", media_type, id))
+            .await
+    }
+
+    pub async fn streams(
+        &self,
+        transport_url: &str,
+        media_type: &str,
+        id: &str,
+    ) -> Result<StreamResponse> {
+        self.resource(
+            transport_url,
+            &ResourceRequest::new("stream", media_type, id),
+        )
+        .await
+    }
+
+    pub async fn subtitles(
+        &self,
+        transport_url: &str,
+        media_type: &str,
+        id: &str,
+        extra: &[(String, String)],
+    ) -> Result<SubtitlesResponse> {
+        let mut request = ResourceRequest::new("subtitles", media_type, id);
+        request.extra = extra.to_vec();
+        self.resource(transport_url, &request).await
+    }
+
+    /// Calls a resource and returns the response, checked against the
+    /// protocol's response shape for the known resources.
+    pub async fn checked(&self, transport_url: &str, request: &ResourceRequest) -> Result<Value> {
+        let (media_type, id, extra) = (&request.media_type, &request.id, &request.extra);
+        let response = match request.resource.as_str() {
+            "catalog" => {
+                let response = self.catalog(transport_url, media_type, id, extra);
+                serde_json::to_value(response.await?)?
+            }
+            "meta" => serde_json::to_value(self.meta(transport_url, media_type, id).await?)?,
+            "stream" => serde_json::to_value(self.streams(transport_url, media_type, id).await?)?,
+            "subtitles" => {
+                let response = self.subtitles(transport_url, media_type, id, extra);
+                serde_json::to_value(response.await?)?
+            }
+            _ => self.resource(transport_url, request).await?,
+        };
+        Ok(response)
+    }
+
+    async fn cached(&self, url: &str) -> Result<Value> {
+        match self.cache.lookup(url) {
+            Lookup::Fresh(value) => Ok(value),
+            Lookup::Stale(value) => {
+                tokio::spawn(self.fetch(url));
+                Ok(value)
+            }
+            Lookup::Miss(fallback) => match (self.fetch(url).await, fallback) {
+                (Ok(value), _) => Ok(value),
+                (Err(e), Some(stale)) => {
+                    warn!("Serving stale response for {}: {}", url, e);
+                    Ok(stale)
+                }
+                (Err(e), None) => Err(anyhow!(e)),
+            },
+        }
+    }
+
+    /// Fetches `url`, joining the request already running for it if any.
+    fn fetch(&self, url: &str) -> InFlight {
+        self.cache.in_flight(url, || {
+            let (http, cache, url) = (self.http.clone(), self.cache.clone(), url.to_string());
+            async move {
+                let result = get(&http, &url).await.map_err(|e| format!("{:#}", e));
+                if let Ok(value) = &result {
+                    cache.store(&url, value);
+                }
+                cache.finished(&url);
+                result
+            }
+            .boxed()
+            .shared()
+        })
+    }
+}
+
+async fn get(http: &reqwest::Client, url: &str) -> Result<Value> {
+    debug!("Addon request: {}", url);
+    let response = http.get(url).send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        bail!("{} returned HTTP {}", url, status);
+    }
+    let body = response.bytes().await?;
+    serde_json::from_slice(&body).with_context(|| format!("Invalid response from {}", url))
+}
+
+/// Turns a transport URL (`https://host/path/manifest.json`, or the
+/// `stremio://` form used by install links) into the base URL resources are
+/// requested under.
+pub fn base_url(transport_url: &str) -> Result<String> {
+    let trimmed = transport_url.trim();
+    let normalized = match trimmed.strip_prefix("stremio://") {
+        Some(rest) => format!("https://{}", rest),
+        None => trimmed.to_string(),
+    };
+    let mut url = Url::parse(&normalized)
+        .with_context(|| format!("Invalid addon URL {:?}", transport_url))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        bail!("Unsupported addon URL scheme {:?}", url.scheme());
+    }
+    url.set_fragment(None);
+    if url.query().is_some() {
+        bail!("Addon URLs cannot have a query string: {:?}", transport_url);
+    }
+
+    let url = url.to_string();
+    let base = url.strip_suffix("/manifest.json").unwrap_or(&url);
+    Ok(base.trim_end_matches('/').to_string())
+}
+
+pub fn resource_url(transport_url: &str, request: &ResourceRequest) -> Result<String> {
+    Ok(format!("{}{}", base_url(transport_url)?, request.path()))
+}
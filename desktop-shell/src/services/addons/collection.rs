@@ -0,0 +1,317 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use super::client::base_url;
+use super::manifest::Manifest;
+use super::settings::AddonSettings;
+use super::updates;
+use super::{native, process, sandbox};
+
+/// An addon in the user's collection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstalledAddon {
+    /// Normalized to `<base url>/manifest.json`.
+    pub transport_url: String,
+    /// The manifest as last fetched.
+    pub manifest: Manifest,
+    /// The manifest the last update replaced, to roll back to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_manifest: Option<Manifest>,
+    /// A version rolled back from, which update checks leave alone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skipped_version: Option<String>,
+    #[serde(default)]
+    pub flags: AddonFlags,
+    /// Set for addons installed through their settings form;
+    /// `transport_url` carries the values.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<AddonSettings>,
+    pub installed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AddonFlags {
+    pub enabled: bool,
+    /// Built-in addons that cannot be uninstalled.
+    pub protected: bool,
+}
+
+impl Default for AddonFlags {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            protected: false,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CollectionFile {
+    addons: Vec<InstalledAddon>,
+}
+
+/// The ordered list of installed addons, persisted as JSON. Order is the
+/// order addons are asked for resources.
+pub struct AddonCollection {
+    path: PathBuf,
+    protected: Vec<String>,
+    addons: Mutex<Vec<InstalledAddon>>,
+}
+
+impl AddonCollection {
+    /// Loads the collection, marking the addons at `protected` transport
+    /// URLs as built-in.
+    pub fn open(path: &Path, protected: &[String]) -> Self {
+        let protected: Vec<String> = protected
+            .iter()
+            .filter_map(|url| match transport_url(url) {
+                Ok(url) => Some(url),
+                Err(e) => {
+                    error!("Ignoring built-in addon {:?}: {}", url, e);
+                    None
+                }
+            })
+            .collect();
+
+        let mut addons = match std::fs::read_to_string(path) {
+            Ok(content) => match serde_json::from_str::<CollectionFile>(&content) {
+                Ok(file) => file.addons,
+                Err(e) => {
+                    error!("Addon collection at {:?} is unreadable: {}", path, e);
+                    Vec::new()
+                }
+            },
+            Err(_) => Vec::new(),
+        };
+        for addon in &mut addons {
+            addon.flags.protected = protected.contains(&addon.transport_url);
+        }
+        info!("Loaded {} installed addons", addons.len());
+
+        Self {
+            path: path.to_path_buf(),
+            protected,
+            addons: Mutex::new(addons),
+        }
+    }
+
+    pub fn list(&self) -> Vec<InstalledAddon> {
+        self.lock().clone()
+    }
+
+    /// Built-in addons not yet in the collection.
+    pub fn missing_protected(&self) -> Vec<String> {
+        let addons = self.lock();
+        self.protected
+            .iter()
+            .filter(|url| !addons.iter().any(|addon| &addon.transport_url == *url))
+            .cloned()
+            .collect()
+    }
+
+    /// Adds an addon at the end, or updates the manifest of an installed one
+    /// in place. An addon with the same manifest id at another URL is
+    /// replaced, e.g. when it is configured again. `settings` of `None`
+    /// keeps the settings of an addon at the same URL.
+    pub fn install(
+        &self,
+        url: &str,
+        manifest: Manifest,
+        settings: Option<AddonSettings>,
+    ) -> Result<InstalledAddon> {
+        let url = transport_url(url)?;
+        let mut addons = self.lock();
+        let mut updated = addons.clone();
+        let position = updated
+            .iter()
+            .position(|addon| addon.transport_url == url)
+            .or_else(|| {
+                updated
+                    .iter()
+                    .position(|addon| addon.manifest.id == manifest.id && !addon.flags.protected)
+            });
+
+        let installed = match position {
+            Some(index) => {
+                let addon = &mut updated[index];
+                if settings.is_some() || addon.transport_url != url {
+                    addon.settings = settings;
+                }
+                addon.transport_url = url.clone();
+                addon.manifest = manifest;
+                addon.flags.protected = self.protected.contains(&url);
+                addon.clone()
+            }
+            None => {
+                let addon = InstalledAddon {
+                    flags: AddonFlags {
+                        enabled: true,
+                        protected: self.protected.contains(&url),
+                    },
+                    transport_url: url,
+                    manifest,
+                    previous_manifest: None,
+                    skipped_version: None,
+                    settings,
+                    installed_at: Utc::now(),
+                };
+                updated.push(addon.clone());
+                addon
+            }
+        };
+        self.replace(&mut addons, updated)?;
+        Ok(installed)
+    }
+
+    pub fn uninstall(&self, url: &str) -> Result<InstalledAddon> {
+        let url = transport_url(url)?;
+        let mut addons = self.lock();
+        let index = Self::index(&addons, &url)?;
+        if addons[index].flags.protected {
+            bail!(
+                "{} is a built-in addon and cannot be uninstalled",
+                addons[index].manifest.name
+            );
+        }
+        let mut updated = addons.clone();
+        let removed = updated.remove(index);
+        self.replace(&mut addons, updated)?;
+        Ok(removed)
+    }
+
+    /// Reorders the collection. `urls` must list every installed addon
+    /// exactly once.
+    pub fn reorder(&self, urls: &[String]) -> Result<Vec<InstalledAddon>> {
+        let urls = urls
+            .iter()
+            .map(|url| transport_url(url))
+            .collect::<Result<Vec<_>>>()?;
+        let mut addons = self.lock();
+        if urls.len() != addons.len() {
+            bail!("Expected {} addons, got {}", addons.len(), urls.len());
+        }
+
+        let mut remaining = addons.clone();
+        let mut ordered = Vec::with_capacity(urls.len());
+        for url in &urls {
+            let index = Self::index(&remaining, url)
+                .with_context(|| format!("{} is listed twice or not installed", url))?;
+            ordered.push(remaining.remove(index));
+        }
+        self.replace(&mut addons, ordered)?;
+        Ok(addons.clone())
+    }
+
+    /// Swaps in an updated manifest if the installed one is still
+    /// `current`, keeping `current` to roll back to. Returns `None` if the
+    /// addon changed in the meantime.
+    pub fn update_manifest(
+        &self,
+        url: &str,
+        current: &Manifest,
+        manifest: Manifest,
+    ) -> Result<Option<InstalledAddon>> {
+        let url = transport_url(url)?;
+        let mut addons = self.lock();
+        let index = Self::index(&addons, &url)?;
+        if !updates::same(&addons[index].manifest, current) {
+            return Ok(None);
+        }
+        let mut updated = addons.clone();
+        let addon = &mut updated[index];
+        addon.previous_manifest = Some(std::mem::replace(&mut addon.manifest, manifest));
+        addon.skipped_version = None;
+        let addon = addon.clone();
+        self.replace(&mut addons, updated)?;
+        Ok(Some(addon))
+    }
+
+    /// Restores the manifest the last update replaced, and skips the
+    /// replaced version in later update checks.
+    pub fn rollback(&self, url: &str) -> Result<InstalledAddon> {
+        let url = transport_url(url)?;
+        let mut addons = self.lock();
+        let index = Self::index(&addons, &url)?;
+        let mut updated = addons.clone();
+        let addon = &mut updated[index];
+        let previous = addon
+            .previous_manifest
+            .take()
+            .with_context(|| format!("{} has no earlier manifest", addon.manifest.name))?;
+        let replaced = std::mem::replace(&mut addon.manifest, previous);
+        addon.skipped_version = Some(replaced.version);
+        let addon = addon.clone();
+        self.replace(&mut addons, updated)?;
+        Ok(addon)
+    }
+
+    pub fn set_enabled(&self, url: &str, enabled: bool) -> Result<InstalledAddon> {
+        let url = transport_url(url)?;
+        let mut addons = self.lock();
+        let index = Self::index(&addons, &url)?;
+        let mut updated = addons.clone();
+        updated[index].flags.enabled = enabled;
+        let addon = updated[index].clone();
+        self.replace(&mut addons, updated)?;
+        Ok(addon)
+    }
+
+    fn index(addons: &[InstalledAddon], url: &str) -> Result<usize> {
+        addons
+            .iter()
+            .position(|addon| addon.transport_url == url)
+            .with_context(|| format!("Addon not installed: {}", url))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<InstalledAddon>> {
+        self.addons.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    /// Saves `updated` and only then swaps it in, so a failed save leaves
+    /// memory agreeing with disk.
+    fn replace(
+        &self,
+        addons: &mut Vec<InstalledAddon>,
+        updated: Vec<InstalledAddon>,
+    ) -> Result<()> {
+        self.save(&updated)?;
+        *addons = updated;
+        Ok(())
+    }
+
+    /// Writes through a temporary file so a crash never leaves a truncated
+    /// collection behind.
+    fn save(&self, addons: &[InstalledAddon]) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = CollectionFile {
+            addons: addons.to_vec(),
+        };
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(&file)?)?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Failed to save addon collection to {:?}", self.path))
+    }
+}
+
+/// The key addons are stored under: `<base url>/manifest.json`, or the URL
+/// as given for native, sandboxed and process addons.
+pub fn transport_url(url: &str) -> Result<String> {
+    if process::is_process(url) {
+        return Ok(url.trim().to_string());
+    }
+    if native::is_native(url) || sandbox::is_sandboxed(url) {
+        return Ok(url.trim().trim_end_matches('/').to_string());
+    }
+    Ok(format!("{}/manifest.json", base_url(url)?))
+}
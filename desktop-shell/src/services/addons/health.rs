@@ -0,0 +1,241 @@
+//! Per-addon request statistics and a circuit breaker that stops asking an
+//! addon for a while once it keeps failing.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::config::CircuitBreakerConfig;
+
+/// Recent requests kept per addon for percentiles and the error rate.
+const SAMPLES: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CircuitState {
+    /// Requests go through.
+    Closed,
+    /// The addon is skipped until the cooldown ends.
+    Open,
+    /// The cooldown ended; one trial request decides whether to close.
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Percentiles {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LastFailure {
+    pub at: DateTime<Utc>,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddonHealth {
+    pub transport_url: String,
+    pub name: String,
+    pub requests: u64,
+    pub failures: u64,
+    /// Share of the recent requests that failed.
+    pub error_rate: f64,
+    /// Latency of the recent successful requests.
+    pub latency_ms: Option<Percentiles>,
+    pub last_failure: Option<LastFailure>,
+    pub circuit: CircuitState,
+    /// Seconds until an open circuit lets a trial request through.
+    pub retry_in_secs: Option<u64>,
+}
+
+struct Sample {
+    latency: Duration,
+    ok: bool,
+}
+
+#[derive(Default)]
+enum Breaker {
+    #[default]
+    Closed,
+    Open {
+        until: Instant,
+    },
+    /// A trial request is in flight. Another is let through if it never
+    /// reports back within the cooldown, e.g. because it was dropped.
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+#[derive(Default)]
+struct Stats {
+    requests: u64,
+    failures: u64,
+    recent: VecDeque<Sample>,
+    consecutive_failures: u32,
+    last_failure: Option<LastFailure>,
+    breaker: Breaker,
+}
+
+pub struct HealthTracker {
+    config: CircuitBreakerConfig,
+    addons: Mutex<HashMap<String, Stats>>,
+}
+
+impl HealthTracker {
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        Self {
+            config: config.clone(),
+            addons: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether the addon may be asked now. Once an open circuit's cooldown
+    /// ends, lets exactly one trial request through.
+    pub fn allow(&self, transport_url: &str) -> bool {
+        let mut addons = self.lock();
+        let Some(stats) = addons.get_mut(transport_url) else {
+            return true;
+        };
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+        let trial = match stats.breaker {
+            Breaker::Closed => return true,
+            Breaker::Open { until } => Instant::now() >= until,
+            Breaker::HalfOpen { since } => since.elapsed() >= cooldown,
+        };
+        if trial {
+            stats.breaker = Breaker::HalfOpen {
+                since: Instant::now(),
+            };
+        }
+        trial
+    }
+
+    /// Why the addon would not be asked now, without using up the trial
+    /// request [`Self::allow`] lets through.
+    pub fn blocked(&self, transport_url: &str) -> Option<String> {
+        let addons = self.lock();
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+        match addons.get(transport_url)?.breaker {
+            Breaker::Closed => None,
+            Breaker::Open { until } => {
+                let remaining = until
+                    .checked_duration_since(Instant::now())
+                    .filter(|remaining| !remaining.is_zero())?;
+                Some(format!(
+                    "Circuit open, retry in {}s",
+                    remaining.as_secs_f64().ceil() as u64
+                ))
+            }
+            Breaker::HalfOpen { since } if since.elapsed() < cooldown => {
+                Some("Circuit half-open, waiting on a trial request".to_string())
+            }
+            Breaker::HalfOpen { .. } => None,
+        }
+    }
+
+    /// Records a finished request, opening the circuit after too many
+    /// failures in a row or a failed trial request.
+    pub fn record(&self, transport_url: &str, latency: Duration, error: Option<String>) {
+        let mut addons = self.lock();
+        let stats = addons.entry(transport_url.to_string()).or_default();
+        stats.requests += 1;
+        if stats.recent.len() == SAMPLES {
+            stats.recent.pop_front();
+        }
+        stats.recent.push_back(Sample {
+            latency,
+            ok: error.is_none(),
+        });
+
+        let Some(error) = error else {
+            if matches!(stats.breaker, Breaker::HalfOpen { .. }) {
+                info!("{} recovered", transport_url);
+            }
+            stats.consecutive_failures = 0;
+            stats.breaker = Breaker::Closed;
+            return;
+        };
+        stats.failures += 1;
+        stats.consecutive_failures += 1;
+        stats.last_failure = Some(LastFailure {
+            at: Utc::now(),
+            error,
+        });
+        let trial_failed = matches!(stats.breaker, Breaker::HalfOpen { .. });
+        let tripped = matches!(stats.breaker, Breaker::Closed)
+            && self.config.failure_threshold > 0
+            && stats.consecutive_failures >= self.config.failure_threshold;
+        if trial_failed || tripped {
+            let cooldown = Duration::from_secs(self.config.cooldown_secs);
+            warn!(
+                "Skipping {} for {:?} after {} failures in a row",
+                transport_url, cooldown, stats.consecutive_failures
+            );
+            stats.breaker = Breaker::Open {
+                until: Instant::now() + cooldown,
+            };
+        }
+    }
+
+    pub fn health(&self, transport_url: &str, name: &str) -> AddonHealth {
+        let addons = self.lock();
+        let stats = addons.get(transport_url);
+        let (circuit, retry_in_secs) = match stats.map(|stats| &stats.breaker) {
+            None | Some(Breaker::Closed) => (CircuitState::Closed, None),
+            Some(Breaker::HalfOpen { .. }) => (CircuitState::HalfOpen, None),
+            Some(Breaker::Open { until }) => match until.checked_duration_since(Instant::now()) {
+                Some(remaining) => (CircuitState::Open, Some(remaining.as_secs())),
+                None => (CircuitState::HalfOpen, None),
+            },
+        };
+
+        let recent = stats.map(|stats| &stats.recent);
+        let failed = recent.map_or(0, |recent| recent.iter().filter(|s| !s.ok).count());
+        let mut latencies: Vec<u64> = recent
+            .into_iter()
+            .flatten()
+            .filter(|sample| sample.ok)
+            .map(|sample| sample.latency.as_millis() as u64)
+            .collect();
+        latencies.sort_unstable();
+
+        AddonHealth {
+            transport_url: transport_url.to_string(),
+            name: name.to_string(),
+            requests: stats.map_or(0, |stats| stats.requests),
+            failures: stats.map_or(0, |stats| stats.failures),
+            error_rate: match recent.map_or(0, VecDeque::len) {
+                0 => 0.0,
+                total => failed as f64 / total as f64,
+            },
+            latency_ms: (!latencies.is_empty()).then(|| Percentiles {
+                p50: percentile(&latencies, 50),
+                p90: percentile(&latencies, 90),
+                p99: percentile(&latencies, 99),
+            }),
+            last_failure: stats.and_then(|stats| stats.last_failure.clone()),
+            circuit,
+            retry_in_secs,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Stats>> {
+        self.addons.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+/// Nearest-rank percentile of sorted, non-empty `values`.
+fn percentile(values: &[u64], p: usize) -> u64 {
+    let rank = (p * values.len()).div_ceil(100).max(1);
+    values[rank - 1]
+}
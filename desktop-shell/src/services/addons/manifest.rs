@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// An addon's self-description, served at `<transport url>/manifest.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub id: String,
+    pub version: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logo: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+    #[serde(default)]
+    pub types: Vec<String>,
+    #[serde(default)]
+    pub resources: Vec<ManifestResource>,
+    #[serde(default)]
+    pub catalogs: Vec<ManifestCatalog>,
+    /// Default id prefixes for resources that do not list their own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_prefixes: Option<Vec<String>>,
+    #[serde(default)]
+    pub behavior_hints: BehaviorHints,
+    /// Settings the user fills in before the addon can be used with them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub config: Vec<ConfigField>,
+    /// Fields this client does not interpret, kept for round-tripping.
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// A resource entry: either just its name, or a name with its own types
+/// and id prefixes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ManifestResource {
+    Short(String),
+    Full {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        types: Option<Vec<String>>,
+        #[serde(
+            rename = "idPrefixes",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        id_prefixes: Option<Vec<String>>,
+    },
+}
+
+impl ManifestResource {
+    pub fn name(&self) -> &str {
+        match self {
+            ManifestResource::Short(name) => name,
+            ManifestResource::Full { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestCatalog {
+    #[serde(rename = "type")]
+    pub media_type: String,
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub extra: Vec<ExtraProperty>,
+    /// Legacy form of `extra`: names of supported and required properties.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_supported: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_required: Vec<String>,
+}
+
+/// An `extra` argument a catalog accepts, such as `search` or `genre`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtraProperty {
+    pub name: String,
+    #[serde(default)]
+    pub is_required: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options_limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BehaviorHints {
+    #[serde(default)]
+    pub adult: bool,
+    #[serde(default)]
+    pub p2p: bool,
+    #[serde(default)]
+    pub configurable: bool,
+    #[serde(default)]
+    pub configuration_required: bool,
+}
+
+/// One setting of a configurable addon, rendered as a form field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigField {
+    pub key: String,
+    #[serde(rename = "type")]
+    pub field_type: ConfigFieldType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+    /// Choices of a `select` field.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigFieldType {
+    Text,
+    Number,
+    Password,
+    Checkbox,
+    Select,
+}
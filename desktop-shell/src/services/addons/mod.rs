@@ -1,11 +1,635 @@
-use log::warn;
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-pub struct AddonManager;
+use anyhow::{bail, Result};
+use futures::{
+    future::join_all,
+    stream::{FuturesUnordered, StreamExt},
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use super::events::EventBus;
+use crate::config::AddonsConfig;
+
+pub mod cache;
+pub mod client;
+pub mod collection;
+pub mod health;
+pub mod manifest;
+pub mod native;
+pub mod process;
+pub mod protocol;
+pub mod routing;
+pub mod sandbox;
+pub mod server;
+pub mod settings;
+pub mod sharing;
+pub mod store;
+#[cfg(test)]
+mod tests;
+pub mod updates;
+pub mod validation;
+
+use client::AddonClient;
+use collection::{AddonCollection, InstalledAddon};
+use health::{AddonHealth, HealthTracker};
+use manifest::Manifest;
+use native::{Addon, NativeAddons};
+use process::ProcessRunner;
+use protocol::ResourceRequest;
+use routing::RouteDecision;
+use sandbox::SandboxRuntime;
+use settings::{AddonSettings, Configured, SettingsForm};
+use sharing::{CollectionExport, ExportedAddon, ImportDiff};
+use store::{AddonStore, StoreListing, StoreQuery};
+use updates::ManifestChanges;
+use validation::ValidationReport;
+
+pub const ADDON_RESULT_EVENT: &str = "addonResult";
+pub const ADDON_DONE_EVENT: &str = "addonResultsDone";
+pub const ADDON_UPDATED_EVENT: &str = "addonUpdated";
+
+/// One addon's answer to a fanned-out request.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AddonResult<'a> {
+    request_id: &'a str,
+    transport_url: &'a str,
+    name: &'a str,
+    response: Value,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddonFailure {
+    pub transport_url: String,
+    pub name: String,
+    pub error: String,
+}
+
+/// How a fan-out went, sent once every addon has answered or timed out.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FanOutSummary {
+    pub request_id: String,
+    /// Transport URLs of the addons that answered.
+    pub succeeded: Vec<String>,
+    pub failed: Vec<AddonFailure>,
+    pub elapsed_ms: u64,
+}
+
+/// The outcome of checking every installed addon for a new manifest.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateReport {
+    pub checked: usize,
+    pub updated: Vec<ManifestChanges>,
+    /// Addons that could not be checked, or whose new manifest is invalid
+    /// and was not applied.
+    pub failed: Vec<AddonFailure>,
+}
+
+/// The collection as a document and as a share link.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedCollection {
+    pub document: CollectionExport,
+    pub share_url: String,
+}
+
+/// What importing a collection changes and, once applied, how it went.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportOutcome {
+    pub diff: ImportDiff,
+    pub applied: bool,
+    pub installed: Vec<InstalledAddon>,
+    /// Addons left out until their settings are entered, e.g. because the
+    /// export was redacted.
+    pub needs_configuration: Vec<ExportedAddon>,
+    pub failed: Vec<AddonFailure>,
+}
+
+pub struct AddonManager {
+    client: AddonClient,
+    native: NativeAddons,
+    collection: AddonCollection,
+    sandbox: SandboxRuntime,
+    processes: ProcessRunner,
+    store: AddonStore,
+    health: HealthTracker,
+    events: EventBus,
+    /// How long each addon gets to answer a fanned-out request.
+    timeout: Duration,
+    update_interval: Duration,
+}
 
-#[allow(dead_code)]
 impl AddonManager {
-    pub fn new() -> Self {
-        warn!("Addon system not implemented");
-        Self
+    pub fn new(
+        config: &AddonsConfig,
+        collection_file: &Path,
+        sandbox_dir: &Path,
+        process_dir: &Path,
+        native: Vec<Arc<dyn Addon>>,
+        events: EventBus,
+    ) -> Result<Self> {
+        let mut sandbox = SandboxRuntime::new(&config.sandbox)?;
+        sandbox.load_dir(sandbox_dir);
+        // Native addons ship with the shell, so they are always installed.
+        let native = NativeAddons::new(native);
+        let mut builtin = config.builtin.clone();
+        builtin.extend(native.transport_urls());
+        Ok(Self {
+            client: AddonClient::new(
+                Duration::from_millis(config.request_timeout_ms),
+                config.cache_max_entries,
+            )?,
+            native,
+            collection: AddonCollection::open(collection_file, &builtin),
+            sandbox,
+            processes: ProcessRunner::new(
+                &config.process,
+                process_dir,
+                Duration::from_millis(config.request_timeout_ms),
+            ),
+            store: AddonStore::new(
+                &config.repositories,
+                Duration::from_millis(config.request_timeout_ms),
+            )?,
+            health: HealthTracker::new(&config.circuit_breaker),
+            events,
+            timeout: Duration::from_millis(config.fan_out_timeout_ms),
+            update_interval: Duration::from_secs(config.update_interval_mins * 60),
+        })
+    }
+
+    pub fn collection(&self) -> &AddonCollection {
+        &self.collection
+    }
+
+    /// Fetches the manifest and adds the addon, or refreshes it if it is
+    /// already installed.
+    pub async fn install(&self, transport_url: &str) -> Result<InstalledAddon> {
+        let manifest = self.manifest(transport_url).await?;
+        if manifest.behavior_hints.configuration_required && self.settings(transport_url).is_none()
+        {
+            bail!(
+                "{} needs to be configured before it can be installed",
+                manifest.name
+            );
+        }
+        self.collection.install(transport_url, manifest, None)
+    }
+
+    /// The settings form for an addon, installed or not. For an addon
+    /// installed with settings, the form is for its unconfigured URL and
+    /// holds the current values.
+    pub async fn settings_form(&self, transport_url: &str) -> Result<SettingsForm> {
+        let (base, values) = match self.settings(transport_url) {
+            Some(settings) => (settings.transport_url, Some(settings.values)),
+            None => (transport_url.to_string(), None),
+        };
+        let manifest = self.manifest(&base).await?;
+        Ok(settings::form(&base, &manifest, values))
+    }
+
+    /// Checks submitted settings and installs the addon configured with them,
+    /// replacing any earlier configuration of it.
+    pub async fn configure(
+        &self,
+        transport_url: &str,
+        values: &Map<String, Value>,
+    ) -> Result<Configured> {
+        let base = self.settings(transport_url).map_or_else(
+            || transport_url.to_string(),
+            |settings| settings.transport_url,
+        );
+        let manifest = self.manifest(&base).await?;
+        if manifest.config.is_empty() {
+            bail!("{} has no settings", manifest.name);
+        }
+        let values = match settings::check(&manifest.config, values) {
+            Ok(values) => values,
+            Err(issues) => {
+                return Ok(Configured {
+                    addon: None,
+                    issues,
+                })
+            }
+        };
+
+        let url = settings::configured_url(&base, &values)?;
+        let manifest = self.manifest(&url).await?;
+        let settings = AddonSettings {
+            transport_url: collection::transport_url(&base)?,
+            values,
+        };
+        let addon = self.collection.install(&url, manifest, Some(settings))?;
+        Ok(Configured {
+            addon: Some(addon),
+            issues: Vec::new(),
+        })
+    }
+
+    /// The settings of the addon installed at `transport_url`, if any.
+    fn settings(&self, transport_url: &str) -> Option<AddonSettings> {
+        let url = collection::transport_url(transport_url).ok()?;
+        self.collection
+            .list()
+            .into_iter()
+            .find(|addon| addon.transport_url == url)?
+            .settings
+    }
+
+    /// Removes the addon, stopping its process if it runs as one.
+    pub fn uninstall(&self, transport_url: &str) -> Result<InstalledAddon> {
+        let addon = self.collection.uninstall(transport_url)?;
+        if process::is_process(transport_url) {
+            self.processes.stop(&addon.transport_url);
+        }
+        Ok(addon)
+    }
+
+    /// Fetches and validates the manifest of an addon over any transport.
+    pub async fn manifest(&self, transport_url: &str) -> Result<Manifest> {
+        if native::is_native(transport_url) {
+            let raw = serde_json::to_value(self.native.get(transport_url)?.manifest())?;
+            validation::parse_manifest(transport_url, raw)
+        } else if sandbox::is_sandboxed(transport_url) {
+            let raw = self.sandbox.get(transport_url)?.manifest().await?;
+            validation::parse_manifest(transport_url, raw)
+        } else if process::is_process(transport_url) {
+            let raw = self.processes.get(transport_url)?.manifest().await?;
+            validation::parse_manifest(transport_url, raw)
+        } else {
+            self.client.manifest(transport_url).await
+        }
+    }
+
+    /// Fetches a manifest and reports everything wrong with it.
+    pub async fn validate(&self, transport_url: &str) -> Result<ValidationReport> {
+        Ok(validation::validate(
+            &self.raw_manifest(transport_url).await?,
+        ))
+    }
+
+    /// The manifest JSON as the addon serves it, before validation.
+    async fn raw_manifest(&self, transport_url: &str) -> Result<Value> {
+        Ok(if native::is_native(transport_url) {
+            serde_json::to_value(self.native.get(transport_url)?.manifest())?
+        } else if sandbox::is_sandboxed(transport_url) {
+            self.sandbox.get(transport_url)?.manifest().await?
+        } else if process::is_process(transport_url) {
+            self.processes.get(transport_url)?.manifest().await?
+        } else {
+            self.client.raw_manifest(transport_url).await?
+        })
+    }
+
+    /// Calls a resource on an addon over any transport, checking the
+    /// response against the protocol's shapes. Addons whose circuit is open
+    /// are not asked.
+    pub async fn call(&self, transport_url: &str, request: &ResourceRequest) -> Result<Value> {
+        let key = health_key(transport_url);
+        if !self.health.allow(&key) {
+            bail!("Skipped {}: it keeps failing", transport_url);
+        }
+        let started = Instant::now();
+        let result = self.dispatch(transport_url, request).await;
+        let error = result.as_ref().err().map(|e| format!("{:#}", e));
+        self.health.record(&key, started.elapsed(), error);
+        result
+    }
+
+    async fn dispatch(&self, transport_url: &str, request: &ResourceRequest) -> Result<Value> {
+        if native::is_native(transport_url) {
+            native::call(self.native.get(transport_url)?.as_ref(), request).await
+        } else if sandbox::is_sandboxed(transport_url) {
+            self.sandbox.get(transport_url)?.request(request).await
+        } else if process::is_process(transport_url) {
+            self.processes.get(transport_url)?.request(request).await
+        } else {
+            self.client.checked(transport_url, request).await
+        }
+    }
+
+    /// Request statistics and circuit state of every installed addon, in
+    /// collection order.
+    pub fn health(&self) -> Vec<AddonHealth> {
+        self.collection
+            .list()
+            .iter()
+            .map(|addon| {
+                self.health
+                    .health(&addon.transport_url, &addon.manifest.name)
+            })
+            .collect()
     }
-}
\ No newline at end of file
+
+    /// Lists the store's addons matching `query`, marking installed ones
+    /// and available updates.
+    pub async fn browse_store(&self, query: &StoreQuery) -> StoreListing {
+        self.store.browse(query, &self.collection.list()).await
+    }
+
+    /// Fetches the store's repository indexes again.
+    pub async fn refresh_store(&self, query: &StoreQuery) -> StoreListing {
+        self.store.refresh().await;
+        self.browse_store(query).await
+    }
+
+    /// Re-fetches every installed addon's manifest and swaps in those that
+    /// changed, announcing each with an [`ADDON_UPDATED_EVENT`]. A manifest
+    /// that fails validation is not applied, so the addon keeps working
+    /// with the one it has.
+    pub async fn check_updates(&self) -> UpdateReport {
+        let addons = self.collection.list();
+        let results = join_all(addons.iter().map(|addon| self.update(addon))).await;
+
+        let mut report = UpdateReport {
+            checked: addons.len(),
+            ..Default::default()
+        };
+        for (addon, result) in addons.into_iter().zip(results) {
+            match result {
+                Ok(Some(changes)) => {
+                    log::info!(
+                        "Updated addon {} from {} to {}",
+                        changes.name,
+                        changes.previous_version,
+                        changes.version
+                    );
+                    self.events.emit(ADDON_UPDATED_EVENT, &changes);
+                    report.updated.push(changes);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    log::warn!("Update check of {} failed: {:#}", addon.transport_url, e);
+                    report.failed.push(AddonFailure {
+                        transport_url: addon.transport_url,
+                        name: addon.manifest.name,
+                        error: format!("{:#}", e),
+                    });
+                }
+            }
+        }
+        report
+    }
+
+    async fn update(&self, addon: &InstalledAddon) -> Result<Option<ManifestChanges>> {
+        let raw = self.raw_manifest(&addon.transport_url).await?;
+        let manifest = validation::parse_manifest(&addon.transport_url, raw)?;
+        if addon.skipped_version.as_ref() == Some(&manifest.version) {
+            return Ok(None);
+        }
+        let Some(changes) = updates::diff(&addon.transport_url, &addon.manifest, &manifest) else {
+            return Ok(None);
+        };
+        let updated =
+            self.collection
+                .update_manifest(&addon.transport_url, &addon.manifest, manifest)?;
+        Ok(updated.map(|_| changes))
+    }
+
+    /// Goes back to the manifest the last update replaced.
+    pub fn rollback(&self, transport_url: &str) -> Result<InstalledAddon> {
+        self.collection.rollback(transport_url)
+    }
+
+    pub fn export_collection(&self, redact: bool) -> SharedCollection {
+        let addons = self.collection.list();
+        SharedCollection {
+            document: sharing::export(&addons, redact),
+            share_url: sharing::share_url(&addons),
+        }
+    }
+
+    /// Compares an exported collection, as a document or share link, with
+    /// the installed addons and, with `apply`, installs what it adds or
+    /// changes. Installed addons it does not list are kept.
+    pub async fn import_collection(&self, source: &Value, apply: bool) -> Result<ImportOutcome> {
+        let import = sharing::parse(source)?;
+        let diff = sharing::diff(&import, &self.collection.list());
+        let mut outcome = ImportOutcome {
+            diff,
+            applied: apply,
+            installed: Vec::new(),
+            needs_configuration: Vec::new(),
+            failed: Vec::new(),
+        };
+        if !apply {
+            return Ok(outcome);
+        }
+
+        for entry in outcome.diff.added.iter().chain(&outcome.diff.changed) {
+            match self.import(entry).await {
+                Ok(Some(addon)) => outcome.installed.push(addon),
+                Ok(None) => outcome.needs_configuration.push(entry.clone()),
+                Err(e) => {
+                    log::warn!("Failed to import {}: {:#}", entry.transport_url, e);
+                    outcome.failed.push(AddonFailure {
+                        transport_url: entry.transport_url.clone(),
+                        name: entry.name.clone(),
+                        error: format!("{:#}", e),
+                    });
+                }
+            }
+        }
+        for entry in &outcome.diff.toggled {
+            let installed = self.collection.list();
+            let Some(addon) = sharing::installed_match(&installed, entry) else {
+                continue;
+            };
+            match self
+                .collection
+                .set_enabled(&addon.transport_url, entry.enabled)
+            {
+                Ok(addon) => outcome.installed.push(addon),
+                Err(e) => outcome.failed.push(AddonFailure {
+                    transport_url: addon.transport_url.clone(),
+                    name: addon.manifest.name.clone(),
+                    error: format!("{:#}", e),
+                }),
+            }
+        }
+        log::info!(
+            "Imported {} addons, {} need configuration, {} failed",
+            outcome.installed.len(),
+            outcome.needs_configuration.len(),
+            outcome.failed.len()
+        );
+        Ok(outcome)
+    }
+
+    /// Installs one imported addon. `None` if it needs settings that the
+    /// import does not carry.
+    async fn import(&self, entry: &ExportedAddon) -> Result<Option<InstalledAddon>> {
+        let addon = match &entry.settings {
+            Some(settings) => {
+                let configured = self
+                    .configure(&settings.transport_url, &settings.values)
+                    .await?;
+                match configured.addon {
+                    Some(addon) => addon,
+                    None => return Ok(None),
+                }
+            }
+            None => {
+                let manifest = self.manifest(&entry.transport_url).await?;
+                if manifest.behavior_hints.configuration_required
+                    && self.settings(&entry.transport_url).is_none()
+                {
+                    return Ok(None);
+                }
+                self.collection
+                    .install(&entry.transport_url, manifest, None)?
+            }
+        };
+        if addon.flags.enabled == entry.enabled {
+            return Ok(Some(addon));
+        }
+        self.collection
+            .set_enabled(&addon.transport_url, entry.enabled)
+            .map(Some)
+    }
+
+    /// Periodically checks installed addons for updates, starting a full
+    /// period after launch.
+    pub fn spawn_update_checks(self: &Arc<Self>, runtime: &tokio::runtime::Runtime) {
+        if self.update_interval.is_zero() {
+            return;
+        }
+        let manager = Arc::downgrade(self);
+        let period = self.update_interval;
+        runtime.spawn(async move {
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                let Some(manager) = manager.upgrade() else {
+                    return;
+                };
+                manager.check_updates().await;
+            }
+        });
+    }
+
+    /// Installs built-in addons missing from the collection, e.g. on first
+    /// run.
+    pub async fn install_builtin(&self) {
+        for url in self.collection.missing_protected() {
+            match self.install(&url).await {
+                Ok(addon) => log::info!("Installed built-in addon {}", addon.manifest.name),
+                Err(e) => log::error!("Failed to install built-in addon {}: {:#}", url, e),
+            }
+        }
+    }
+
+    /// Explains for every installed addon, in collection order, whether it
+    /// would be asked for `request`.
+    pub fn who_handles(&self, request: &ResourceRequest) -> Vec<RouteDecision> {
+        self.collection
+            .list()
+            .iter()
+            .map(|addon| self.decide(addon, request))
+            .collect()
+    }
+
+    /// The enabled addons that declare `request`'s resource, type and id and
+    /// are not skipped for failing, in collection order.
+    pub fn route(&self, request: &ResourceRequest) -> Vec<InstalledAddon> {
+        self.collection
+            .list()
+            .into_iter()
+            .filter(|addon| self.decide(addon, request).selected)
+            .collect()
+    }
+
+    /// Whether an addon that would be asked for `request` lists its id's
+    /// prefix, e.g. `kitsu:`, rather than taking any id.
+    pub fn claims_id(&self, request: &ResourceRequest) -> bool {
+        self.collection.list().iter().any(|addon| {
+            self.decide(addon, request).selected
+                && routing::declares_prefix(&addon.manifest, request)
+        })
+    }
+
+    /// Routes by the manifest, leaving out addons whose circuit is open.
+    fn decide(&self, addon: &InstalledAddon, request: &ResourceRequest) -> RouteDecision {
+        let mut decision = routing::decide(addon, request);
+        if decision.selected {
+            if let Some(reason) = self.health.blocked(&health_key(&addon.transport_url)) {
+                decision.selected = false;
+                decision.reason = reason;
+            }
+        }
+        decision
+    }
+
+    /// Asks every addon that handles `request` at once. Each answer is
+    /// pushed as an [`ADDON_RESULT_EVENT`] as soon as it arrives, tagged with
+    /// `request_id`; a slow addon is given up on after the request timeout.
+    /// Ends with an [`ADDON_DONE_EVENT`] carrying the returned summary.
+    pub async fn fan_out(&self, request_id: &str, request: &ResourceRequest) -> FanOutSummary {
+        let started = Instant::now();
+        let mut pending: FuturesUnordered<_> = self
+            .route(request)
+            .into_iter()
+            .map(|addon| async move {
+                let result =
+                    tokio::time::timeout(self.timeout, self.call(&addon.transport_url, request))
+                        .await;
+                (addon, result)
+            })
+            .collect();
+
+        let mut summary = FanOutSummary {
+            request_id: request_id.to_string(),
+            ..Default::default()
+        };
+        while let Some((addon, result)) = pending.next().await {
+            let (transport_url, name) = (addon.transport_url, addon.manifest.name);
+            let error = match result {
+                Ok(Ok(response)) => {
+                    self.events.emit(
+                        ADDON_RESULT_EVENT,
+                        AddonResult {
+                            request_id,
+                            transport_url: &transport_url,
+                            name: &name,
+                            response,
+                        },
+                    );
+                    summary.succeeded.push(transport_url);
+                    continue;
+                }
+                Ok(Err(e)) => format!("{:#}", e),
+                Err(_) => {
+                    let error = format!("Timed out after {} ms", self.timeout.as_millis());
+                    self.health
+                        .record(&transport_url, self.timeout, Some(error.clone()));
+                    error
+                }
+            };
+            log::warn!("{} failed: {}", name, error);
+            summary.failed.push(AddonFailure {
+                transport_url,
+                name,
+                error,
+            });
+        }
+
+        summary.elapsed_ms = started.elapsed().as_millis() as u64;
+        self.events.emit(ADDON_DONE_EVENT, &summary);
+        summary
+    }
+}
+
+/// Health is tracked under the installed form of the transport URL.
+fn health_key(transport_url: &str) -> String {
+    collection::transport_url(transport_url).unwrap_or_else(|_| transport_url.to_string())
+}
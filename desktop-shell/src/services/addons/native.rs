@@ -0,0 +1,103 @@
+//! Addons compiled into the shell. They are called directly, without a
+//! server or sandbox, under `native://<manifest id>`.
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+
+use super::manifest::Manifest;
+use super::protocol::{
+    CatalogResponse, MetaResponse, ResourceRequest, StreamResponse, SubtitlesResponse,
+};
+
+pub const SCHEME: &str = "native://";
+
+/// A built-in addon. Only the resources the manifest declares are asked
+/// for; the rest keep their default, which fails.
+#[async_trait]
+pub trait Addon: Send + Sync {
+    fn manifest(&self) -> Manifest;
+
+    async fn catalog(&self, request: &ResourceRequest) -> Result<CatalogResponse> {
+        bail!(
+            "{} has no catalog for {:?}",
+            self.manifest().name,
+            request.id
+        )
+    }
+
+    async fn meta(&self, request: &ResourceRequest) -> Result<MetaResponse> {
+        bail!("{} has no meta for {:?}", self.manifest().name, request.id)
+    }
+
+    async fn stream(&self, request: &ResourceRequest) -> Result<StreamResponse> {
+        bail!(
+            "{} has no streams for {:?}",
+            self.manifest().name,
+            request.id
+        )
+    }
+
+    async fn subtitles(&self, request: &ResourceRequest) -> Result<SubtitlesResponse> {
+        bail!(
+            "{} has no subtitles for {:?}",
+            self.manifest().name,
+            request.id
+        )
+    }
+}
+
+/// The native addons this build ships, by transport URL.
+#[derive(Default)]
+pub struct NativeAddons {
+    addons: HashMap<String, Arc<dyn Addon>>,
+}
+
+impl NativeAddons {
+    pub fn new(addons: Vec<Arc<dyn Addon>>) -> Self {
+        Self {
+            addons: addons
+                .into_iter()
+                .map(|addon| (transport_url(addon.as_ref()), addon))
+                .collect(),
+        }
+    }
+
+    pub fn transport_urls(&self) -> Vec<String> {
+        self.addons.keys().cloned().collect()
+    }
+
+    pub fn list(&self) -> Vec<Arc<dyn Addon>> {
+        self.addons.values().cloned().collect()
+    }
+
+    /// The addon a `native://` transport URL refers to.
+    pub fn get(&self, transport_url: &str) -> Result<Arc<dyn Addon>> {
+        self.addons
+            .get(transport_url.trim().trim_end_matches('/'))
+            .cloned()
+            .with_context(|| format!("No native addon at {}", transport_url))
+    }
+}
+
+pub fn transport_url(addon: &dyn Addon) -> String {
+    format!("{}{}", SCHEME, addon.manifest().id)
+}
+
+pub fn is_native(transport_url: &str) -> bool {
+    transport_url.trim().starts_with(SCHEME)
+}
+
+/// Calls the method for `request`'s resource, returning the response as an
+/// HTTP addon would send it.
+pub async fn call(addon: &dyn Addon, request: &ResourceRequest) -> Result<Value> {
+    Ok(match request.resource.as_str() {
+        "catalog" => serde_json::to_value(addon.catalog(request).await?)?,
+        "meta" => serde_json::to_value(addon.meta(request).await?)?,
+        "stream" => serde_json::to_value(addon.stream(request).await?)?,
+        "subtitles" => serde_json::to_value(addon.subtitles(request).await?)?,
+        other => bail!("Native addons do not serve {:?}", other),
+    })
+}
@@ -0,0 +1,364 @@
+//! Runs local addons as child processes.
+//!
+//! A process addon is addressed as `process://<path to executable>`, e.g.
+//! `process:///opt/addons/local-files.py`. Only executables inside the
+//! process addons directory are run. The process reads one JSON request
+//! per line on stdin and answers each with one line on stdout, in any order:
+//!
+//! - `{"id": 1, "method": "manifest"}`
+//! - `{"id": 2, "method": "resource", "request": {"resource", "type", "id", "extra"}}`
+//! - answered with `{"id": 2, "result": ...}` or `{"id": 2, "error": "..."}`
+//!
+//! Lines on stderr are logged. The process is started on first use and
+//! restarted with exponential backoff when it exits.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{Child, Command},
+    sync::{mpsc, oneshot, watch, Notify},
+};
+
+use super::protocol::{check_response, ResourceRequest};
+use crate::config::ProcessConfig;
+
+pub const SCHEME: &str = "process://";
+
+/// A process that stayed up this long is considered healthy again, and its
+/// next crash restarts it after the shortest delay.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+/// How long a request waits for a (re)starting process.
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<Value, String>>>>>;
+
+/// The pipes of the running process.
+#[derive(Clone)]
+struct Connection {
+    stdin: mpsc::UnboundedSender<String>,
+    pending: Pending,
+}
+
+#[derive(Deserialize)]
+struct Reply {
+    id: u64,
+    #[serde(default)]
+    result: Value,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+pub struct ProcessAddon {
+    path: PathBuf,
+    config: ProcessConfig,
+    timeout: Duration,
+    connection: watch::Sender<Option<Connection>>,
+    next_id: AtomicU64,
+    stopped: AtomicBool,
+    stop: Notify,
+}
+
+impl ProcessAddon {
+    /// The manifest JSON the process reports.
+    pub async fn manifest(&self) -> Result<Value> {
+        self.send("manifest", None).await
+    }
+
+    pub async fn request(&self, request: &ResourceRequest) -> Result<Value> {
+        let response = self.send("resource", Some(request)).await?;
+        check_response(&request.resource, response)
+            .with_context(|| format!("Invalid response from {:?}", self.path))
+    }
+
+    async fn send(&self, method: &str, request: Option<&ResourceRequest>) -> Result<Value> {
+        let mut connection = self.connection.subscribe();
+        let connection = tokio::time::timeout(
+            START_TIMEOUT,
+            connection.wait_for(|connection| connection.is_some()),
+        )
+        .await
+        .with_context(|| format!("{:?} is not running", self.path))??
+        .clone()
+        .context("Addon process stopped")?;
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (reply, answer) = oneshot::channel();
+        lock(&connection.pending).insert(id, reply);
+        let line = json!({ "id": id, "method": method, "request": request }).to_string();
+        if connection.stdin.send(line).is_err() {
+            lock(&connection.pending).remove(&id);
+            bail!("{:?} exited", self.path);
+        }
+
+        match tokio::time::timeout(self.timeout, answer).await {
+            Ok(Ok(Ok(value))) => Ok(value),
+            Ok(Ok(Err(e))) => bail!("{:?} answered with an error: {}", self.path, e),
+            Ok(Err(_)) => bail!("{:?} exited before answering", self.path),
+            Err(_) => {
+                lock(&connection.pending).remove(&id);
+                bail!("{:?} did not answer in time", self.path)
+            }
+        }
+    }
+
+    /// Keeps the process running until stopped, restarting it with
+    /// exponential backoff whenever it exits.
+    async fn supervise(self: Arc<Self>) {
+        let mut crashes = 0;
+        while !self.stopped.load(Ordering::SeqCst) {
+            let started = Instant::now();
+            match self.run().await {
+                Ok(Some(status)) => warn!("Addon process {:?} exited: {}", self.path, status),
+                Ok(None) => break,
+                Err(e) => error!("Failed to run addon process {:?}: {:#}", self.path, e),
+            }
+            if self.stopped.load(Ordering::SeqCst) {
+                break;
+            }
+
+            if started.elapsed() > STABLE_AFTER {
+                crashes = 0;
+            }
+            crashes += 1;
+            let delay = backoff(crashes, Duration::from_secs(self.config.max_backoff_secs));
+            info!("Restarting {:?} in {:?}", self.path, delay);
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = self.stop.notified() => break,
+            }
+        }
+        debug!("Stopped supervising {:?}", self.path);
+    }
+
+    /// Runs the process once. Returns its exit status, or `None` if it was
+    /// stopped.
+    async fn run(&self) -> Result<Option<std::process::ExitStatus>> {
+        let mut command = Command::new(&self.path);
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(dir) = self.path.parent() {
+            command.current_dir(dir);
+        }
+        #[cfg(target_os = "linux")]
+        apply_limits(&mut command, &self.config);
+        let mut child = command.spawn()?;
+        info!(
+            "Started addon process {:?} (pid {:?})",
+            self.path,
+            child.id()
+        );
+
+        let pending = Pending::default();
+        let (stdin, lines) = mpsc::unbounded_channel();
+        let pipes = tokio::spawn(pump(&mut child, lines, pending.clone(), self.path.clone())?);
+        self.connection.send_replace(Some(Connection {
+            stdin,
+            pending: pending.clone(),
+        }));
+
+        let status = tokio::select! {
+            status = child.wait() => Some(status?),
+            _ = self.stop.notified() => {
+                if let Err(e) = child.kill().await {
+                    warn!("Failed to kill addon process {:?}: {}", self.path, e);
+                }
+                None
+            }
+        };
+
+        self.connection.send_replace(None);
+        pipes.abort();
+        for (_, reply) in lock(&pending).drain() {
+            let _ = reply.send(Err("Addon process exited".to_string()));
+        }
+        Ok(status)
+    }
+}
+
+/// Wires the child's pipes: queued lines go to stdin, replies on stdout are
+/// matched to pending requests and stderr is logged.
+fn pump(
+    child: &mut Child,
+    mut lines: mpsc::UnboundedReceiver<String>,
+    pending: Pending,
+    path: PathBuf,
+) -> Result<impl std::future::Future<Output = ()>> {
+    let mut stdin = child.stdin.take().context("No stdin")?;
+    let stdout = child.stdout.take().context("No stdout")?;
+    let stderr = child.stderr.take().context("No stderr")?;
+
+    Ok(async move {
+        let write = async {
+            while let Some(line) = lines.recv().await {
+                let written = async {
+                    stdin.write_all(line.as_bytes()).await?;
+                    stdin.write_all(b"\n").await?;
+                    stdin.flush().await
+                };
+                if written.await.is_err() {
+                    break;
+                }
+            }
+        };
+        let read = async {
+            let mut replies = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = replies.next_line().await {
+                match serde_json::from_str::<Reply>(&line) {
+                    Ok(reply) => {
+                        if let Some(sender) = lock(&pending).remove(&reply.id) {
+                            let _ = sender.send(match reply.error {
+                                Some(e) => Err(e),
+                                None => Ok(reply.result),
+                            });
+                        }
+                    }
+                    Err(e) => warn!("Unreadable line from {:?}: {}", path, e),
+                }
+            }
+        };
+        let log = async {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                info!("[{:?}] {}", path.file_name().unwrap_or_default(), line);
+            }
+        };
+        tokio::join!(write, read, log);
+    })
+}
+
+/// Starts, finds and stops process addons.
+pub struct ProcessRunner {
+    config: ProcessConfig,
+    /// Where the executables must live.
+    dir: PathBuf,
+    timeout: Duration,
+    addons: Mutex<HashMap<String, Arc<ProcessAddon>>>,
+}
+
+impl ProcessRunner {
+    pub fn new(config: &ProcessConfig, dir: &Path, timeout: Duration) -> Self {
+        Self {
+            config: config.clone(),
+            dir: dir.to_path_buf(),
+            timeout,
+            addons: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The addon a `process://` transport URL refers to, starting it if it
+    /// is not running.
+    pub fn get(&self, transport_url: &str) -> Result<Arc<ProcessAddon>> {
+        let path = transport_url
+            .trim()
+            .strip_prefix(SCHEME)
+            .map(PathBuf::from)
+            .with_context(|| format!("Not a process addon URL: {}", transport_url))?;
+        if !path.is_absolute() {
+            bail!("Process addon path must be absolute: {:?}", path);
+        }
+        let path = self.executable(&path)?;
+
+        let mut addons = lock(&self.addons);
+        if let Some(addon) = addons.get(transport_url.trim()) {
+            return Ok(addon.clone());
+        }
+        let addon = Arc::new(ProcessAddon {
+            path,
+            config: self.config.clone(),
+            timeout: self.timeout,
+            connection: watch::Sender::new(None),
+            next_id: AtomicU64::new(1),
+            stopped: AtomicBool::new(false),
+            stop: Notify::new(),
+        });
+        tokio::spawn(addon.clone().supervise());
+        addons.insert(transport_url.trim().to_string(), addon.clone());
+        Ok(addon)
+    }
+
+    /// The executable at `path`, if it lies inside the process addons
+    /// directory. Links are resolved first so they cannot lead out of it.
+    fn executable(&self, path: &Path) -> Result<PathBuf> {
+        let dir = self
+            .dir
+            .canonicalize()
+            .with_context(|| format!("No process addons directory at {:?}", self.dir))?;
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("No addon executable at {:?}", path))?;
+        if !path.starts_with(&dir) {
+            bail!("Process addons must be in {:?}, not at {:?}", dir, path);
+        }
+        if !path.is_file() {
+            bail!("No addon executable at {:?}", path);
+        }
+        Ok(path)
+    }
+
+    /// Kills the process, if running, and forgets it.
+    pub fn stop(&self, transport_url: &str) {
+        if let Some(addon) = lock(&self.addons).remove(transport_url.trim()) {
+            addon.stopped.store(true, Ordering::SeqCst);
+            addon.stop.notify_one();
+        }
+    }
+}
+
+pub fn is_process(transport_url: &str) -> bool {
+    transport_url.trim().starts_with(SCHEME)
+}
+
+/// 1s, 2s, 4s, ... up to `max`.
+fn backoff(crashes: u32, max: Duration) -> Duration {
+    Duration::from_secs(1u64 << crashes.saturating_sub(1).min(16)).min(max)
+}
+
+/// Caps memory, CPU time and open files in the child before it execs.
+#[cfg(target_os = "linux")]
+fn apply_limits(command: &mut Command, config: &ProcessConfig) {
+    let limits = [
+        (libc::RLIMIT_AS, config.memory_limit_mb * 1024 * 1024),
+        (libc::RLIMIT_CPU, config.cpu_time_secs),
+        (libc::RLIMIT_NOFILE, config.open_files),
+    ];
+    // SAFETY: only calls setrlimit, which is async-signal-safe, between fork
+    // and exec.
+    unsafe {
+        command.pre_exec(move || {
+            for (resource, value) in limits {
+                if value == 0 {
+                    continue;
+                }
+                let limit = libc::rlimit {
+                    rlim_cur: value,
+                    rlim_max: value,
+                };
+                if libc::setrlimit(resource, &limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|p| p.into_inner())
+}
@@ -0,0 +1,209 @@
+use anyhow::Result;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Characters `encodeURIComponent` leaves alone; addons decode path
+/// segments the way the JavaScript SDK encodes them.
+const URI_COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'!')
+    .remove(b'~')
+    .remove(b'*')
+    .remove(b'\'')
+    .remove(b'(')
+    .remove(b')');
+
+pub fn encode_component(value: &str) -> String {
+    utf8_percent_encode(value, URI_COMPONENT).to_string()
+}
+
+/// One call to an addon resource: `/<resource>/<type>/<id>[/<extra>].json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceRequest {
+    pub resource: String,
+    #[serde(rename = "type")]
+    pub media_type: String,
+    pub id: String,
+    /// `extra` arguments in the order they are sent, e.g. `search`, `skip`,
+    /// `genre`.
+    #[serde(default)]
+    pub extra: Vec<(String, String)>,
+}
+
+impl ResourceRequest {
+    pub fn new(resource: &str, media_type: &str, id: &str) -> Self {
+        Self {
+            resource: resource.to_string(),
+            media_type: media_type.to_string(),
+            id: id.to_string(),
+            extra: Vec::new(),
+        }
+    }
+
+    /// The request path relative to the addon's base URL.
+    pub fn path(&self) -> String {
+        let mut path = format!(
+            "/{}/{}/{}",
+            encode_component(&self.resource),
+            encode_component(&self.media_type),
+            encode_component(&self.id)
+        );
+        if !self.extra.is_empty() {
+            let extra: Vec<String> = self
+                .extra
+                .iter()
+                .map(|(name, value)| {
+                    format!("{}={}", encode_component(name), encode_component(value))
+                })
+                .collect();
+            path.push('/');
+            path.push_str(&extra.join("&"));
+        }
+        path.push_str(".json");
+        path
+    }
+
+    /// Parses a request path as [`Self::path`] writes it, with or without
+    /// the leading slash. `None` if it is not a resource request.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let path = path.trim_start_matches('/').strip_suffix(".json")?;
+        let segments: Vec<&str> = path.split('/').collect();
+        let (resource, media_type, id, extra) = match segments[..] {
+            [resource, media_type, id] => (resource, media_type, id, None),
+            [resource, media_type, id, extra] => (resource, media_type, id, Some(extra)),
+            _ => return None,
+        };
+        let decode = |segment: &str| {
+            percent_decode_str(segment)
+                .decode_utf8()
+                .ok()
+                .map(|text| text.into_owned())
+        };
+        let mut request = Self::new(&decode(resource)?, &decode(media_type)?, &decode(id)?);
+        if request.resource.is_empty() || request.media_type.is_empty() || request.id.is_empty() {
+            return None;
+        }
+        if let Some(extra) = extra {
+            request.extra = url::form_urlencoded::parse(extra.as_bytes())
+                .map(|(name, value)| (name.into_owned(), value.into_owned()))
+                .collect();
+        }
+        Some(request)
+    }
+}
+
+/// Caching directives addons may attach to any response, in seconds.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheHints {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_max_age: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stale_revalidate: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stale_error: Option<u64>,
+}
+
+/// A catalog entry or full meta item as addons send it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetaItem {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub media_type: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poster: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Year or year range, e.g. `2008-2013`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_info: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub genres: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cast: Vec<String>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Stream {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub yt_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_idx: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subtitles: Vec<Subtitle>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subtitle {
+    pub id: String,
+    pub url: String,
+    pub lang: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatalogResponse {
+    pub metas: Vec<MetaItem>,
+    #[serde(flatten)]
+    pub cache: CacheHints,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetaResponse {
+    pub meta: MetaItem,
+    #[serde(flatten)]
+    pub cache: CacheHints,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamResponse {
+    pub streams: Vec<Stream>,
+    #[serde(flatten)]
+    pub cache: CacheHints,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubtitlesResponse {
+    pub subtitles: Vec<Subtitle>,
+    #[serde(flatten)]
+    pub cache: CacheHints,
+}
+
+/// Checks a response against the protocol's shape for the known resources,
+/// returning it as the typed response serializes. Other resources pass
+/// through unchanged.
+pub fn check_response(resource: &str, response: Value) -> Result<Value> {
+    Ok(match resource {
+        "catalog" => serde_json::to_value(serde_json::from_value::<CatalogResponse>(response)?)?,
+        "meta" => serde_json::to_value(serde_json::from_value::<MetaResponse>(response)?)?,
+        "stream" => serde_json::to_value(serde_json::from_value::<StreamResponse>(response)?)?,
+        "subtitles" => {
+            serde_json::to_value(serde_json::from_value::<SubtitlesResponse>(response)?)?
+        }
+        _ => response,
+    })
+}
@@ -0,0 +1,119 @@
+use serde::Serialize;
+
+use super::collection::InstalledAddon;
+use super::manifest::{Manifest, ManifestResource};
+use super::protocol::ResourceRequest;
+
+/// Whether an addon is asked for a request, and why.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteDecision {
+    pub transport_url: String,
+    pub name: String,
+    pub selected: bool,
+    pub reason: String,
+}
+
+pub fn decide(addon: &InstalledAddon, request: &ResourceRequest) -> RouteDecision {
+    let outcome = if addon.flags.enabled {
+        matches(&addon.manifest, request)
+    } else {
+        Err("Addon is disabled".to_string())
+    };
+    let (selected, reason) = match outcome {
+        Ok(reason) => (true, reason),
+        Err(reason) => (false, reason),
+    };
+    RouteDecision {
+        transport_url: addon.transport_url.clone(),
+        name: addon.manifest.name.clone(),
+        selected,
+        reason,
+    }
+}
+
+/// Checks a request against what the manifest declares, explaining the
+/// match or the first mismatch.
+fn matches(manifest: &Manifest, request: &ResourceRequest) -> Result<String, String> {
+    let resource = manifest
+        .resources
+        .iter()
+        .find(|resource| resource.name() == request.resource)
+        .ok_or_else(|| format!("Does not provide {}", request.resource))?;
+
+    // Catalogs are addressed by the catalog ids the manifest lists rather
+    // than by id prefix.
+    if request.resource == "catalog" {
+        return manifest
+            .catalogs
+            .iter()
+            .find(|catalog| catalog.media_type == request.media_type && catalog.id == request.id)
+            .map(|_| format!("Lists catalog {}/{}", request.media_type, request.id))
+            .ok_or_else(|| format!("Has no catalog {}/{}", request.media_type, request.id));
+    }
+
+    let (types, id_prefixes) = declared(manifest, resource);
+    if !types.contains(&request.media_type) {
+        return Err(format!(
+            "{} does not serve type {} (serves {})",
+            request.resource,
+            request.media_type,
+            types.join(", ")
+        ));
+    }
+    match id_prefixes {
+        None => Ok(format!(
+            "Provides {} for {}, any id",
+            request.resource, request.media_type
+        )),
+        Some(prefixes) => prefixes
+            .iter()
+            .find(|prefix| request.id.starts_with(prefix.as_str()))
+            .map(|prefix| {
+                format!(
+                    "Provides {} for {}, id matches prefix {:?}",
+                    request.resource, request.media_type, prefix
+                )
+            })
+            .ok_or_else(|| {
+                format!(
+                    "Id {:?} matches none of the prefixes {}",
+                    request.id,
+                    prefixes.join(", ")
+                )
+            }),
+    }
+}
+
+/// Whether the manifest lists a prefix of `request`'s id for its resource,
+/// rather than taking any id.
+pub fn declares_prefix(manifest: &Manifest, request: &ResourceRequest) -> bool {
+    manifest
+        .resources
+        .iter()
+        .find(|resource| resource.name() == request.resource)
+        .and_then(|resource| declared(manifest, resource).1)
+        .is_some_and(|prefixes| {
+            prefixes
+                .iter()
+                .any(|prefix| request.id.starts_with(prefix.as_str()))
+        })
+}
+
+/// The types and non-empty id prefixes a resource is served for; a full
+/// resource entry overrides the manifest's.
+fn declared<'a>(
+    manifest: &'a Manifest,
+    resource: &'a ManifestResource,
+) -> (&'a Vec<String>, Option<&'a Vec<String>>) {
+    let (types, id_prefixes) = match resource {
+        ManifestResource::Short(_) => (&manifest.types, manifest.id_prefixes.as_ref()),
+        ManifestResource::Full {
+            types, id_prefixes, ..
+        } => (
+            types.as_ref().unwrap_or(&manifest.types),
+            id_prefixes.as_ref().or(manifest.id_prefixes.as_ref()),
+        ),
+    };
+    (types, id_prefixes.filter(|prefixes| !prefixes.is_empty()))
+}
@@ -0,0 +1,528 @@
+//! Runs community addons as WebAssembly modules.
+//!
+//! A sandboxed addon is a directory under the sandboxed addons dir holding
+//! `addon.wasm` and, optionally, `capabilities.json` with the host functions
+//! it asks for. It is addressed as `sandbox://<directory name>`. What it may
+//! actually use is granted in `grants.json` next to the packages, keyed by
+//! directory name, so a package cannot grant itself anything.
+//!
+//! Modules exchange JSON through their own memory. Byte ranges are passed as
+//! an `i64` packing `(ptr << 32) | len`. A module exports:
+//!
+//! - `memory`
+//! - `alloc(len: i32) -> i32`, which the host uses to hand it data
+//! - `manifest() -> i64`, the addon's manifest
+//! - `handle(ptr: i32, len: i32) -> i64`, which takes a resource request
+//!   `{"resource", "type", "id", "extra"}` and returns the response an HTTP
+//!   addon would serve for it
+//!
+//! and may import from `host`:
+//!
+//! - `log(level: i32, ptr: i32, len: i32)`, level 0–4 from error to trace
+//! - `http_get(ptr: i32, len: i32) -> i64`, a GET of the URL, answered with
+//!   `{"status", "body"}` or `{"error"}`; only allowlisted hosts are reachable
+//! - `kv_get(ptr: i32, len: i32) -> i64`, 0 when the key is missing
+//! - `kv_set(key_ptr: i32, key_len: i32, ptr: i32, len: i32) -> i32`, 0 on
+//!   success
+//!
+//! Every call runs in a fresh instance with a fuel budget and memory limit.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{error, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use url::Url;
+use wasmtime::{
+    Caller, Config, Engine, Extern, InstancePre, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder,
+};
+
+use super::protocol::{check_response, ResourceRequest};
+use crate::config::SandboxConfig;
+
+pub const SCHEME: &str = "sandbox://";
+
+/// Largest HTTP response body handed to a module.
+const MAX_BODY_BYTES: usize = 8 * 1024 * 1024;
+/// The user's grants, in the sandboxed addons dir.
+const GRANTS_FILE: &str = "grants.json";
+/// Fuel a module burns between yields to the async runtime.
+const FUEL_YIELD_INTERVAL: u64 = 100_000;
+
+/// Host functions an addon may use. Nothing is granted by default.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Capabilities {
+    /// Hosts `http_get` may reach; `*.example.com` also matches subdomains.
+    pub http_hosts: Vec<String>,
+    pub storage: bool,
+    pub log: bool,
+}
+
+impl Capabilities {
+    fn allows(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        matches!(url.scheme(), "http" | "https")
+            && self
+                .http_hosts
+                .iter()
+                .any(|allowed| match allowed.strip_prefix("*.") {
+                    Some(domain) => host == domain || host.ends_with(&format!(".{}", domain)),
+                    None => host == allowed,
+                })
+    }
+
+    /// Whether everything `requested` is granted here.
+    fn covers(&self, requested: &Capabilities) -> bool {
+        (self.storage || !requested.storage)
+            && (self.log || !requested.log)
+            && requested
+                .http_hosts
+                .iter()
+                .all(|host| self.http_hosts.contains(host))
+    }
+}
+
+/// Per-addon key-value storage, persisted as JSON next to the module.
+struct KvStore {
+    path: PathBuf,
+    quota_bytes: usize,
+    values: Mutex<HashMap<String, String>>,
+    /// Held while a snapshot is written, so writes land in order.
+    saving: tokio::sync::Mutex<()>,
+}
+
+impl KvStore {
+    fn open(path: &Path, quota_bytes: usize) -> Self {
+        let values = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Self {
+            path: path.to_path_buf(),
+            quota_bytes,
+            values: Mutex::new(values),
+            saving: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        self.lock().get(key).cloned()
+    }
+
+    async fn set(&self, key: &str, value: &str) -> Result<()> {
+        let _saving = self.saving.lock().await;
+        let content = {
+            let mut values = self.lock();
+            let used: usize = values
+                .iter()
+                .filter(|(k, _)| k.as_str() != key)
+                .map(|(k, v)| k.len() + v.len())
+                .sum();
+            if used + key.len() + value.len() > self.quota_bytes {
+                bail!("Storage quota of {} bytes exceeded", self.quota_bytes);
+            }
+            values.insert(key.to_string(), value.to_string());
+            serde_json::to_string(&*values)?
+        };
+
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || {
+            let tmp = path.with_extension("json.tmp");
+            std::fs::write(&tmp, content)?;
+            std::fs::rename(&tmp, &path)
+        })
+        .await??;
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, String>> {
+        self.values.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+/// What host functions need to know about the addon calling them.
+struct AddonContext {
+    name: String,
+    capabilities: Capabilities,
+    http: reqwest::Client,
+    storage: Option<KvStore>,
+}
+
+struct HostState {
+    limits: StoreLimits,
+    addon: Arc<AddonContext>,
+}
+
+/// A loaded module, ready to be instantiated for each call.
+pub struct SandboxedAddon {
+    pre: InstancePre<HostState>,
+    context: Arc<AddonContext>,
+    fuel: u64,
+    memory_limit: usize,
+}
+
+impl SandboxedAddon {
+    /// The manifest JSON the module reports.
+    pub async fn manifest(&self) -> Result<Value> {
+        let output = self.call("manifest", None).await?;
+        serde_json::from_slice(&output)
+            .with_context(|| format!("{} returned an invalid manifest", self.context.name))
+    }
+
+    pub async fn request(&self, request: &ResourceRequest) -> Result<Value> {
+        let input = serde_json::to_vec(request)?;
+        let output = self.call("handle", Some(&input)).await?;
+        let response = serde_json::from_slice(&output)
+            .with_context(|| format!("Invalid response from {}", self.context.name))?;
+        check_response(&request.resource, response)
+            .with_context(|| format!("Invalid response from {}", self.context.name))
+    }
+
+    /// Calls an export in a fresh instance, passing `input` as a byte range
+    /// when given, and returns the bytes it points to in its result.
+    async fn call(&self, export: &str, input: Option<&[u8]>) -> Result<Vec<u8>> {
+        let state = HostState {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(self.memory_limit)
+                .instances(1)
+                .build(),
+            addon: self.context.clone(),
+        };
+        let mut store = Store::new(self.pre.module().engine(), state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(self.fuel)?;
+        store.fuel_async_yield_interval(Some(FUEL_YIELD_INTERVAL))?;
+
+        let result = async {
+            let instance = self.pre.instantiate_async(&mut store).await?;
+            let memory = instance
+                .get_memory(&mut store, "memory")
+                .context("Module does not export its memory")?;
+            let packed = match input {
+                None => {
+                    let func = instance.get_typed_func::<(), i64>(&mut store, export)?;
+                    func.call_async(&mut store, ()).await?
+                }
+                Some(input) => {
+                    let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+                    let ptr = alloc.call_async(&mut store, input.len() as i32).await?;
+                    memory.write(&mut store, ptr as u32 as usize, input)?;
+                    let func = instance.get_typed_func::<(i32, i32), i64>(&mut store, export)?;
+                    func.call_async(&mut store, (ptr, input.len() as i32))
+                        .await?
+                }
+            };
+            read(&memory, &store, packed)
+        }
+        .await;
+        result
+            .with_context(|| format!("Sandboxed addon {} failed in {}", self.context.name, export))
+    }
+}
+
+/// Compiles and runs sandboxed addons.
+pub struct SandboxRuntime {
+    engine: Engine,
+    linker: Linker<HostState>,
+    config: SandboxConfig,
+    addons: HashMap<String, Arc<SandboxedAddon>>,
+}
+
+impl SandboxRuntime {
+    pub fn new(config: &SandboxConfig) -> Result<Self> {
+        let mut engine_config = Config::new();
+        engine_config.async_support(true).consume_fuel(true);
+        let engine = Engine::new(&engine_config)?;
+        let mut linker = Linker::new(&engine);
+        define_host(&mut linker)?;
+        Ok(Self {
+            engine,
+            linker,
+            config: config.clone(),
+            addons: HashMap::new(),
+        })
+    }
+
+    /// Loads every addon directory under `dir`. Addons that fail to load are
+    /// logged and skipped.
+    pub fn load_dir(&mut self, dir: &Path) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        let grants_file = dir.join(GRANTS_FILE);
+        let mut grants: HashMap<String, Capabilities> = match std::fs::read_to_string(&grants_file)
+        {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                error!("Sandbox grants at {:?} are unreadable: {}", grants_file, e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !path.join("addon.wasm").is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let granted = grants.remove(&name).unwrap_or_default();
+            match self.load_package(&name, &path, granted, &grants_file) {
+                Ok(()) => info!("Loaded sandboxed addon {}", name),
+                Err(e) => error!("Failed to load sandboxed addon {}: {:#}", name, e),
+            }
+        }
+    }
+
+    fn load_package(
+        &mut self,
+        name: &str,
+        dir: &Path,
+        granted: Capabilities,
+        grants_file: &Path,
+    ) -> Result<()> {
+        let wasm = std::fs::read(dir.join("addon.wasm"))?;
+        // The package's own list is only a request until the user grants it.
+        if let Ok(content) = std::fs::read_to_string(dir.join("capabilities.json")) {
+            let requested: Capabilities =
+                serde_json::from_str(&content).context("Invalid capabilities.json")?;
+            if !granted.covers(&requested) {
+                warn!(
+                    "Sandboxed addon {} asks for {:?} but is granted {:?}; grant it in {:?}",
+                    name, requested, granted, grants_file
+                );
+            }
+        }
+        self.load(name, &wasm, granted, Some(&dir.join("storage.json")))
+    }
+
+    /// Compiles a module and registers it as `sandbox://<name>`. `wasm` may
+    /// be a binary module or its text format.
+    pub fn load(
+        &mut self,
+        name: &str,
+        wasm: &[u8],
+        capabilities: Capabilities,
+        storage_file: Option<&Path>,
+    ) -> Result<()> {
+        let module = Module::new(&self.engine, wasm)?;
+        let pre = self.linker.instantiate_pre(&module)?;
+
+        let allowed = capabilities.clone();
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_millis(self.config.http_timeout_ms))
+            .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+                if allowed.allows(attempt.url()) {
+                    attempt.follow()
+                } else {
+                    attempt.error("Redirect to a host the addon may not reach")
+                }
+            }))
+            .build()?;
+        let storage = match storage_file {
+            Some(path) if capabilities.storage => Some(KvStore::open(
+                path,
+                self.config.storage_quota_kb as usize * 1024,
+            )),
+            _ => None,
+        };
+
+        let addon = SandboxedAddon {
+            pre,
+            context: Arc::new(AddonContext {
+                name: name.to_string(),
+                capabilities,
+                http,
+                storage,
+            }),
+            fuel: self.config.fuel_per_call,
+            memory_limit: self.config.memory_limit_mb as usize * 1024 * 1024,
+        };
+        self.addons.insert(name.to_string(), Arc::new(addon));
+        Ok(())
+    }
+
+    /// The addon a `sandbox://` transport URL refers to.
+    pub fn get(&self, transport_url: &str) -> Result<Arc<SandboxedAddon>> {
+        let name = transport_url
+            .trim()
+            .strip_prefix(SCHEME)
+            .map(|name| name.trim_end_matches('/'))
+            .with_context(|| format!("Not a sandboxed addon URL: {}", transport_url))?;
+        self.addons
+            .get(name)
+            .cloned()
+            .with_context(|| format!("No sandboxed addon named {:?}", name))
+    }
+}
+
+pub fn is_sandboxed(transport_url: &str) -> bool {
+    transport_url.trim().starts_with(SCHEME)
+}
+
+fn define_host(linker: &mut Linker<HostState>) -> Result<()> {
+    linker.func_wrap(
+        "host",
+        "log",
+        |mut caller: Caller<'_, HostState>, level: i32, ptr: i32, len: i32| -> Result<()> {
+            let addon = caller.data().addon.clone();
+            if !addon.capabilities.log {
+                return Ok(());
+            }
+            let bytes = read_range(&mut caller, ptr, len)?;
+            let message = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]);
+            let level = match level {
+                0 => log::Level::Error,
+                1 => log::Level::Warn,
+                2 => log::Level::Info,
+                3 => log::Level::Debug,
+                _ => log::Level::Trace,
+            };
+            log::log!(level, "[{}] {}", addon.name, message);
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap_async(
+        "host",
+        "http_get",
+        |mut caller: Caller<'_, HostState>, (ptr, len): (i32, i32)| {
+            Box::new(async move {
+                let addon = caller.data().addon.clone();
+                let url = String::from_utf8(read_range(&mut caller, ptr, len)?)?;
+                let response = match http_get(&addon, &url).await {
+                    Ok(response) => response,
+                    Err(e) => {
+                        warn!("[{}] GET {} failed: {:#}", addon.name, url, e);
+                        json!({ "error": format!("{:#}", e) })
+                    }
+                };
+                write(&mut caller, &serde_json::to_vec(&response)?).await
+            })
+        },
+    )?;
+
+    linker.func_wrap_async(
+        "host",
+        "kv_get",
+        |mut caller: Caller<'_, HostState>, (ptr, len): (i32, i32)| {
+            Box::new(async move {
+                let addon = caller.data().addon.clone();
+                let key = String::from_utf8(read_range(&mut caller, ptr, len)?)?;
+                match addon.storage.as_ref().and_then(|storage| storage.get(&key)) {
+                    Some(value) => write(&mut caller, value.as_bytes()).await,
+                    None => Ok(0),
+                }
+            })
+        },
+    )?;
+
+    linker.func_wrap_async(
+        "host",
+        "kv_set",
+        |mut caller: Caller<'_, HostState>, (key_ptr, key_len, ptr, len): (i32, i32, i32, i32)| {
+            Box::new(async move {
+                let addon = caller.data().addon.clone();
+                let Some(storage) = &addon.storage else {
+                    return Ok(-1);
+                };
+                let key = String::from_utf8(read_range(&mut caller, key_ptr, key_len)?)?;
+                let value = String::from_utf8(read_range(&mut caller, ptr, len)?)?;
+                match storage.set(&key, &value).await {
+                    Ok(()) => Ok(0),
+                    Err(e) => {
+                        warn!("[{}] Storage write failed: {:#}", addon.name, e);
+                        Ok(-1)
+                    }
+                }
+            })
+        },
+    )?;
+    Ok(())
+}
+
+async fn http_get(addon: &AddonContext, url: &str) -> Result<Value> {
+    let url = Url::parse(url)?;
+    if !addon.capabilities.allows(&url) {
+        bail!(
+            "Host {:?} is not allowed",
+            url.host_str().unwrap_or_default()
+        );
+    }
+    let mut response = addon.http.get(url).send().await?;
+    let status = response.status().as_u16();
+    let too_large = || anyhow!("Response is larger than {} bytes", MAX_BODY_BYTES);
+    if response
+        .content_length()
+        .is_some_and(|len| len > MAX_BODY_BYTES as u64)
+    {
+        return Err(too_large());
+    }
+    // Read in chunks, as the length may be missing or wrong.
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > MAX_BODY_BYTES {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(json!({ "status": status, "body": String::from_utf8_lossy(&body) }))
+}
+
+fn memory(caller: &mut Caller<'_, HostState>) -> Result<Memory> {
+    match caller.get_export("memory") {
+        Some(Extern::Memory(memory)) => Ok(memory),
+        _ => bail!("Module does not export its memory"),
+    }
+}
+
+fn read_range(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> Result<Vec<u8>> {
+    let memory = memory(caller)?;
+    copy_out(
+        memory.data(&caller),
+        ptr as u32 as usize,
+        len as u32 as usize,
+    )
+}
+
+/// Copies `bytes` into memory the module allocates and returns the packed
+/// range.
+async fn write(caller: &mut Caller<'_, HostState>, bytes: &[u8]) -> Result<i64> {
+    let alloc = match caller.get_export("alloc") {
+        Some(Extern::Func(func)) => func.typed::<i32, i32>(&caller)?,
+        _ => bail!("Module does not export alloc"),
+    };
+    let ptr = alloc.call_async(&mut *caller, bytes.len() as i32).await?;
+    memory(caller)?.write(&mut *caller, ptr as u32 as usize, bytes)?;
+    Ok(((ptr as u32 as i64) << 32) | bytes.len() as i64)
+}
+
+fn read(memory: &Memory, store: &Store<HostState>, packed: i64) -> Result<Vec<u8>> {
+    let (ptr, len) = (
+        (packed as u64 >> 32) as usize,
+        (packed as u64 & 0xffff_ffff) as usize,
+    );
+    copy_out(memory.data(store), ptr, len)
+}
+
+/// Copies a range the module names out of its memory, checking the bounds
+/// before allocating so a bogus length cannot exhaust the host's memory.
+fn copy_out(data: &[u8], ptr: usize, len: usize) -> Result<Vec<u8>> {
+    match ptr.checked_add(len) {
+        Some(end) if end <= data.len() => Ok(data[ptr..end].to_vec()),
+        _ => bail!(
+            "Range {}+{} is outside the module's {} bytes of memory",
+            ptr,
+            len,
+            data.len()
+        ),
+    }
+}
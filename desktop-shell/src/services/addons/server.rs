@@ -0,0 +1,183 @@
+//! Serves native addons over the HTTP addon protocol, so Stremio and other
+//! apps can install them from `http://127.0.0.1:<port>/<id>/manifest.json`.
+//!
+//! `/` lists the served addons; every response allows any origin, as the
+//! web clients fetch addons cross-origin.
+
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Path, Request, State},
+    http::{header, HeaderValue, Method, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+
+use super::native::{self, NativeAddons};
+use super::protocol::{CacheHints, ResourceRequest};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ServedAddon {
+    id: String,
+    name: String,
+    version: String,
+    /// Relative to the server, e.g. `/fixtures/manifest.json`.
+    manifest_path: String,
+}
+
+/// Listens on `port` of the loopback interface; 0 picks a free port.
+pub async fn bind(port: u16) -> Result<TcpListener> {
+    TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+        .await
+        .with_context(|| format!("Failed to listen on port {}", port))
+}
+
+/// Serves `addons` on `listener` until the runtime shuts down.
+pub async fn serve(listener: TcpListener, addons: Arc<NativeAddons>) -> Result<()> {
+    let address = listener.local_addr()?;
+    for addon in addons.list() {
+        info!(
+            "Serving {} at http://{}/{}/manifest.json",
+            addon.manifest().name,
+            address,
+            addon.manifest().id
+        );
+    }
+    axum::serve(listener, router(addons))
+        .await
+        .context("Addon server stopped")
+}
+
+pub fn router(addons: Arc<NativeAddons>) -> Router {
+    Router::new()
+        .route("/", get(index))
+        .route("/{addon}/manifest.json", get(manifest))
+        .route("/{addon}/{*path}", get(resource))
+        .fallback(not_found)
+        .layer(middleware::from_fn(cors))
+        .with_state(addons)
+}
+
+async fn index(State(addons): State<Arc<NativeAddons>>) -> Json<Vec<ServedAddon>> {
+    let mut served: Vec<ServedAddon> = addons
+        .list()
+        .into_iter()
+        .map(|addon| {
+            let manifest = addon.manifest();
+            ServedAddon {
+                manifest_path: format!("/{}/manifest.json", manifest.id),
+                id: manifest.id,
+                name: manifest.name,
+                version: manifest.version,
+            }
+        })
+        .collect();
+    served.sort_by(|a, b| a.id.cmp(&b.id));
+    Json(served)
+}
+
+async fn manifest(State(addons): State<Arc<NativeAddons>>, Path(id): Path<String>) -> Response {
+    match addons.get(&format!("{}{}", native::SCHEME, id)) {
+        Ok(addon) => Json(addon.manifest()).into_response(),
+        Err(_) => not_found().await,
+    }
+}
+
+async fn resource(
+    State(addons): State<Arc<NativeAddons>>,
+    Path((id, _)): Path<(String, String)>,
+    uri: Uri,
+) -> Response {
+    let Ok(addon) = addons.get(&format!("{}{}", native::SCHEME, id)) else {
+        return not_found().await;
+    };
+    // The raw path, as the extracted one is already percent-decoded.
+    let request = uri
+        .path()
+        .trim_start_matches('/')
+        .split_once('/')
+        .and_then(|(_, path)| ResourceRequest::from_path(path));
+    let Some(request) = request else {
+        return not_found().await;
+    };
+    let declared = addon
+        .manifest()
+        .resources
+        .iter()
+        .any(|resource| resource.name() == request.resource);
+    if !declared {
+        return not_found().await;
+    }
+
+    match native::call(addon.as_ref(), &request).await {
+        Ok(body) => {
+            let cache_control = cache_control(&body);
+            let mut response = Json(body).into_response();
+            if let Some(value) = cache_control {
+                response.headers_mut().insert(header::CACHE_CONTROL, value);
+            }
+            response
+        }
+        Err(e) => {
+            warn!("{} failed {}: {:#}", id, request.path(), e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "err": format!("{:#}", e) })),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn not_found() -> Response {
+    (StatusCode::NOT_FOUND, Json(json!({ "err": "Not found" }))).into_response()
+}
+
+/// Lets any origin fetch the addons, answering preflight requests itself.
+async fn cors(request: Request, next: Next) -> Response {
+    let mut response = if request.method() == Method::OPTIONS {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        next.run(request).await
+    };
+    let headers = response.headers_mut();
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_HEADERS,
+        HeaderValue::from_static("*"),
+    );
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_METHODS,
+        HeaderValue::from_static("GET, OPTIONS"),
+    );
+    response
+}
+
+/// The `Cache-Control` header for a response's cache hints, as the SDK
+/// sends it.
+fn cache_control(body: &Value) -> Option<HeaderValue> {
+    let hints: CacheHints = serde_json::from_value(body.clone()).ok()?;
+    let mut directives = vec![format!("max-age={}", hints.cache_max_age?)];
+    if let Some(secs) = hints.stale_revalidate {
+        directives.push(format!("stale-while-revalidate={}", secs));
+    }
+    if let Some(secs) = hints.stale_error {
+        directives.push(format!("stale-if-error={}", secs));
+    }
+    directives.push("public".to_string());
+    HeaderValue::from_str(&directives.join(", ")).ok()
+}
@@ -0,0 +1,166 @@
+//! Settings of configurable addons. The UI renders a form from the
+//! manifest's `config` fields; the values it submits are checked here and
+//! carried in the transport URL the way Stremio encodes them:
+//! `<base url>/<URI-encoded JSON>/manifest.json`.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+
+use super::client::base_url;
+use super::collection::InstalledAddon;
+use super::manifest::{ConfigField, ConfigFieldType, Manifest};
+use super::protocol::encode_component;
+use super::validation::{Issue, Severity};
+
+/// The settings an installed addon was configured with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddonSettings {
+    /// The addon's transport URL without settings, to configure it again.
+    pub transport_url: String,
+    pub values: Map<String, Value>,
+}
+
+/// What the UI needs to render an addon's settings form.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsForm {
+    /// The transport URL to submit the form for.
+    pub transport_url: String,
+    pub name: String,
+    pub configurable: bool,
+    pub configuration_required: bool,
+    pub fields: Vec<ConfigField>,
+    /// The installed values, or the defaults for a new configuration.
+    pub values: Map<String, Value>,
+}
+
+/// The outcome of submitting a settings form: the installed addon, or why
+/// the values were refused.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Configured {
+    pub addon: Option<InstalledAddon>,
+    pub issues: Vec<Issue>,
+}
+
+pub fn form(
+    transport_url: &str,
+    manifest: &Manifest,
+    installed: Option<Map<String, Value>>,
+) -> SettingsForm {
+    SettingsForm {
+        transport_url: transport_url.to_string(),
+        name: manifest.name.clone(),
+        configurable: manifest.behavior_hints.configurable,
+        configuration_required: manifest.behavior_hints.configuration_required,
+        fields: manifest.config.clone(),
+        values: installed.unwrap_or_else(|| defaults(&manifest.config)),
+    }
+}
+
+fn defaults(fields: &[ConfigField]) -> Map<String, Value> {
+    fields
+        .iter()
+        .filter_map(|field| {
+            let value = coerce(field, field.default.clone()?).ok()?;
+            Some((field.key.clone(), value))
+        })
+        .collect()
+}
+
+/// Checks submitted values against `fields`, filling in defaults and
+/// normalizing each value to its field's type: strings for text, password
+/// and select, numbers and booleans for number and checkbox fields.
+pub fn check(
+    fields: &[ConfigField],
+    submitted: &Map<String, Value>,
+) -> Result<Map<String, Value>, Vec<Issue>> {
+    let mut values = Map::new();
+    let mut issues = Vec::new();
+    let mut error = |key: &str, message: &str| {
+        issues.push(Issue {
+            severity: Severity::Error,
+            path: key.to_string(),
+            message: message.to_string(),
+        })
+    };
+
+    for key in submitted.keys() {
+        if !fields.iter().any(|field| &field.key == key) {
+            error(key, "Not a setting of this addon");
+        }
+    }
+    for field in fields {
+        let value = submitted
+            .get(&field.key)
+            .filter(|value| !value.is_null() && value.as_str() != Some(""))
+            .or(field.default.as_ref())
+            .cloned();
+        let Some(value) = value else {
+            if field.required {
+                error(&field.key, "Required");
+            }
+            continue;
+        };
+        match coerce(field, value) {
+            Ok(value) => {
+                values.insert(field.key.clone(), value);
+            }
+            Err(message) => error(&field.key, message),
+        }
+    }
+
+    if issues.is_empty() {
+        Ok(values)
+    } else {
+        Err(issues)
+    }
+}
+
+fn coerce(field: &ConfigField, value: Value) -> Result<Value, &'static str> {
+    match (field.field_type, value) {
+        (ConfigFieldType::Text | ConfigFieldType::Password, Value::String(text)) => {
+            Ok(Value::String(text))
+        }
+        (ConfigFieldType::Text | ConfigFieldType::Password, _) => Err("Must be text"),
+        (ConfigFieldType::Number, Value::Number(number)) => Ok(Value::Number(number)),
+        (ConfigFieldType::Number, Value::String(text)) => {
+            let text = text.trim();
+            text.parse::<i64>()
+                .map(Number::from)
+                .ok()
+                .or_else(|| text.parse::<f64>().ok().and_then(Number::from_f64))
+                .map(Value::Number)
+                .ok_or("Must be a number")
+        }
+        (ConfigFieldType::Number, _) => Err("Must be a number"),
+        (ConfigFieldType::Checkbox, Value::Bool(checked)) => Ok(Value::Bool(checked)),
+        // The SDK writes checked defaults as "checked".
+        (ConfigFieldType::Checkbox, Value::String(text)) => match text.as_str() {
+            "checked" | "on" | "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => Err("Must be checked or unchecked"),
+        },
+        (ConfigFieldType::Checkbox, _) => Err("Must be checked or unchecked"),
+        (ConfigFieldType::Select, Value::String(choice)) if field.options.contains(&choice) => {
+            Ok(Value::String(choice))
+        }
+        (ConfigFieldType::Select, _) => Err("Must be one of the listed options"),
+    }
+}
+
+/// The transport URL of the addon at `transport_url` configured with
+/// `values`.
+pub fn configured_url(transport_url: &str, values: &Map<String, Value>) -> Result<String> {
+    if !transport_url.trim().starts_with("http") {
+        bail!("Only HTTP addons can be configured: {}", transport_url);
+    }
+    let settings = Value::Object(values.clone()).to_string();
+    Ok(format!(
+        "{}/{}/manifest.json",
+        base_url(transport_url)?,
+        encode_component(&settings)
+    ))
+}
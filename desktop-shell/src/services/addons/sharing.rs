@@ -0,0 +1,307 @@
+//! Moving an addon collection between machines: a JSON document of the
+//! installed addons, or a share link carrying only their URLs:
+//!
+//! ```text
+//! westream://addons?a=<enabled transport URL>&d=<disabled transport URL>
+//! ```
+//!
+//! Process addons are never exported or imported, as their transport URL
+//! is a command line to run.
+
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::{STANDARD_NO_PAD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+use super::collection::{transport_url, InstalledAddon};
+use super::manifest::ConfigFieldType;
+use super::process;
+use super::settings::AddonSettings;
+
+pub const FORMAT_VERSION: u32 = 1;
+pub const SHARE_URL_PREFIX: &str = "westream://addons";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionExport {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    /// In collection order.
+    pub addons: Vec<ExportedAddon>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedAddon {
+    pub transport_url: String,
+    /// Manifest id and name; empty for addons from a share link.
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<AddonSettings>,
+    /// The settings lost their password fields, and `transport_url` is the
+    /// unconfigured one; the addon has to be configured again.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub redacted: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+/// What importing a collection would change, matched by transport URL or
+/// manifest id.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportDiff {
+    /// Not installed yet.
+    pub added: Vec<ExportedAddon>,
+    /// Installed at another URL or with other settings.
+    pub changed: Vec<ExportedAddon>,
+    /// Installed the same way, but enabled or disabled differently.
+    pub toggled: Vec<ExportedAddon>,
+    pub unchanged: Vec<ExportedAddon>,
+    /// Installed addons the import does not list; importing keeps them.
+    pub not_listed: Vec<ExportedAddon>,
+}
+
+/// The collection as a document. With `redact`, configured addons keep
+/// their unconfigured URL and their settings lose every value that is not
+/// known to be safe to share.
+pub fn export(addons: &[InstalledAddon], redact: bool) -> CollectionExport {
+    CollectionExport {
+        version: FORMAT_VERSION,
+        exported_at: Utc::now(),
+        addons: addons
+            .iter()
+            .filter(|addon| !process::is_process(&addon.transport_url))
+            .map(|addon| exported(addon, redact))
+            .collect(),
+    }
+}
+
+fn exported(addon: &InstalledAddon, redact: bool) -> ExportedAddon {
+    let mut entry = ExportedAddon {
+        transport_url: addon.transport_url.clone(),
+        id: addon.manifest.id.clone(),
+        name: addon.manifest.name.clone(),
+        enabled: addon.flags.enabled,
+        settings: addon.settings.clone(),
+        redacted: false,
+    };
+    if !redact {
+        return entry;
+    }
+    if let Some(settings) = &mut entry.settings {
+        // Keys the manifest does not describe may be secrets too.
+        settings.values.retain(|key, _| {
+            addon
+                .manifest
+                .config
+                .iter()
+                .any(|field| &field.key == key && field.field_type != ConfigFieldType::Password)
+        });
+        entry.transport_url = settings.transport_url.clone();
+        entry.redacted = true;
+    } else if let Some(url) = pasted_config(addon) {
+        entry.transport_url = url;
+        entry.redacted = true;
+    }
+    entry
+}
+
+/// For a configurable addon installed from an already-configured URL, e.g.
+/// one pasted with an API key in it, rather than through its settings
+/// form: the URL without the path segment carrying the settings. URLs
+/// whose last segment does not read as settings are left alone, as it may
+/// just be part of where the addon is hosted.
+fn pasted_config(addon: &InstalledAddon) -> Option<String> {
+    let manifest = &addon.manifest;
+    let configurable = manifest.behavior_hints.configurable
+        || manifest.behavior_hints.configuration_required
+        || !manifest.config.is_empty();
+    if addon.settings.is_some() || !configurable {
+        return None;
+    }
+    let mut url = Url::parse(&addon.transport_url).ok()?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+    // `[<base path>..., <settings>, "manifest.json"]`
+    let segments: Vec<String> = url.path_segments()?.map(str::to_string).collect();
+    let [base @ .., config, _] = segments.as_slice() else {
+        return None;
+    };
+    if !is_config_segment(config) {
+        return None;
+    }
+    url.set_path(&format!("{}/manifest.json", base.join("/")));
+    url.set_query(None);
+    Some(url.to_string())
+}
+
+/// Whether a path segment holds settings in one of the forms addons use:
+/// a JSON object (as `configured_url` writes it), base64-encoded JSON, or
+/// `key=value` pairs joined by `|` or `&`.
+fn is_config_segment(segment: &str) -> bool {
+    let decoded = percent_decode_str(segment).decode_utf8_lossy();
+    let is_object =
+        |bytes: &[u8]| serde_json::from_slice::<Value>(bytes).is_ok_and(|value| value.is_object());
+    if is_object(decoded.as_bytes()) {
+        return true;
+    }
+    let unpadded = decoded.trim_end_matches('=');
+    let is_base64_object = [URL_SAFE_NO_PAD, STANDARD_NO_PAD]
+        .iter()
+        .any(|engine| engine.decode(unpadded).is_ok_and(|bytes| is_object(&bytes)));
+    if is_base64_object {
+        return true;
+    }
+    decoded.split(['|', '&']).all(|pair| {
+        pair.split_once('=').is_some_and(|(key, _)| {
+            !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+        })
+    })
+}
+
+/// A share link for the collection. Configured addons, including those
+/// installed from a configured URL, are shared by their unconfigured URL,
+/// so it never carries settings.
+pub fn share_url(addons: &[InstalledAddon]) -> String {
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    for entry in export(addons, true).addons {
+        query.append_pair(if entry.enabled { "a" } else { "d" }, &entry.transport_url);
+    }
+    format!("{}?{}", SHARE_URL_PREFIX, query.finish())
+}
+
+/// Reads a collection from a document, as an object or JSON text, or from
+/// a share link.
+pub fn parse(source: &Value) -> Result<CollectionExport> {
+    let document = match source {
+        Value::String(text) if text.trim().starts_with(SHARE_URL_PREFIX) => {
+            return parse_share_url(text.trim())
+        }
+        Value::String(text) => serde_json::from_str(text).context("Not a collection export")?,
+        other => serde_json::from_value::<CollectionExport>(other.clone())
+            .context("Not a collection export")?,
+    };
+    if document.version > FORMAT_VERSION {
+        bail!(
+            "The collection was exported by a newer version (format {})",
+            document.version
+        );
+    }
+    let mut addons = Vec::with_capacity(document.addons.len());
+    for mut entry in document.addons {
+        entry.transport_url = importable(&entry.transport_url)?;
+        addons.push(entry);
+    }
+    Ok(CollectionExport { addons, ..document })
+}
+
+fn parse_share_url(link: &str) -> Result<CollectionExport> {
+    let link = Url::parse(link).context("Invalid share link")?;
+    let mut addons = Vec::new();
+    for (key, url) in link.query_pairs() {
+        let enabled = match key.as_ref() {
+            "a" => true,
+            "d" => false,
+            _ => continue,
+        };
+        addons.push(ExportedAddon {
+            transport_url: importable(&url)?,
+            id: String::new(),
+            name: String::new(),
+            enabled,
+            settings: None,
+            redacted: false,
+        });
+    }
+    Ok(CollectionExport {
+        version: FORMAT_VERSION,
+        exported_at: Utc::now(),
+        addons,
+    })
+}
+
+fn importable(url: &str) -> Result<String> {
+    if process::is_process(url) {
+        bail!("Process addons cannot be imported: {}", url);
+    }
+    transport_url(url)
+}
+
+/// The installed addon `entry` would replace: the one at the same URL, or
+/// else one configured from the same URL or with the same manifest id.
+pub fn installed_match<'a>(
+    installed: &'a [InstalledAddon],
+    entry: &ExportedAddon,
+) -> Option<&'a InstalledAddon> {
+    installed
+        .iter()
+        .find(|addon| addon.transport_url == entry.transport_url)
+        .or_else(|| {
+            installed.iter().find(|addon| {
+                configured_from(addon).as_deref() == Some(unconfigured_url(entry))
+                    || (!entry.id.is_empty() && addon.manifest.id == entry.id)
+            })
+        })
+}
+
+pub fn diff(import: &CollectionExport, installed: &[InstalledAddon]) -> ImportDiff {
+    let mut diff = ImportDiff::default();
+    let mut matched = Vec::new();
+    for entry in &import.addons {
+        let Some(addon) = installed_match(installed, entry) else {
+            diff.added.push(entry.clone());
+            continue;
+        };
+        matched.push(addon.transport_url.clone());
+        // An entry without its full settings, redacted or from a share
+        // link, leaves an addon configured from the same URL as it is.
+        let partial = entry.redacted || entry.settings.is_none();
+        let same = addon.transport_url == entry.transport_url
+            || (partial && configured_from(addon).as_deref() == Some(unconfigured_url(entry)));
+        let list = if !same {
+            &mut diff.changed
+        } else if addon.flags.enabled != entry.enabled {
+            &mut diff.toggled
+        } else {
+            &mut diff.unchanged
+        };
+        list.push(entry.clone());
+    }
+    diff.not_listed = installed
+        .iter()
+        .filter(|addon| !matched.contains(&addon.transport_url))
+        .map(|addon| exported(addon, true))
+        .collect();
+    diff
+}
+
+/// The unconfigured URL of a configured addon.
+fn configured_from(addon: &InstalledAddon) -> Option<String> {
+    match &addon.settings {
+        Some(settings) => Some(settings.transport_url.clone()),
+        None => pasted_config(addon),
+    }
+}
+
+fn unconfigured_url(entry: &ExportedAddon) -> &str {
+    entry
+        .settings
+        .as_ref()
+        .map_or(&entry.transport_url, |settings| &settings.transport_url)
+}
@@ -0,0 +1,295 @@
+//! The addon store: addons listed by repository indexes, each signed with
+//! its repository's Ed25519 key.
+//!
+//! An index is a JSON file, fetched over HTTP or read from disk, with a
+//! detached hex signature of its exact bytes at the same location plus
+//! `.sig`:
+//!
+//! ```json
+//! {
+//!   "name": "Community",
+//!   "categories": [{ "id": "subtitles", "name": "Subtitles" }],
+//!   "addons": [{
+//!     "transportUrl": "https://addon.example.com/manifest.json",
+//!     "manifest": { ... },
+//!     "categories": ["subtitles"],
+//!     "downloadUrl": "https://example.com/addon.wasm"
+//!   }]
+//! }
+//! ```
+
+use std::{
+    collections::HashSet,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use ed25519_dalek::{Signature, VerifyingKey};
+use futures::future::join_all;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::collection::{transport_url, InstalledAddon};
+use super::manifest::Manifest;
+use super::validation::validate;
+use crate::config::RepositoryConfig;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Category {
+    pub id: String,
+    pub name: String,
+}
+
+/// An addon as a repository lists it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoreEntry {
+    pub transport_url: String,
+    pub manifest: Manifest,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// Where to download the addon, for addons not served over HTTP.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_url: Option<String>,
+    /// Name of the repository that lists it.
+    #[serde(default)]
+    pub repository: String,
+}
+
+#[derive(Deserialize)]
+struct RepositoryIndex {
+    name: String,
+    #[serde(default)]
+    categories: Vec<Category>,
+    /// Parsed one by one so a bad entry does not hide the others.
+    #[serde(default)]
+    addons: Vec<Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RepositoryError {
+    pub url: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct StoreQuery {
+    /// Words that must all appear in an addon's id, name or description.
+    pub search: Option<String>,
+    /// Only addons in the category with this id.
+    pub category: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListedAddon {
+    #[serde(flatten)]
+    pub entry: StoreEntry,
+    pub installed: bool,
+    /// Installed, and the store lists a newer version.
+    pub update_available: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StoreListing {
+    pub categories: Vec<Category>,
+    pub addons: Vec<ListedAddon>,
+    /// Repositories that could not be loaded or failed verification.
+    pub errors: Vec<RepositoryError>,
+}
+
+#[derive(Default)]
+struct Loaded {
+    categories: Vec<Category>,
+    entries: Vec<StoreEntry>,
+    errors: Vec<RepositoryError>,
+}
+
+pub struct AddonStore {
+    repositories: Vec<RepositoryConfig>,
+    http: reqwest::Client,
+    /// `None` until the indexes are first loaded.
+    loaded: Mutex<Option<Loaded>>,
+}
+
+impl AddonStore {
+    pub fn new(repositories: &[RepositoryConfig], timeout: Duration) -> Result<Self> {
+        Ok(Self {
+            repositories: repositories.to_vec(),
+            http: reqwest::Client::builder().timeout(timeout).build()?,
+            loaded: Mutex::new(None),
+        })
+    }
+
+    /// Fetches every repository index again. An addon listed by several
+    /// repositories is taken from the first one in the configuration.
+    pub async fn refresh(&self) {
+        let indexes = join_all(self.repositories.iter().map(|repo| self.load(repo))).await;
+
+        let mut loaded = Loaded::default();
+        let mut seen = HashSet::new();
+        for (repo, index) in self.repositories.iter().zip(indexes) {
+            let (categories, entries) = match index {
+                Ok(index) => index,
+                Err(e) => {
+                    warn!("Addon repository {} unavailable: {:#}", repo.url, e);
+                    loaded.errors.push(RepositoryError {
+                        url: repo.url.clone(),
+                        error: format!("{:#}", e),
+                    });
+                    continue;
+                }
+            };
+            for category in categories {
+                if !loaded.categories.iter().any(|c| c.id == category.id) {
+                    loaded.categories.push(category);
+                }
+            }
+            loaded.entries.extend(
+                entries
+                    .into_iter()
+                    .filter(|entry| seen.insert(entry.manifest.id.clone())),
+            );
+        }
+        info!(
+            "Addon store lists {} addons from {} repositories",
+            loaded.entries.len(),
+            self.repositories.len() - loaded.errors.len()
+        );
+        *self.lock() = Some(loaded);
+    }
+
+    /// The addons matching `query`, marked against the installed ones.
+    /// Loads the indexes on first use.
+    pub async fn browse(&self, query: &StoreQuery, installed: &[InstalledAddon]) -> StoreListing {
+        if self.lock().is_none() {
+            self.refresh().await;
+        }
+        let loaded = self.lock();
+        let Some(loaded) = loaded.as_ref() else {
+            return StoreListing::default();
+        };
+
+        let words: Vec<String> = query
+            .search
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_lowercase)
+            .collect();
+        let addons = loaded
+            .entries
+            .iter()
+            .filter(|entry| {
+                query
+                    .category
+                    .as_ref()
+                    .is_none_or(|category| entry.categories.contains(category))
+            })
+            .filter(|entry| {
+                let text = format!(
+                    "{} {} {}",
+                    entry.manifest.id, entry.manifest.name, entry.manifest.description
+                )
+                .to_lowercase();
+                words.iter().all(|word| text.contains(word))
+            })
+            .map(|entry| {
+                let installed = installed
+                    .iter()
+                    .find(|addon| addon.manifest.id == entry.manifest.id);
+                ListedAddon {
+                    entry: entry.clone(),
+                    installed: installed.is_some(),
+                    update_available: installed
+                        .is_some_and(|addon| is_newer(&entry.manifest, &addon.manifest)),
+                }
+            })
+            .collect();
+
+        StoreListing {
+            categories: loaded.categories.clone(),
+            addons,
+            errors: loaded.errors.clone(),
+        }
+    }
+
+    /// Reads and verifies one index, returning its categories and the
+    /// entries whose manifests are valid.
+    async fn load(&self, repo: &RepositoryConfig) -> Result<(Vec<Category>, Vec<StoreEntry>)> {
+        let index = self.read(&repo.url).await?;
+        let signature = self.read(&format!("{}.sig", repo.url)).await?;
+        verify(&repo.public_key, &index, &signature)?;
+
+        let index: RepositoryIndex =
+            serde_json::from_slice(&index).context("Invalid repository index")?;
+        let mut entries = Vec::new();
+        for (position, value) in index.addons.into_iter().enumerate() {
+            match entry(value, &index.name) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!(
+                    "Skipping addons[{}] of repository {}: {:#}",
+                    position, repo.url, e
+                ),
+            }
+        }
+        Ok((index.categories, entries))
+    }
+
+    async fn read(&self, location: &str) -> Result<Vec<u8>> {
+        if location.starts_with("https://") || location.starts_with("http://") {
+            let response = self.http.get(location).send().await?;
+            let status = response.status();
+            if !status.is_success() {
+                bail!("{} returned HTTP {}", location, status);
+            }
+            Ok(response.bytes().await?.to_vec())
+        } else {
+            std::fs::read(location).with_context(|| format!("Failed to read {}", location))
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<Loaded>> {
+        self.loaded.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+fn entry(value: Value, repository: &str) -> Result<StoreEntry> {
+    let report = validate(value.get("manifest").unwrap_or(&Value::Null));
+    if !report.valid {
+        bail!("Invalid manifest: {}", report);
+    }
+    let mut entry: StoreEntry = serde_json::from_value(value)?;
+    entry.transport_url = transport_url(&entry.transport_url)?;
+    entry.repository = repository.to_string();
+    Ok(entry)
+}
+
+/// Checks `signature`, a hex Ed25519 signature, of `index` against the
+/// hex-encoded `public_key`.
+pub fn verify(public_key: &str, index: &[u8], signature: &[u8]) -> Result<()> {
+    let key: [u8; 32] = hex::decode(public_key.trim())
+        .context("Repository key is not hex")?
+        .try_into()
+        .map_err(|_| anyhow!("Repository key must be 32 bytes"))?;
+    let signature: [u8; 64] = hex::decode(String::from_utf8_lossy(signature).trim())
+        .context("Index signature is not hex")?
+        .try_into()
+        .map_err(|_| anyhow!("Index signature must be 64 bytes"))?;
+    VerifyingKey::from_bytes(&key)?
+        .verify_strict(index, &Signature::from_bytes(&signature))
+        .context("Index signature does not match the repository key")
+}
+
+fn is_newer(listed: &Manifest, installed: &Manifest) -> bool {
+    match (
+        semver::Version::parse(&listed.version),
+        semver::Version::parse(&installed.version),
+    ) {
+        (Ok(listed), Ok(installed)) => listed > installed,
+        _ => false,
+    }
+}
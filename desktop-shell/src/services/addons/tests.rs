@@ -0,0 +1,1521 @@
+//! Runs the addon client against a local mock addon server.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use serde_json::{json, Map, Value};
+
+use super::client::{base_url, resource_url, AddonClient};
+use super::collection::{AddonCollection, AddonFlags, InstalledAddon};
+use super::health::{CircuitState, HealthTracker};
+use super::manifest::{Manifest, ManifestResource};
+use super::native::{self, NativeAddons};
+#[cfg(unix)]
+use super::process::ProcessRunner;
+use super::protocol::ResourceRequest;
+use super::routing::decide;
+use super::sandbox::{Capabilities, SandboxRuntime};
+use super::server;
+use super::settings;
+use super::sharing;
+use super::store::{AddonStore, StoreQuery};
+use super::updates;
+use super::validation::{validate, Severity};
+use super::AddonManager;
+use crate::config::{AddonsConfig, CircuitBreakerConfig};
+use crate::services::events::EventBus;
+use crate::services::metadata::fixtures::FixtureProvider;
+
+fn with_extra(resource: &str, id: &str, extra: &[(&str, &str)]) -> ResourceRequest {
+    let mut request = ResourceRequest::new(resource, "movie", id);
+    request.extra = extra
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    request
+}
+
+/// Serves canned JSON by request path and records every path requested.
+struct MockAddon {
+    base: String,
+    routes: Arc<Mutex<HashMap<String, (u16, String)>>>,
+    requests: Arc<Mutex<Vec<String>>>,
+    /// How long to wait before answering.
+    delay: Arc<Mutex<Duration>>,
+}
+
+impl MockAddon {
+    fn start(routes: Vec<(&str, u16, Value)>) -> Self {
+        let routes: HashMap<String, (u16, String)> = routes
+            .into_iter()
+            .map(|(path, status, body)| (path.to_string(), (status, body.to_string())))
+            .collect();
+        let routes = Arc::new(Mutex::new(routes));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}/addon", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let delay = Arc::new(Mutex::new(Duration::ZERO));
+
+        let (served, recorded, wait) = (routes.clone(), requests.clone(), delay.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                // Drain the headers.
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+
+                let path = request_line.split(' ').nth(1).unwrap_or("").to_string();
+                recorded.lock().unwrap().push(path.clone());
+                let (status, body) = match path
+                    .strip_prefix("/addon")
+                    .and_then(|p| served.lock().unwrap().get(p).cloned())
+                {
+                    Some(route) => route,
+                    None => (404, "{}".to_string()),
+                };
+                thread::sleep(*wait.lock().unwrap());
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+            }
+        });
+
+        Self {
+            base,
+            routes,
+            requests,
+            delay,
+        }
+    }
+
+    fn delay(&self, delay: Duration) {
+        *self.delay.lock().unwrap() = delay;
+    }
+
+    fn set(&self, path: &str, status: u16, body: Value) {
+        self.routes
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), (status, body.to_string()));
+    }
+
+    fn request_count(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+
+    fn manifest_url(&self) -> String {
+        format!("{}/manifest.json", self.base)
+    }
+
+    fn last_request(&self) -> String {
+        self.requests
+            .lock()
+            .unwrap()
+            .last()
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// A manager with an empty collection in a fresh directory, and no
+/// built-in addons.
+fn manager(name: &str, config: AddonsConfig, events: EventBus) -> (AddonManager, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("addon-manager-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = AddonsConfig {
+        builtin: Vec::new(),
+        ..config
+    };
+    let manager = AddonManager::new(
+        &config,
+        &dir.join("addons.json"),
+        &dir.join("sandboxed"),
+        &dir.join("processes"),
+        Vec::new(),
+        events,
+    )
+    .unwrap();
+    (manager, dir)
+}
+
+fn client() -> AddonClient {
+    AddonClient::new(Duration::from_secs(5), 100).unwrap()
+}
+
+fn manifest() -> Value {
+    json!({
+        "id": "org.example.mock",
+        "version": "1.2.0",
+        "name": "Mock Addon",
+        "description": "Test fixture",
+        "types": ["movie", "series"],
+        "resources": [
+            "catalog",
+            { "name": "meta", "types": ["series"], "idPrefixes": ["tt"] },
+            "stream",
+            "subtitles"
+        ],
+        "catalogs": [{
+            "type": "movie",
+            "id": "top",
+            "name": "Top",
+            "extra": [
+                { "name": "search" },
+                { "name": "genre", "options": ["Drama", "Sci-Fi"] },
+                { "name": "skip" }
+            ]
+        }],
+        "behaviorHints": { "configurable": true },
+        "addonCatalogs": []
+    })
+}
+
+#[tokio::test]
+async fn fetches_and_parses_manifest() {
+    let addon = MockAddon::start(vec![("/manifest.json", 200, manifest())]);
+
+    let manifest = client().manifest(&addon.manifest_url()).await.unwrap();
+
+    assert_eq!(addon.last_request(), "/addon/manifest.json");
+    assert_eq!(manifest.id, "org.example.mock");
+    assert_eq!(manifest.types, ["movie", "series"]);
+    assert_eq!(manifest.resources.len(), 4);
+    assert!(matches!(&manifest.resources[0], ManifestResource::Short(name) if name == "catalog"));
+    assert!(matches!(
+        &manifest.resources[1],
+        ManifestResource::Full { name, id_prefixes: Some(prefixes), .. }
+            if name == "meta" && prefixes == &["tt"]
+    ));
+    assert_eq!(manifest.catalogs[0].extra.len(), 3);
+    assert_eq!(manifest.catalogs[0].extra[1].options, ["Drama", "Sci-Fi"]);
+    assert!(manifest.behavior_hints.configurable);
+    // Unknown fields survive a round trip.
+    assert!(manifest.other.contains_key("addonCatalogs"));
+}
+
+#[tokio::test]
+async fn accepts_base_url_without_manifest_suffix() {
+    let addon = MockAddon::start(vec![("/manifest.json", 200, manifest())]);
+
+    let manifest = client()
+        .manifest(&format!("{}/", addon.base))
+        .await
+        .unwrap();
+
+    assert_eq!(manifest.name, "Mock Addon");
+    assert_eq!(addon.last_request(), "/addon/manifest.json");
+}
+
+#[test]
+fn normalizes_transport_urls() {
+    assert_eq!(
+        base_url("stremio://example.com/abc/manifest.json").unwrap(),
+        "https://example.com/abc"
+    );
+    assert_eq!(
+        base_url(" https://example.com/manifest.json#x ").unwrap(),
+        "https://example.com"
+    );
+    assert!(base_url("ftp://example.com/manifest.json").is_err());
+    assert!(base_url("https://example.com/manifest.json?a=b").is_err());
+    assert!(base_url("not a url").is_err());
+}
+
+#[test]
+fn encodes_ids_and_extra_like_encode_uri_component() {
+    let request = with_extra(
+        "catalog",
+        "top",
+        &[
+            ("search", "the matrix & co/2"),
+            ("genre", "Sci-Fi"),
+            ("skip", "100"),
+        ],
+    );
+    assert_eq!(
+        resource_url("https://example.com/manifest.json", &request).unwrap(),
+        "https://example.com/catalog/movie/top/search=the%20matrix%20%26%20co%2F2&genre=Sci-Fi&skip=100.json"
+    );
+
+    let request = ResourceRequest::new("stream", "series", "tt0903747:1:1");
+    assert_eq!(request.path(), "/stream/series/tt0903747%3A1%3A1.json");
+
+    let request = with_extra("catalog", "top", &[("search", "Amélie (2001)!")]);
+    assert_eq!(
+        request.path(),
+        "/catalog/movie/top/search=Am%C3%A9lie%20(2001)!.json"
+    );
+}
+
+#[tokio::test]
+async fn requests_catalog_with_extra() {
+    let addon = MockAddon::start(vec![(
+        "/catalog/movie/top/search=star%20wars&skip=20.json",
+        200,
+        json!({
+            "metas": [
+                { "id": "tt0076759", "type": "movie", "name": "Star Wars", "releaseInfo": "1977", "imdbRating": "8.6" }
+            ],
+            "cacheMaxAge": 3600
+        }),
+    )]);
+    let extra = vec![
+        ("search".to_string(), "star wars".to_string()),
+        ("skip".to_string(), "20".to_string()),
+    ];
+
+    let catalog = client()
+        .catalog(&addon.manifest_url(), "movie", "top", &extra)
+        .await
+        .unwrap();
+
+    assert_eq!(catalog.metas.len(), 1);
+    assert_eq!(catalog.metas[0].name, "Star Wars");
+    assert_eq!(catalog.metas[0].release_info.as_deref(), Some("1977"));
+    assert_eq!(catalog.metas[0].other["imdbRating"], "8.6");
+    assert_eq!(catalog.cache.cache_max_age, Some(3600));
+}
+
+#[tokio::test]
+async fn requests_meta_streams_and_subtitles() {
+    let addon = MockAddon::start(vec![
+        (
+            "/meta/series/tt0903747.json",
+            200,
+            json!({ "meta": { "id": "tt0903747", "type": "series", "name": "Breaking Bad", "videos": [] } }),
+        ),
+        (
+            "/stream/series/tt0903747%3A1%3A1.json",
+            200,
+            json!({
+                "streams": [
+                    { "url": "https://cdn.example.com/bb-s1e1.mp4", "name": "HTTP", "title": "1080p" },
+                    { "infoHash": "0123456789abcdef0123456789abcdef01234567", "fileIdx": 2 }
+                ],
+                "staleRevalidate": 60
+            }),
+        ),
+        (
+            "/subtitles/series/tt0903747%3A1%3A1/videoHash=abc123&videoSize=1048576.json",
+            200,
+            json!({ "subtitles": [ { "id": "1", "url": "https://subs.example.com/1.srt", "lang": "eng" } ] }),
+        ),
+    ]);
+    let client = client();
+    let url = addon.manifest_url();
+
+    let meta = client.meta(&url, "series", "tt0903747").await.unwrap();
+    assert_eq!(meta.meta.name, "Breaking Bad");
+
+    let streams = client
+        .streams(&url, "series", "tt0903747:1:1")
+        .await
+        .unwrap();
+    assert_eq!(streams.streams.len(), 2);
+    assert_eq!(
+        streams.streams[0].url.as_deref(),
+        Some("https://cdn.example.com/bb-s1e1.mp4")
+    );
+    assert_eq!(streams.streams[1].file_idx, Some(2));
+    assert_eq!(streams.cache.stale_revalidate, Some(60));
+
+    let extra = vec![
+        ("videoHash".to_string(), "abc123".to_string()),
+        ("videoSize".to_string(), "1048576".to_string()),
+    ];
+    let subtitles = client
+        .subtitles(&url, "series", "tt0903747:1:1", &extra)
+        .await
+        .unwrap();
+    assert_eq!(subtitles.subtitles[0].lang, "eng");
+}
+
+#[tokio::test]
+async fn reports_http_and_parse_errors() {
+    let addon = MockAddon::start(vec![
+        (
+            "/meta/movie/broken.json",
+            200,
+            json!({ "unexpected": true }),
+        ),
+        (
+            "/meta/movie/down.json",
+            503,
+            json!({ "err": "maintenance" }),
+        ),
+    ]);
+    let client = client();
+    let url = addon.manifest_url();
+
+    let missing = client.meta(&url, "movie", "nothing").await.unwrap_err();
+    assert!(missing.to_string().contains("404"), "{}", missing);
+
+    let down = client.meta(&url, "movie", "down").await.unwrap_err();
+    assert!(down.to_string().contains("503"), "{}", down);
+
+    let broken = client.meta(&url, "movie", "broken").await.unwrap_err();
+    assert!(
+        broken.to_string().contains("Invalid response"),
+        "{}",
+        broken
+    );
+}
+
+#[test]
+fn accepts_well_formed_manifest() {
+    let report = validate(&manifest());
+
+    assert!(report.valid, "{}", report);
+    assert!(report.issues.is_empty(), "{:?}", report.issues);
+}
+
+#[test]
+fn reports_every_manifest_problem() {
+    let report = validate(&json!({
+        "id": "not an id",
+        "version": "1.0",
+        "types": [],
+        "resources": ["stream", { "name": "stream" }, "telepathy"],
+        "catalogs": [
+            { "type": "movie", "id": "top", "extra": [{ "name": "skip" }, { "name": "skip", "optionsLimit": 0 }] },
+            { "type": "movie", "id": "top", "extraRequired": ["genre"] }
+        ],
+        "behaviorHints": { "configurable": "yes", "configurationRequired": true }
+    }));
+
+    let errors: Vec<&str> = report.errors().map(|issue| issue.path.as_str()).collect();
+    assert_eq!(
+        errors,
+        [
+            "id",
+            "version",
+            "name",
+            "types",
+            "resources[1]",
+            "catalogs[0].extra[1]",
+            "catalogs[0].extra[1].optionsLimit",
+            "catalogs[1]",
+            "catalogs[1].extraRequired",
+            "behaviorHints.configurable",
+        ]
+    );
+    let warnings: Vec<&str> = report
+        .issues
+        .iter()
+        .filter(|issue| issue.severity == Severity::Warning)
+        .map(|issue| issue.path.as_str())
+        .collect();
+    assert_eq!(
+        warnings,
+        [
+            "description",
+            "resources[2]",
+            "resources",
+            "behaviorHints.configurationRequired"
+        ]
+    );
+    assert!(!report.valid);
+}
+
+#[tokio::test]
+async fn refuses_invalid_manifest() {
+    let addon = MockAddon::start(vec![(
+        "/manifest.json",
+        200,
+        json!({ "id": "org.example.broken", "version": "one", "name": "Broken" }),
+    )]);
+
+    let error = client().manifest(&addon.manifest_url()).await.unwrap_err();
+
+    assert!(
+        error
+            .to_string()
+            .contains("version: Not a semantic version"),
+        "{}",
+        error
+    );
+}
+
+fn installed(manifest: Value, enabled: bool) -> InstalledAddon {
+    InstalledAddon {
+        transport_url: "http://127.0.0.1/manifest.json".to_string(),
+        manifest: serde_json::from_value(manifest).unwrap(),
+        flags: AddonFlags {
+            enabled,
+            protected: false,
+        },
+        previous_manifest: None,
+        skipped_version: None,
+        settings: None,
+        installed_at: chrono::Utc::now(),
+    }
+}
+
+#[test]
+fn routes_by_resource_type_and_id_prefix() {
+    // Meta is limited to series with tt ids; streams fall back to the
+    // manifest-wide prefixes.
+    let mut mock = manifest();
+    mock["idPrefixes"] = json!(["tt", "kitsu:"]);
+    let addon = installed(mock, true);
+    let routed = |resource: &str, media_type: &str, id: &str| {
+        decide(&addon, &ResourceRequest::new(resource, media_type, id)).selected
+    };
+
+    assert!(routed("meta", "series", "tt0903747"));
+    assert!(!routed("meta", "movie", "tt0816692"));
+    assert!(!routed("meta", "series", "kitsu:123"));
+    assert!(routed("stream", "movie", "tt0816692"));
+    assert!(routed("stream", "series", "kitsu:123:4"));
+    assert!(!routed("stream", "series", "tmdb:1396"));
+    assert!(!routed("stream", "channel", "tt0816692"));
+    assert!(routed("catalog", "movie", "top"));
+    assert!(!routed("catalog", "series", "top"));
+    assert!(!routed("addon_catalog", "movie", "top"));
+
+    let decision = decide(
+        &installed(manifest(), false),
+        &ResourceRequest::new("stream", "movie", "tt0816692"),
+    );
+    assert!(!decision.selected);
+    assert_eq!(decision.reason, "Addon is disabled");
+}
+
+#[tokio::test]
+async fn serves_fresh_responses_from_cache() {
+    let path = "/stream/movie/tt0816692.json";
+    let addon = MockAddon::start(vec![(
+        path,
+        200,
+        json!({ "streams": [], "cacheMaxAge": 60 }),
+    )]);
+    let client = client();
+    let url = addon.manifest_url();
+
+    client.streams(&url, "movie", "tt0816692").await.unwrap();
+    client.streams(&url, "movie", "tt0816692").await.unwrap();
+
+    assert_eq!(addon.request_count(), 1);
+}
+
+#[tokio::test]
+async fn falls_back_to_stale_response_on_error() {
+    let path = "/stream/movie/tt0816692.json";
+    let addon = MockAddon::start(vec![(
+        path,
+        200,
+        json!({ "streams": [{ "url": "https://cdn.example.com/a.mp4" }], "staleError": 60 }),
+    )]);
+    let client = client();
+    let url = addon.manifest_url();
+
+    client.streams(&url, "movie", "tt0816692").await.unwrap();
+    addon.set(path, 500, json!({}));
+    let stale = client.streams(&url, "movie", "tt0816692").await.unwrap();
+
+    assert_eq!(addon.request_count(), 2);
+    assert_eq!(stale.streams.len(), 1);
+}
+
+#[tokio::test]
+async fn revalidates_stale_responses_in_background() {
+    let path = "/stream/movie/tt0816692.json";
+    let addon = MockAddon::start(vec![(
+        path,
+        200,
+        json!({ "streams": [], "staleRevalidate": 60 }),
+    )]);
+    let client = client();
+    let url = addon.manifest_url();
+
+    client.streams(&url, "movie", "tt0816692").await.unwrap();
+    addon.set(
+        path,
+        200,
+        json!({ "streams": [{ "url": "https://cdn.example.com/new.mp4" }], "staleRevalidate": 60 }),
+    );
+    let stale = client.streams(&url, "movie", "tt0816692").await.unwrap();
+    assert!(stale.streams.is_empty());
+
+    for _ in 0..50 {
+        let response = client.streams(&url, "movie", "tt0816692").await.unwrap();
+        if !response.streams.is_empty() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("Stale response was never refreshed");
+}
+
+#[tokio::test]
+async fn deduplicates_concurrent_requests() {
+    let addon = MockAddon::start(vec![(
+        "/stream/movie/tt0816692.json",
+        200,
+        json!({ "streams": [] }),
+    )]);
+    let client = client();
+    let url = addon.manifest_url();
+
+    let (first, second) = tokio::join!(
+        client.streams(&url, "movie", "tt0816692"),
+        client.streams(&url, "movie", "tt0816692"),
+    );
+
+    first.unwrap();
+    second.unwrap();
+    assert_eq!(addon.request_count(), 1);
+}
+
+/// A WAT addon module serving `manifest()` and running `handle` as given,
+/// with `data` placed at offset 8192.
+fn wasm_addon(data: &str, handle: &str) -> String {
+    let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+    let manifest = json!({
+        "id": "org.example.sandboxed",
+        "version": "0.1.0",
+        "name": "Sandboxed",
+        "description": "Test fixture",
+        "types": ["movie"],
+        "resources": ["stream"],
+        "catalogs": []
+    })
+    .to_string();
+    format!(
+        r#"(module
+            (import "host" "log" (func $log (param i32 i32 i32)))
+            (import "host" "http_get" (func $http_get (param i32 i32) (result i64)))
+            (import "host" "kv_get" (func $kv_get (param i32 i32) (result i64)))
+            (import "host" "kv_set" (func $kv_set (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (global $next (mut i32) (i32.const 32768))
+            (func (export "alloc") (param $len i32) (result i32)
+                (global.get $next)
+                (global.set $next (i32.add (global.get $next) (local.get $len))))
+            (data (i32.const 0) "{manifest}")
+            (data (i32.const 8192) "{data}")
+            (func (export "manifest") (result i64) (i64.const {manifest_len}))
+            (func (export "handle") (param $ptr i32) (param $len i32) (result i64) {handle}))"#,
+        manifest = escape(&manifest),
+        manifest_len = manifest.len(),
+        data = escape(data),
+        handle = handle,
+    )
+}
+
+/// The packed range of `len` bytes of `data` in [`wasm_addon`].
+fn data_range(len: usize) -> String {
+    format!("(i64.const {})", (8192i64 << 32) | len as i64)
+}
+
+fn sandbox() -> SandboxRuntime {
+    SandboxRuntime::new(&crate::config::SandboxConfig {
+        fuel_per_call: 10_000_000,
+        memory_limit_mb: 2,
+        storage_quota_kb: 1,
+        http_timeout_ms: 5_000,
+    })
+    .unwrap()
+}
+
+#[tokio::test]
+async fn runs_sandboxed_addon() {
+    let streams = json!({ "streams": [{ "url": "https://cdn.example.com/a.mp4" }] }).to_string();
+    let mut runtime = sandbox();
+    let module = wasm_addon(&streams, &data_range(streams.len()));
+    runtime
+        .load("static", module.as_bytes(), Capabilities::default(), None)
+        .unwrap();
+    let addon = runtime.get("sandbox://static").unwrap();
+
+    let manifest = addon.manifest().await.unwrap();
+    assert!(validate(&manifest).valid);
+    let response = addon
+        .request(&ResourceRequest::new("stream", "movie", "tt0816692"))
+        .await
+        .unwrap();
+    assert_eq!(
+        response["streams"][0]["url"],
+        "https://cdn.example.com/a.mp4"
+    );
+}
+
+#[tokio::test]
+async fn stops_sandboxed_addon_at_fuel_and_memory_limits() {
+    let mut runtime = sandbox();
+    let spin = wasm_addon("", "(loop $spin (br $spin)) (i64.const 0)");
+    runtime
+        .load("spin", spin.as_bytes(), Capabilities::default(), None)
+        .unwrap();
+    // 100 pages is 6.4 MB, over the 2 MB limit.
+    let hog = wasm_addon(
+        "",
+        "(if (i32.eq (memory.grow (i32.const 100)) (i32.const -1)) (then unreachable)) (i64.const 0)",
+    );
+    runtime
+        .load("hog", hog.as_bytes(), Capabilities::default(), None)
+        .unwrap();
+    // Claims a 4 GiB response, far past the end of its memory.
+    let liar = wasm_addon("", "(i64.const 0xffffffff)");
+    runtime
+        .load("liar", liar.as_bytes(), Capabilities::default(), None)
+        .unwrap();
+    let request = ResourceRequest::new("stream", "movie", "tt0816692");
+
+    let spin = runtime.get("sandbox://spin").unwrap();
+    let error = spin.request(&request).await.unwrap_err();
+    assert!(format!("{:#}", error).contains("fuel"), "{:#}", error);
+
+    let hog = runtime.get("sandbox://hog").unwrap();
+    let error = hog.request(&request).await.unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("unreachable"),
+        "{:#}",
+        error
+    );
+
+    let liar = runtime.get("sandbox://liar").unwrap();
+    let error = liar.request(&request).await.unwrap_err();
+    assert!(format!("{:#}", error).contains("outside"), "{:#}", error);
+}
+
+#[tokio::test]
+async fn gates_sandboxed_http_by_host() {
+    let addon = MockAddon::start(vec![("/data.json", 200, json!({ "hello": "world" }))]);
+    let url = format!("{}/data.json", addon.base);
+    let module = wasm_addon(
+        &url,
+        &format!(
+            "(call $http_get (i32.const 8192) (i32.const {}))",
+            url.len()
+        ),
+    );
+    let mut runtime = sandbox();
+    let allowed = Capabilities {
+        http_hosts: vec!["127.0.0.1".to_string()],
+        ..Default::default()
+    };
+    runtime
+        .load("allowed", module.as_bytes(), allowed, None)
+        .unwrap();
+    runtime
+        .load("denied", module.as_bytes(), Capabilities::default(), None)
+        .unwrap();
+    let request = ResourceRequest::new("probe", "movie", "tt0816692");
+
+    let allowed = runtime.get("sandbox://allowed").unwrap();
+    let response = allowed.request(&request).await.unwrap();
+    assert_eq!(response["status"], 200);
+    assert_eq!(response["body"], r#"{"hello":"world"}"#);
+
+    let denied = runtime.get("sandbox://denied").unwrap();
+    let response = denied.request(&request).await.unwrap();
+    assert!(response["error"].as_str().unwrap().contains("not allowed"));
+    assert_eq!(addon.request_count(), 1);
+}
+
+#[tokio::test]
+async fn persists_sandboxed_storage_within_quota() {
+    let dir = std::env::temp_dir().join(format!("sandbox-kv-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let storage = dir.join("storage.json");
+    let _ = std::fs::remove_file(&storage);
+    // Stores the 11-byte value after the 3-byte key, then reads it back.
+    let module = wasm_addon(
+        r#"key{"ok":true}"#,
+        "(drop (call $kv_set (i32.const 8192) (i32.const 3) (i32.const 8195) (i32.const 11)))
+         (call $kv_get (i32.const 8192) (i32.const 3))",
+    );
+    let mut runtime = sandbox();
+    let capabilities = Capabilities {
+        storage: true,
+        ..Default::default()
+    };
+    runtime
+        .load("kv", module.as_bytes(), capabilities, Some(&storage))
+        .unwrap();
+
+    let addon = runtime.get("sandbox://kv").unwrap();
+    let response = addon
+        .request(&ResourceRequest::new("probe", "movie", "x"))
+        .await
+        .unwrap();
+
+    assert_eq!(response, json!({ "ok": true }));
+    let saved: Value = serde_json::from_str(&std::fs::read_to_string(&storage).unwrap()).unwrap();
+    assert_eq!(saved["key"], r#"{"ok":true}"#);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn grants_sandboxed_packages_only_what_the_user_granted() {
+    let dir = std::env::temp_dir().join(format!("sandbox-grants-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let module = wasm_addon(
+        r#"key{"ok":true}"#,
+        "(drop (call $kv_set (i32.const 8192) (i32.const 3) (i32.const 8195) (i32.const 11)))
+         (call $kv_get (i32.const 8192) (i32.const 3))",
+    );
+    for name in ["asks", "granted"] {
+        let package = dir.join(name);
+        std::fs::create_dir_all(&package).unwrap();
+        std::fs::write(package.join("addon.wasm"), &module).unwrap();
+        // Both packages ask for storage in their own file.
+        std::fs::write(package.join("capabilities.json"), r#"{"storage": true}"#).unwrap();
+    }
+    std::fs::write(dir.join("grants.json"), r#"{"granted": {"storage": true}}"#).unwrap();
+    let mut runtime = sandbox();
+    runtime.load_dir(&dir);
+    let request = ResourceRequest::new("probe", "movie", "x");
+
+    let asks = runtime.get("sandbox://asks").unwrap();
+    assert!(asks.request(&request).await.is_err());
+    assert!(!dir.join("asks/storage.json").exists());
+
+    let granted = runtime.get("sandbox://granted").unwrap();
+    assert_eq!(
+        granted.request(&request).await.unwrap(),
+        json!({ "ok": true })
+    );
+    assert!(dir.join("granted/storage.json").is_file());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Writes an executable process addon that answers with its pid and open
+/// file limit, and exits when asked for the id `crash`.
+#[cfg(unix)]
+fn process_addon(dir: &std::path::Path) -> String {
+    use std::os::unix::fs::PermissionsExt;
+
+    let script = format!(
+        r#"#!/bin/sh
+while read -r line; do
+  id=$(echo "$line" | sed 's/^{{"id":\([0-9]*\).*/\1/')
+  case "$line" in
+    *'"method":"manifest"'*) echo '{{"id":'$id',"result":{}}}' ;;
+    *'"id":"crash"'*) exit 1 ;;
+    *) echo '{{"id":'$id',"result":{{"streams":[{{"url":"https://cdn.example.com/'$$'.mp4","title":"'$(ulimit -n)'"}}]}}}}' ;;
+  esac
+done
+"#,
+        manifest()
+    );
+    let path = dir.join("addon.sh");
+    std::fs::write(&path, script).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    format!("process://{}", path.display())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn restarts_crashed_process_addon_and_stops_it() {
+    let dir = std::env::temp_dir().join(format!("process-addon-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let url = process_addon(&dir);
+    let runner = ProcessRunner::new(
+        &crate::config::ProcessConfig {
+            open_files: 64,
+            ..Default::default()
+        },
+        &dir,
+        Duration::from_secs(5),
+    );
+    let addon = runner.get(&url).unwrap();
+    let stream = |response: Value| response["streams"][0].clone();
+
+    assert!(validate(&addon.manifest().await.unwrap()).valid);
+    let request = ResourceRequest::new("stream", "movie", "tt0816692");
+    let first = stream(addon.request(&request).await.unwrap());
+    if cfg!(target_os = "linux") {
+        assert_eq!(first["title"], "64");
+    }
+
+    let crash = ResourceRequest::new("stream", "movie", "crash");
+    let error = addon.request(&crash).await.unwrap_err();
+    assert!(format!("{:#}", error).contains("exited"), "{:#}", error);
+    let second = stream(addon.request(&request).await.unwrap());
+    assert_ne!(first["url"], second["url"]);
+
+    runner.stop(&url);
+    let pid = second["url"]
+        .as_str()
+        .and_then(|url| url.strip_prefix("https://cdn.example.com/"))
+        .and_then(|url| url.strip_suffix(".mp4"))
+        .unwrap()
+        .to_string();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let alive = std::process::Command::new("kill")
+        .args(["-0", &pid])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!alive.success(), "process {} still running", pid);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn runs_only_process_addons_inside_their_directory() {
+    let (manager, dir) = manager("process-dir", AddonsConfig::default(), EventBus::default());
+    let allowed = dir.join("processes");
+    let outside = dir.join("elsewhere");
+    std::fs::create_dir_all(&allowed).unwrap();
+    std::fs::create_dir_all(&outside).unwrap();
+    let url = process_addon(&outside);
+    let escape = format!("process://{}/../elsewhere/addon.sh", allowed.display());
+    std::os::unix::fs::symlink(outside.join("addon.sh"), allowed.join("link.sh")).unwrap();
+    let link = format!("process://{}", allowed.join("link.sh").display());
+
+    for url in [&url, &escape, &link, &"process:///bin/sh".to_string()] {
+        let error = manager.install(url).await.unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("must be in"),
+            "{}: {:#}",
+            url,
+            error
+        );
+        assert!(manager.validate(url).await.is_err(), "{}", url);
+    }
+    assert!(manager.collection().list().is_empty());
+
+    let url = process_addon(&allowed);
+    manager.install(&url).await.unwrap();
+    manager.uninstall(&url).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn serves_fixtures_as_native_addon() {
+    let dir = std::env::temp_dir().join(format!("native-fixtures-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let catalog = json!([
+        { "id": "tt0816692", "type": "movie", "title": "Interstellar", "year": 2014 },
+        { "id": "tt1375666", "type": "movie", "title": "Inception", "year": 2010 },
+        { "id": "tt0903747", "type": "series", "title": "Breaking Bad", "year": 2008 }
+    ]);
+    std::fs::write(dir.join("catalog.json"), catalog.to_string()).unwrap();
+    let fixtures = Arc::new(FixtureProvider::new(&dir));
+    let addons = NativeAddons::new(vec![fixtures]);
+    let addon = addons.get("native://fixtures").unwrap();
+
+    let manifest = serde_json::to_value(addon.manifest()).unwrap();
+    let report = validate(&manifest);
+    assert!(report.valid, "{}", report);
+
+    let search = with_extra("catalog", "fixtures", &[("search", "incep")]);
+    let response = native::call(addon.as_ref(), &search).await.unwrap();
+    assert_eq!(response["metas"].as_array().unwrap().len(), 1);
+    assert_eq!(response["metas"][0]["name"], "Inception");
+    assert_eq!(response["metas"][0]["releaseInfo"], "2010");
+
+    let meta = ResourceRequest::new("meta", "series", "tt0903747");
+    let response = native::call(addon.as_ref(), &meta).await.unwrap();
+    assert_eq!(response["meta"]["name"], "Breaking Bad");
+
+    let stream = ResourceRequest::new("stream", "movie", "tt0816692");
+    assert!(native::call(addon.as_ref(), &stream).await.is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn serves_builtin_catalog_without_fixture_dir() {
+    let addons = NativeAddons::new(vec![Arc::new(FixtureProvider::builtin())]);
+    let addon = addons.get("native://fixtures").unwrap();
+
+    let catalog = ResourceRequest::new("catalog", "movie", "fixtures");
+    let response = native::call(addon.as_ref(), &catalog).await.unwrap();
+    assert_eq!(response["metas"].as_array().unwrap().len(), 3);
+
+    let meta = ResourceRequest::new("meta", "movie", "tt0816692");
+    let response = native::call(addon.as_ref(), &meta).await.unwrap();
+    assert_eq!(response["meta"]["name"], "Interstellar");
+}
+
+#[tokio::test]
+async fn serves_native_addons_over_http() {
+    let dir = std::env::temp_dir().join(format!("served-fixtures-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let catalog = json!([
+        { "id": "tt0816692", "type": "movie", "title": "Interstellar", "year": 2014 },
+        { "id": "tt1375666", "type": "movie", "title": "Inception", "year": 2010 }
+    ]);
+    std::fs::write(dir.join("catalog.json"), catalog.to_string()).unwrap();
+    let addons = NativeAddons::new(vec![Arc::new(FixtureProvider::new(&dir))]);
+    let listener = server::bind(0).await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(server::serve(listener, Arc::new(addons)));
+
+    let search = with_extra(
+        "catalog",
+        "fixtures",
+        &[("search", "incep tion&co"), ("skip", "0")],
+    );
+    assert_eq!(ResourceRequest::from_path(&search.path()), Some(search));
+
+    // The standard client installs and queries it like any HTTP addon.
+    let url = format!("{}/fixtures/manifest.json", base);
+    assert_eq!(client().manifest(&url).await.unwrap().id, "fixtures");
+    let search = with_extra("catalog", "fixtures", &[("search", "interstellar")]);
+    let response = client().checked(&url, &search).await.unwrap();
+    assert_eq!(response["metas"][0]["name"], "Interstellar");
+
+    let http = reqwest::Client::new();
+    let response = http.get(&url).send().await.unwrap();
+    assert_eq!(response.headers()["access-control-allow-origin"], "*");
+    let preflight = http
+        .request(
+            reqwest::Method::OPTIONS,
+            format!("{}/fixtures/meta/movie/tt1.json", base),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(preflight.status(), 204);
+    for missing in [
+        "/unknown/manifest.json",
+        "/fixtures/stream/movie/tt0816692.json",
+        "/fixtures/nonsense",
+    ] {
+        let response = http
+            .get(format!("{}{}", base, missing))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404, "{}", missing);
+    }
+    let index: Value = http.get(&base).send().await.unwrap().json().await.unwrap();
+    assert_eq!(index[0]["manifestPath"], "/fixtures/manifest.json");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn exports_and_imports_collections_without_secrets() {
+    let mut configurable = manifest();
+    configurable["id"] = json!("org.example.configured");
+    configurable["config"] = json!([
+        { "key": "apiKey", "type": "password" },
+        { "key": "quality", "type": "select", "options": ["720p", "1080p"] }
+    ]);
+    let mut configured = installed(configurable, true);
+    let values: Map<String, Value> =
+        serde_json::from_value(json!({ "apiKey": "s3cret", "quality": "720p" })).unwrap();
+    configured.transport_url =
+        settings::configured_url("http://configured.test/manifest.json", &values).unwrap();
+    configured.settings = Some(settings::AddonSettings {
+        transport_url: "http://configured.test/manifest.json".to_string(),
+        values,
+    });
+    let plain = installed(manifest(), false);
+    // Installed from a URL with the key in it, without the settings form.
+    let mut pasted = installed(manifest(), true);
+    pasted.manifest.id = "org.example.pasted".to_string();
+    pasted.transport_url = "http://pasted.test/addon/key%3Ds3cret/manifest.json".to_string();
+    let mut process = installed(manifest(), true);
+    process.transport_url = "process://addon --serve".to_string();
+    let collection = vec![configured, plain.clone(), pasted, process];
+
+    // A full export imports back as no change; process addons stay local.
+    let document = serde_json::to_string(&sharing::export(&collection, false)).unwrap();
+    let import = sharing::parse(&Value::String(document)).unwrap();
+    let diff = sharing::diff(&import, &collection);
+    assert_eq!((diff.unchanged.len(), diff.not_listed.len()), (3, 1));
+
+    let redacted = sharing::export(&collection, true);
+    let document = serde_json::to_value(&redacted).unwrap();
+    assert!(!document.to_string().contains("s3cret"));
+    let entry = &redacted.addons[0];
+    assert!(entry.redacted);
+    assert_eq!(entry.transport_url, "http://configured.test/manifest.json");
+    assert_eq!(entry.settings.as_ref().unwrap().values["quality"], "720p");
+    let entry = &redacted.addons[2];
+    assert!(entry.redacted);
+    assert_eq!(
+        entry.transport_url,
+        "http://pasted.test/addon/manifest.json"
+    );
+    let diff = sharing::diff(&sharing::parse(&document).unwrap(), &collection);
+    assert_eq!(diff.unchanged.len(), 3);
+
+    let link = sharing::share_url(&collection);
+    assert!(!link.contains("s3cret") && !link.contains("process"));
+    let import = sharing::parse(&Value::String(link)).unwrap();
+    assert_eq!(sharing::diff(&import, &collection).unchanged.len(), 3);
+    let diff = sharing::diff(&import, &[]);
+    assert_eq!(diff.added.len(), 3);
+    assert!(!diff.added[1].enabled);
+
+    let mut enabled = plain;
+    enabled.flags.enabled = true;
+    assert_eq!(sharing::diff(&import, &[enabled]).toggled.len(), 1);
+
+    let process_link = format!(
+        "{}?a={}",
+        sharing::SHARE_URL_PREFIX,
+        "process%3A%2F%2Frm+-rf"
+    );
+    assert!(sharing::parse(&Value::String(process_link)).is_err());
+}
+
+#[test]
+fn redacts_only_segments_that_read_as_settings() {
+    let pasted = |url: &str| {
+        let mut addon = installed(manifest(), true);
+        addon.manifest.behavior_hints.configurable = true;
+        addon.transport_url = url.to_string();
+        addon
+    };
+    let cases = [
+        // Hosted under a sub-path, not configured.
+        (
+            "https://host.test/stremio/addon/manifest.json",
+            "https://host.test/stremio/addon/manifest.json",
+            false,
+        ),
+        (
+            "https://host.test/providers=yts|sort=seeders/manifest.json",
+            "https://host.test/manifest.json",
+            true,
+        ),
+        (
+            "https://host.test/addon/%7B%22apiKey%22%3A%22s3cret%22%7D/manifest.json",
+            "https://host.test/addon/manifest.json",
+            true,
+        ),
+        // `{"apiKey":"s3cret"}` in base64
+        (
+            "https://host.test/eyJhcGlLZXkiOiJzM2NyZXQifQ/manifest.json",
+            "https://host.test/manifest.json",
+            true,
+        ),
+    ];
+    for (url, expected, redacted) in cases {
+        let collection = [pasted(url)];
+        let entry = &sharing::export(&collection, true).addons[0];
+        assert_eq!(entry.transport_url, expected, "{}", url);
+        assert_eq!(entry.redacted, redacted, "{}", url);
+        let link = sharing::share_url(&collection);
+        let import = sharing::parse(&Value::String(link)).unwrap();
+        assert_eq!(import.addons[0].transport_url, expected, "{}", url);
+    }
+}
+
+#[test]
+fn checks_settings_and_encodes_them_into_the_url() {
+    let mut manifest = manifest();
+    manifest["behaviorHints"]["configurationRequired"] = json!(true);
+    manifest["config"] = json!([
+        { "key": "apiKey", "type": "password", "title": "API key", "required": true },
+        { "key": "quality", "type": "select", "options": ["720p", "1080p"], "default": "1080p" },
+        { "key": "limit", "type": "number" },
+        { "key": "adult", "type": "checkbox", "default": "checked" }
+    ]);
+    let report = validate(&manifest);
+    assert!(report.valid, "{}", report);
+    let manifest: Manifest = serde_json::from_value(manifest).unwrap();
+
+    let form = settings::form("https://addon.example.com/manifest.json", &manifest, None);
+    assert!(form.configuration_required);
+    assert_eq!(
+        Value::Object(form.values),
+        json!({ "quality": "1080p", "adult": true })
+    );
+
+    let submitted = json!({ "quality": "4k", "limit": "many", "extra": 1 });
+    let issues = settings::check(&manifest.config, submitted.as_object().unwrap()).unwrap_err();
+    let mut paths: Vec<&str> = issues.iter().map(|issue| issue.path.as_str()).collect();
+    paths.sort();
+    assert_eq!(paths, ["apiKey", "extra", "limit", "quality"]);
+
+    let submitted = json!({ "apiKey": "s3cret/key", "limit": "20", "adult": false });
+    let values = settings::check(&manifest.config, submitted.as_object().unwrap()).unwrap();
+    assert_eq!(
+        Value::Object(values.clone()),
+        json!({ "apiKey": "s3cret/key", "quality": "1080p", "limit": 20, "adult": false })
+    );
+    let url = settings::configured_url("https://addon.example.com/manifest.json", &values).unwrap();
+    let encoded = url
+        .strip_prefix("https://addon.example.com/")
+        .and_then(|rest| rest.strip_suffix("/manifest.json"))
+        .unwrap();
+    assert!(!encoded.contains('/'));
+    let decoded = percent_encoding::percent_decode_str(encoded)
+        .decode_utf8()
+        .unwrap();
+    assert_eq!(
+        serde_json::from_str::<Value>(&decoded).unwrap(),
+        Value::Object(values)
+    );
+    assert!(settings::configured_url("sandbox://static", &Map::new()).is_err());
+}
+
+#[tokio::test]
+async fn lists_store_addons_from_signed_indexes() {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    let dir = std::env::temp_dir().join(format!("addon-store-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let listed = |id: &str, name: &str, version: &str, category: &str| {
+        let mut manifest = manifest();
+        manifest["id"] = json!(id);
+        manifest["name"] = json!(name);
+        manifest["version"] = json!(version);
+        json!({
+            "transportUrl": format!("https://{}.example.com/manifest.json", name.to_lowercase()),
+            "manifest": manifest,
+            "categories": [category]
+        })
+    };
+    let mut broken = listed("org.example.broken", "Broken", "1.0.0", "streams");
+    broken["manifest"]["version"] = json!("latest");
+    let index = json!({
+        "name": "Community",
+        "categories": [
+            { "id": "subtitles", "name": "Subtitles" },
+            { "id": "streams", "name": "Streams" }
+        ],
+        "addons": [
+            listed("org.example.subs", "Subs", "2.0.0", "subtitles"),
+            listed("org.example.torrents", "Torrents", "1.0.0", "streams"),
+            broken
+        ]
+    })
+    .to_string();
+    let write_repo = |name: &str, index: &str, signed: &str| {
+        let path = dir.join(name);
+        std::fs::write(&path, index).unwrap();
+        let signature = hex::encode(key.sign(signed.as_bytes()).to_bytes());
+        std::fs::write(dir.join(format!("{}.sig", name)), signature).unwrap();
+        path.to_string_lossy().to_string()
+    };
+    let good = write_repo("good.json", &index, &index);
+    let tampered = write_repo(
+        "tampered.json",
+        &index.replace("Torrents", "Malware"),
+        &index,
+    );
+    let public_key = hex::encode(key.verifying_key().to_bytes());
+    let repositories: Vec<_> = [tampered.clone(), good]
+        .into_iter()
+        .map(|url| crate::config::RepositoryConfig {
+            url,
+            public_key: public_key.clone(),
+        })
+        .collect();
+    let store = AddonStore::new(&repositories, Duration::from_secs(5)).unwrap();
+
+    let mut old_subs: Value = manifest();
+    old_subs["id"] = json!("org.example.subs");
+    old_subs["version"] = json!("1.5.0");
+    let collection = [installed(old_subs, true)];
+    let listing = store.browse(&StoreQuery::default(), &collection).await;
+    assert_eq!(listing.categories.len(), 2);
+    assert_eq!(listing.errors.len(), 1);
+    assert_eq!(listing.errors[0].url, tampered);
+    let ids: Vec<&str> = listing
+        .addons
+        .iter()
+        .map(|addon| addon.entry.manifest.id.as_str())
+        .collect();
+    assert_eq!(ids, ["org.example.subs", "org.example.torrents"]);
+    assert!(listing.addons[0].installed && listing.addons[0].update_available);
+    assert!(!listing.addons[1].installed);
+
+    let query = StoreQuery {
+        search: Some("torr".into()),
+        ..Default::default()
+    };
+    assert_eq!(store.browse(&query, &[]).await.addons.len(), 1);
+    let query = StoreQuery {
+        category: Some("subtitles".into()),
+        ..Default::default()
+    };
+    let listing = store.browse(&query, &[]).await;
+    assert_eq!(listing.addons.len(), 1);
+    assert_eq!(listing.addons[0].entry.repository, "Community");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn swaps_updated_manifests_and_rolls_back() {
+    let dir = std::env::temp_dir().join(format!("addon-updates-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let collection = AddonCollection::open(&dir.join("addons.json"), &[]);
+    let url = "https://addon.example.com/manifest.json";
+    let old: Manifest = serde_json::from_value(manifest()).unwrap();
+    collection.install(url, old.clone(), None).unwrap();
+
+    let mut new = manifest();
+    new["version"] = json!("1.3.0");
+    new["resources"][1]["idPrefixes"] = json!(["tt", "kitsu:"]);
+    new["catalogs"][0]["id"] = json!("popular");
+    let new: Manifest = serde_json::from_value(new).unwrap();
+    assert!(updates::diff(url, &old, &old).is_none());
+    let changes = updates::diff(url, &old, &new).unwrap();
+    assert!(changes.capabilities_changed);
+    assert_eq!(changes.catalogs.added, ["movie/popular"]);
+    assert_eq!(changes.catalogs.removed, ["movie/top"]);
+    assert_eq!(changes.id_prefixes.added, ["kitsu:"]);
+    assert!(changes.resources.added.is_empty());
+
+    let updated = collection.update_manifest(url, &old, new.clone()).unwrap();
+    assert_eq!(updated.unwrap().manifest.version, "1.3.0");
+    // Only swaps if the manifest is still the one the update was made from.
+    assert!(collection
+        .update_manifest(url, &old, new)
+        .unwrap()
+        .is_none());
+
+    let reopened = AddonCollection::open(&dir.join("addons.json"), &[]);
+    let rolled_back = reopened.rollback(url).unwrap();
+    assert_eq!(rolled_back.manifest.version, "1.2.0");
+    assert_eq!(rolled_back.skipped_version.as_deref(), Some("1.3.0"));
+    assert!(reopened.rollback(url).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn keeps_collection_unchanged_when_saving_fails() {
+    let dir = std::env::temp_dir().join(format!("addon-save-fails-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let file = dir.join("addons.json");
+    let collection = AddonCollection::open(&file, &[]);
+    let manifest_with_id = |id: &str| {
+        let mut manifest = manifest();
+        manifest["id"] = json!(id);
+        serde_json::from_value::<Manifest>(manifest).unwrap()
+    };
+    let (first, second) = (
+        "https://first.example.com/manifest.json",
+        "https://second.example.com/manifest.json",
+    );
+    collection
+        .install(first, manifest_with_id("org.example.first"), None)
+        .unwrap();
+    collection
+        .install(second, manifest_with_id("org.example.second"), None)
+        .unwrap();
+    let before = serde_json::to_value(collection.list()).unwrap();
+
+    // A directory where the temporary file goes makes every save fail.
+    std::fs::create_dir_all(file.with_extension("json.tmp")).unwrap();
+    let third = "https://third.example.com/manifest.json";
+    assert!(collection
+        .install(third, manifest_with_id("org.example.third"), None)
+        .is_err());
+    assert!(collection.uninstall(first).is_err());
+    assert!(collection
+        .reorder(&[second.to_string(), first.to_string()])
+        .is_err());
+    assert!(collection.set_enabled(first, false).is_err());
+    assert_eq!(serde_json::to_value(collection.list()).unwrap(), before);
+
+    std::fs::remove_dir(file.with_extension("json.tmp")).unwrap();
+    collection.set_enabled(first, false).unwrap();
+    assert!(!AddonCollection::open(&file, &[]).list()[0].flags.enabled);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn tracks_addon_health_and_trips_circuit_breaker() {
+    let url = "https://addon.example.com/manifest.json";
+    let breaker = |cooldown_secs| crate::config::CircuitBreakerConfig {
+        failure_threshold: 3,
+        cooldown_secs,
+    };
+    let tracker = HealthTracker::new(&breaker(60));
+    for ms in 1..=10 {
+        tracker.record(url, Duration::from_millis(ms * 10), None);
+    }
+    let health = tracker.health(url, "Mock");
+    let latency = health.latency_ms.unwrap();
+    assert_eq!((latency.p50, latency.p90, latency.p99), (50, 90, 100));
+    assert_eq!(health.circuit, CircuitState::Closed);
+
+    for _ in 0..3 {
+        assert!(tracker.allow(url));
+        tracker.record(url, Duration::from_millis(5), Some("HTTP 500".into()));
+    }
+    assert!(!tracker.allow(url));
+    let reason = tracker.blocked(url).unwrap();
+    assert!(reason.starts_with("Circuit open, retry in 6"), "{}", reason);
+    let health = tracker.health(url, "Mock");
+    assert_eq!(health.circuit, CircuitState::Open);
+    assert_eq!((health.requests, health.failures), (13, 3));
+    assert!((health.error_rate - 3.0 / 13.0).abs() < 1e-9);
+    assert_eq!(health.last_failure.unwrap().error, "HTTP 500");
+
+    // Once the cooldown is over, a successful trial request closes it.
+    let tracker = HealthTracker::new(&breaker(0));
+    for _ in 0..3 {
+        tracker.record(url, Duration::from_millis(5), Some("down".into()));
+    }
+    assert!(tracker.blocked(url).is_none());
+    assert!(tracker.allow(url));
+    assert_eq!(tracker.health(url, "Mock").circuit, CircuitState::HalfOpen);
+    tracker.record(url, Duration::from_millis(5), None);
+    assert_eq!(tracker.health(url, "Mock").circuit, CircuitState::Closed);
+}
+
+#[tokio::test]
+async fn routes_around_addons_with_open_circuit() {
+    let addon = MockAddon::start(vec![
+        ("/manifest.json", 200, manifest()),
+        ("/stream/movie/tt0816692.json", 500, json!({})),
+    ]);
+    let config = AddonsConfig {
+        circuit_breaker: CircuitBreakerConfig {
+            failure_threshold: 1,
+            cooldown_secs: 60,
+        },
+        ..Default::default()
+    };
+    let (manager, dir) = manager("circuit", config, EventBus::default());
+    manager.install(&addon.manifest_url()).await.unwrap();
+    let request = ResourceRequest::new("stream", "movie", "tt0816692");
+    assert_eq!(manager.route(&request).len(), 1);
+
+    assert!(manager.call(&addon.manifest_url(), &request).await.is_err());
+    assert!(manager.route(&request).is_empty());
+    let decision = &manager.who_handles(&request)[0];
+    assert!(!decision.selected);
+    assert!(
+        decision.reason.starts_with("Circuit open"),
+        "{}",
+        decision.reason
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn fans_out_and_gives_up_on_slow_addons() {
+    let mut slow_manifest = manifest();
+    slow_manifest["id"] = json!("org.example.slow");
+    let fast = MockAddon::start(vec![
+        ("/manifest.json", 200, manifest()),
+        (
+            "/stream/movie/tt0816692.json",
+            200,
+            json!({ "streams": [] }),
+        ),
+    ]);
+    let slow = MockAddon::start(vec![
+        ("/manifest.json", 200, slow_manifest),
+        (
+            "/stream/movie/tt0816692.json",
+            200,
+            json!({ "streams": [] }),
+        ),
+    ]);
+    let events = EventBus::default();
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sink = sent.clone();
+    events.attach(move |message| {
+        sink.lock()
+            .unwrap()
+            .push(serde_json::from_str::<Value>(&message).unwrap())
+    });
+    let config = AddonsConfig {
+        fan_out_timeout_ms: 300,
+        ..Default::default()
+    };
+    let (manager, dir) = manager("fan-out", config, events);
+    manager.install(&slow.manifest_url()).await.unwrap();
+    manager.install(&fast.manifest_url()).await.unwrap();
+    slow.delay(Duration::from_secs(2));
+
+    let request = ResourceRequest::new("stream", "movie", "tt0816692");
+    let summary = manager.fan_out("r1", &request).await;
+    assert_eq!(summary.succeeded, vec![fast.manifest_url()]);
+    assert_eq!(summary.failed.len(), 1);
+    assert!(summary.elapsed_ms < 2000);
+
+    let sent = sent.lock().unwrap();
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0]["event"], "addonResult");
+    assert_eq!(sent[0]["data"]["requestId"], "r1");
+    assert_eq!(sent[0]["data"]["transportUrl"], fast.manifest_url());
+    assert_eq!(sent[1]["event"], "addonResultsDone");
+    let failed = &sent[1]["data"]["failed"][0];
+    assert_eq!(failed["transportUrl"], slow.manifest_url());
+    assert!(failed["error"].as_str().unwrap().starts_with("Timed out"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn persists_collection_and_guards_builtin_addons() {
+    let dir = std::env::temp_dir().join(format!("addon-collection-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let file = dir.join("addons.json");
+    let manifest_with_id = |id: &str| {
+        let mut manifest = manifest();
+        manifest["id"] = json!(id);
+        serde_json::from_value::<Manifest>(manifest).unwrap()
+    };
+    let (builtin, other) = (
+        "https://builtin.example.com/manifest.json",
+        "https://other.example.com/manifest.json",
+    );
+    let collection = AddonCollection::open(&file, &[builtin.to_string()]);
+    assert_eq!(collection.missing_protected(), vec![builtin.to_string()]);
+    collection
+        .install(builtin, manifest_with_id("org.example.builtin"), None)
+        .unwrap();
+    // Base URLs are normalized to the manifest URL.
+    collection
+        .install(
+            "https://other.example.com",
+            manifest_with_id("org.example.other"),
+            None,
+        )
+        .unwrap();
+    collection.set_enabled(other, false).unwrap();
+
+    let error = collection.uninstall(builtin).unwrap_err();
+    assert!(error.to_string().contains("built-in"), "{}", error);
+    assert!(collection.reorder(&[other.to_string()]).is_err());
+    assert!(collection
+        .reorder(&[other.to_string(), other.to_string()])
+        .is_err());
+    collection
+        .reorder(&[other.to_string(), builtin.to_string()])
+        .unwrap();
+
+    // The same addon at another URL, e.g. configured again, replaces it in
+    // place.
+    let moved = "https://moved.example.com/manifest.json";
+    collection
+        .install(moved, manifest_with_id("org.example.other"), None)
+        .unwrap();
+
+    let reopened = AddonCollection::open(&file, &[builtin.to_string()]);
+    let addons = reopened.list();
+    let urls: Vec<&str> = addons.iter().map(|a| a.transport_url.as_str()).collect();
+    assert_eq!(urls, vec![moved, builtin]);
+    assert!(!addons[0].flags.enabled);
+    assert!(addons[1].flags.protected);
+    assert!(reopened.missing_protected().is_empty());
+    assert!(reopened.uninstall(builtin).is_err());
+    reopened.uninstall(moved).unwrap();
+    assert!(reopened.uninstall(moved).is_err());
+    assert_eq!(AddonCollection::open(&file, &[]).list().len(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,98 @@
+//! What changed between an installed addon's manifest and the one it
+//! serves now, for telling the UI about new or dropped capabilities.
+
+use serde::Serialize;
+
+use super::manifest::{Manifest, ManifestResource};
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ListChange {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl ListChange {
+    fn between(old: Vec<String>, new: Vec<String>) -> Self {
+        Self {
+            added: new.iter().filter(|v| !old.contains(v)).cloned().collect(),
+            removed: old.iter().filter(|v| !new.contains(v)).cloned().collect(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestChanges {
+    pub transport_url: String,
+    pub name: String,
+    pub previous_version: String,
+    pub version: String,
+    pub resources: ListChange,
+    pub types: ListChange,
+    /// Catalogs as `<type>/<id>`.
+    pub catalogs: ListChange,
+    pub id_prefixes: ListChange,
+    /// Whether any of the lists above changed, as opposed to only e.g. the
+    /// description.
+    pub capabilities_changed: bool,
+}
+
+/// The changes from `old` to `new`, or `None` if they are the same.
+pub fn diff(transport_url: &str, old: &Manifest, new: &Manifest) -> Option<ManifestChanges> {
+    if same(old, new) {
+        return None;
+    }
+    let resources = |m: &Manifest| m.resources.iter().map(|r| r.name().to_string()).collect();
+    let catalogs = |m: &Manifest| {
+        m.catalogs
+            .iter()
+            .map(|c| format!("{}/{}", c.media_type, c.id))
+            .collect()
+    };
+    let id_prefixes = |m: &Manifest| {
+        let mut prefixes = m.id_prefixes.clone().unwrap_or_default();
+        for resource in &m.resources {
+            if let ManifestResource::Full {
+                id_prefixes: Some(own),
+                ..
+            } = resource
+            {
+                for prefix in own {
+                    if !prefixes.contains(prefix) {
+                        prefixes.push(prefix.clone());
+                    }
+                }
+            }
+        }
+        prefixes
+    };
+
+    let mut changes = ManifestChanges {
+        transport_url: transport_url.to_string(),
+        name: new.name.clone(),
+        previous_version: old.version.clone(),
+        version: new.version.clone(),
+        resources: ListChange::between(resources(old), resources(new)),
+        types: ListChange::between(old.types.clone(), new.types.clone()),
+        catalogs: ListChange::between(catalogs(old), catalogs(new)),
+        id_prefixes: ListChange::between(id_prefixes(old), id_prefixes(new)),
+        capabilities_changed: false,
+    };
+    changes.capabilities_changed = ![
+        &changes.resources,
+        &changes.types,
+        &changes.catalogs,
+        &changes.id_prefixes,
+    ]
+    .iter()
+    .all(|change| change.is_empty());
+    Some(changes)
+}
+
+pub fn same(a: &Manifest, b: &Manifest) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
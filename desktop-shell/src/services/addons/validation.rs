@@ -0,0 +1,460 @@
+use std::{collections::HashSet, fmt, sync::LazyLock};
+
+use anyhow::{bail, Result};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use super::manifest::Manifest;
+
+/// Reverse-domain style ids, e.g. `org.stremio.opensubtitles`.
+static ADDON_ID: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[A-Za-z0-9]+(?:[._-][A-Za-z0-9]+)*$").unwrap());
+
+const KNOWN_RESOURCES: &[&str] = &["catalog", "meta", "stream", "subtitles", "addon_catalog"];
+const BEHAVIOR_HINTS: &[&str] = &["adult", "p2p", "configurable", "configurationRequired"];
+const CONFIG_TYPES: &[&str] = &["text", "number", "password", "checkbox", "select"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Severity {
+    /// The addon cannot be installed.
+    Error,
+    /// Installable, but likely to misbehave.
+    Warning,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Issue {
+    pub severity: Severity,
+    /// JSON path of the offending field, e.g. `catalogs[0].extra[1].name`.
+    pub path: String,
+    pub message: String,
+}
+
+/// Everything wrong with a manifest, for the addon store UI to show.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationReport {
+    pub valid: bool,
+    pub issues: Vec<Issue>,
+}
+
+impl ValidationReport {
+    pub fn errors(&self) -> impl Iterator<Item = &Issue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Error)
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors: Vec<String> = self
+            .errors()
+            .map(|issue| format!("{}: {}", issue.path, issue.message))
+            .collect();
+        write!(f, "{}", errors.join("; "))
+    }
+}
+
+/// Checks a manifest as served, before it is deserialized, so that every
+/// problem is reported at once instead of the first serde error.
+pub fn validate(manifest: &Value) -> ValidationReport {
+    let mut validator = Validator::default();
+    match manifest.as_object() {
+        Some(manifest) => validator.manifest(manifest),
+        None => validator.error("", "Manifest must be a JSON object"),
+    }
+    ValidationReport {
+        valid: !validator
+            .issues
+            .iter()
+            .any(|i| i.severity == Severity::Error),
+        issues: validator.issues,
+    }
+}
+
+/// Validates a manifest and parses it, failing with the validation errors
+/// if it is not installable.
+pub fn parse_manifest(transport_url: &str, raw: Value) -> Result<Manifest> {
+    let report = validate(&raw);
+    if !report.valid {
+        bail!("Invalid addon manifest at {}: {}", transport_url, report);
+    }
+    Ok(serde_json::from_value(raw)?)
+}
+
+#[derive(Default)]
+struct Validator {
+    issues: Vec<Issue>,
+}
+
+impl Validator {
+    fn manifest(&mut self, manifest: &Map<String, Value>) {
+        if let Some(id) = self.required_string(manifest, "", "id") {
+            if !ADDON_ID.is_match(id) {
+                self.error(
+                    "id",
+                    "Must contain only letters, digits and single '.', '_' or '-' separators",
+                );
+            }
+        }
+        if let Some(version) = self.required_string(manifest, "", "version") {
+            if let Err(e) = semver::Version::parse(version) {
+                self.error("version", &format!("Not a semantic version: {}", e));
+            }
+        }
+        self.required_string(manifest, "", "name");
+        match manifest.get("description") {
+            None => self.warning("description", "Missing description"),
+            Some(value) => {
+                self.string(value, "description");
+            }
+        }
+        for field in ["logo", "background"] {
+            if let Some(value) = manifest.get(field) {
+                if let Some(url) = self.string(value, field) {
+                    if !["https://", "http://", "data:"]
+                        .iter()
+                        .any(|scheme| url.starts_with(scheme))
+                    {
+                        self.warning(field, "Not an http(s) or data URL");
+                    }
+                }
+            }
+        }
+
+        let types = match manifest.get("types") {
+            Some(types) => self.string_list(types, "types", true),
+            None => {
+                self.error("types", "Missing required field");
+                Vec::new()
+            }
+        };
+        if let Some(prefixes) = manifest.get("idPrefixes") {
+            self.string_list(prefixes, "idPrefixes", false);
+        }
+
+        let resources = match manifest.get("resources") {
+            Some(resources) => self.resources(resources),
+            None => {
+                self.error("resources", "Missing required field");
+                Vec::new()
+            }
+        };
+        let catalogs = match manifest.get("catalogs") {
+            Some(catalogs) => self.catalogs(catalogs, &types),
+            None => {
+                self.error("catalogs", "Missing required field");
+                0
+            }
+        };
+        let has_catalog = resources.iter().any(|name| name == "catalog");
+        if has_catalog && catalogs == 0 {
+            self.warning(
+                "catalogs",
+                "The catalog resource is declared but no catalogs are listed",
+            );
+        } else if !has_catalog && catalogs > 0 {
+            self.warning(
+                "resources",
+                "Catalogs are listed but the catalog resource is not declared",
+            );
+        }
+
+        if let Some(hints) = manifest.get("behaviorHints") {
+            self.behavior_hints(hints);
+        }
+        if let Some(config) = manifest.get("config") {
+            self.config(config);
+        }
+    }
+
+    /// Validates `resources` and returns the declared resource names.
+    fn resources(&mut self, resources: &Value) -> Vec<String> {
+        let Some(resources) = resources.as_array() else {
+            self.error("resources", "Must be an array");
+            return Vec::new();
+        };
+        if resources.is_empty() {
+            self.error("resources", "Must declare at least one resource");
+        }
+
+        let mut names = Vec::new();
+        for (index, resource) in resources.iter().enumerate() {
+            let path = format!("resources[{}]", index);
+            let name = match resource {
+                Value::String(name) => Some(name.as_str()),
+                Value::Object(resource) => {
+                    for field in ["types", "idPrefixes"] {
+                        if let Some(list) = resource.get(field) {
+                            self.string_list(list, &format!("{}.{}", path, field), false);
+                        }
+                    }
+                    self.required_string(resource, &path, "name")
+                }
+                _ => {
+                    self.error(&path, "Must be a resource name or an object with a name");
+                    None
+                }
+            };
+            let Some(name) = name else {
+                continue;
+            };
+            if name.is_empty() {
+                self.error(&path, "Resource name is empty");
+                continue;
+            }
+            if !KNOWN_RESOURCES.contains(&name) {
+                self.warning(&path, &format!("Unknown resource {:?}", name));
+            }
+            if names.iter().any(|seen| seen == name) {
+                self.error(&path, &format!("Resource {:?} is declared twice", name));
+            }
+            names.push(name.to_string());
+        }
+        names
+    }
+
+    /// Validates `catalogs` and returns how many there are.
+    fn catalogs(&mut self, catalogs: &Value, types: &[String]) -> usize {
+        let Some(catalogs) = catalogs.as_array() else {
+            self.error("catalogs", "Must be an array");
+            return 0;
+        };
+
+        let mut seen = HashSet::new();
+        for (index, catalog) in catalogs.iter().enumerate() {
+            let path = format!("catalogs[{}]", index);
+            let Some(catalog) = catalog.as_object() else {
+                self.error(&path, "Must be an object");
+                continue;
+            };
+            let media_type = self.required_string(catalog, &path, "type");
+            let id = self.required_string(catalog, &path, "id");
+            if let Some(media_type) = media_type {
+                if !types.is_empty() && !types.iter().any(|t| t == media_type) {
+                    self.warning(
+                        &format!("{}.type", path),
+                        &format!(
+                            "Type {:?} is not listed in the manifest's types",
+                            media_type
+                        ),
+                    );
+                }
+            }
+            if let (Some(media_type), Some(id)) = (media_type, id) {
+                if !seen.insert((media_type, id)) {
+                    self.error(
+                        &path,
+                        &format!("Duplicate catalog {:?} of type {:?}", id, media_type),
+                    );
+                }
+            }
+            if let Some(name) = catalog.get("name") {
+                self.string(name, &format!("{}.name", path));
+            }
+            if let Some(extra) = catalog.get("extra") {
+                self.extra(extra, &format!("{}.extra", path));
+            }
+
+            let supported = catalog
+                .get("extraSupported")
+                .map(|list| self.string_list(list, &format!("{}.extraSupported", path), false));
+            let required = catalog
+                .get("extraRequired")
+                .map(|list| self.string_list(list, &format!("{}.extraRequired", path), false));
+            if let Some(required) = required {
+                let supported = supported.unwrap_or_default();
+                for name in required.iter().filter(|name| !supported.contains(name)) {
+                    self.error(
+                        &format!("{}.extraRequired", path),
+                        &format!("{:?} is required but not in extraSupported", name),
+                    );
+                }
+            }
+        }
+        catalogs.len()
+    }
+
+    fn extra(&mut self, extra: &Value, path: &str) {
+        let Some(extra) = extra.as_array() else {
+            self.error(path, "Must be an array");
+            return;
+        };
+
+        let mut names = HashSet::new();
+        for (index, property) in extra.iter().enumerate() {
+            let path = format!("{}[{}]", path, index);
+            let Some(property) = property.as_object() else {
+                self.error(&path, "Must be an object");
+                continue;
+            };
+            if let Some(name) = self.required_string(property, &path, "name") {
+                if !names.insert(name) {
+                    self.error(
+                        &path,
+                        &format!("Extra property {:?} is defined twice", name),
+                    );
+                }
+            }
+            if let Some(required) = property.get("isRequired") {
+                if !required.is_boolean() {
+                    self.error(&format!("{}.isRequired", path), "Must be a boolean");
+                }
+            }
+            if let Some(options) = property.get("options") {
+                self.string_list(options, &format!("{}.options", path), false);
+            }
+            if let Some(limit) = property.get("optionsLimit") {
+                if limit.as_u64().is_none_or(|limit| limit == 0) {
+                    self.error(
+                        &format!("{}.optionsLimit", path),
+                        "Must be a positive integer",
+                    );
+                }
+            }
+        }
+    }
+
+    fn behavior_hints(&mut self, hints: &Value) {
+        let Some(hints) = hints.as_object() else {
+            self.error("behaviorHints", "Must be an object");
+            return;
+        };
+        for name in BEHAVIOR_HINTS {
+            if let Some(value) = hints.get(*name) {
+                if !value.is_boolean() {
+                    self.error(&format!("behaviorHints.{}", name), "Must be a boolean");
+                }
+            }
+        }
+        let flag = |name: &str| hints.get(name).and_then(Value::as_bool).unwrap_or(false);
+        if flag("configurationRequired") && !flag("configurable") {
+            self.warning(
+                "behaviorHints.configurationRequired",
+                "Configuration is required but the addon is not configurable",
+            );
+        }
+    }
+
+    fn config(&mut self, config: &Value) {
+        let Some(fields) = config.as_array() else {
+            self.error("config", "Must be an array");
+            return;
+        };
+
+        let mut keys = HashSet::new();
+        for (index, field) in fields.iter().enumerate() {
+            let path = format!("config[{}]", index);
+            let Some(field) = field.as_object() else {
+                self.error(&path, "Must be an object");
+                continue;
+            };
+            if let Some(key) = self.required_string(field, &path, "key") {
+                if !keys.insert(key) {
+                    self.error(&path, &format!("Setting {:?} is defined twice", key));
+                }
+            }
+            let field_type = self.required_string(field, &path, "type");
+            if let Some(field_type) = field_type {
+                if !CONFIG_TYPES.contains(&field_type) {
+                    self.error(
+                        &format!("{}.type", path),
+                        &format!("Unknown setting type {:?}", field_type),
+                    );
+                }
+            }
+            if let Some(title) = field.get("title") {
+                self.string(title, &format!("{}.title", path));
+            }
+            if let Some(required) = field.get("required") {
+                if !required.is_boolean() {
+                    self.error(&format!("{}.required", path), "Must be a boolean");
+                }
+            }
+            let options = field
+                .get("options")
+                .map(|options| self.string_list(options, &format!("{}.options", path), false))
+                .unwrap_or_default();
+            if field_type == Some("select") && options.is_empty() {
+                self.error(&path, "A select setting must list its options");
+            }
+        }
+    }
+
+    /// A required, non-empty string field of `object`.
+    fn required_string<'a>(
+        &mut self,
+        object: &'a Map<String, Value>,
+        parent: &str,
+        field: &str,
+    ) -> Option<&'a str> {
+        let path = if parent.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", parent, field)
+        };
+        match object.get(field) {
+            None => {
+                self.error(&path, "Missing required field");
+                None
+            }
+            Some(value) => match self.string(value, &path) {
+                Some("") => {
+                    self.error(&path, "Must not be empty");
+                    None
+                }
+                value => value,
+            },
+        }
+    }
+
+    fn string<'a>(&mut self, value: &'a Value, path: &str) -> Option<&'a str> {
+        let string = value.as_str();
+        if string.is_none() {
+            self.error(path, "Must be a string");
+        }
+        string
+    }
+
+    /// An array of non-empty strings; `non_empty` also rejects an empty array.
+    fn string_list(&mut self, value: &Value, path: &str, non_empty: bool) -> Vec<String> {
+        let Some(items) = value.as_array() else {
+            self.error(path, "Must be an array of strings");
+            return Vec::new();
+        };
+        if non_empty && items.is_empty() {
+            self.error(path, "Must not be empty");
+        }
+        let mut strings = Vec::new();
+        for (index, item) in items.iter().enumerate() {
+            match item.as_str() {
+                Some(item) if !item.is_empty() => strings.push(item.to_string()),
+                _ => self.error(
+                    &format!("{}[{}]", path, index),
+                    "Must be a non-empty string",
+                ),
+            }
+        }
+        strings
+    }
+
+    fn error(&mut self, path: &str, message: &str) {
+        self.issue(Severity::Error, path, message);
+    }
+
+    fn warning(&mut self, path: &str, message: &str) {
+        self.issue(Severity::Warning, path, message);
+    }
+
+    fn issue(&mut self, severity: Severity, path: &str, message: &str) {
+        self.issues.push(Issue {
+            severity,
+            path: path.to_string(),
+            message: message.to_string(),
+        });
+    }
+}
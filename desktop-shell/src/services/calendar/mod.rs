@@ -0,0 +1,265 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Days, Local, Months, NaiveDate, Utc};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use super::events::EventBus;
+use super::library::LibraryService;
+use super::metadata::{aggregator::MetadataAggregator, ids::IdResolver, MediaItem};
+use crate::config::CalendarConfig;
+
+#[cfg(test)]
+mod tests;
+
+pub const NEW_EPISODE_EVENT: &str = "newEpisode";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CalendarView {
+    #[default]
+    Month,
+    /// Monday to Sunday.
+    Week,
+}
+
+/// Arguments accepted by `getCalendar`. Every field is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CalendarArgs {
+    pub view: CalendarView,
+    /// Any day inside the period to show; defaults to today.
+    pub date: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CalendarEpisode {
+    pub series_id: String,
+    pub series_title: String,
+    pub poster: String,
+    pub video_id: String,
+    pub title: String,
+    pub season: Option<u32>,
+    pub episode: Option<u32>,
+    pub released: DateTime<Utc>,
+    pub aired: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CalendarDay {
+    pub date: NaiveDate,
+    pub episodes: Vec<CalendarEpisode>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Calendar {
+    pub view: CalendarView,
+    pub start: NaiveDate,
+    /// Last day of the period, inclusive.
+    pub end: NaiveDate,
+    /// Only days with at least one episode, in order.
+    pub days: Vec<CalendarDay>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CheckState {
+    last_checked: Option<DateTime<Utc>>,
+}
+
+/// Builds episode calendars for the series in the local library and
+/// announces episodes as they air.
+pub struct CalendarService {
+    config: CalendarConfig,
+    metadata: Arc<MetadataAggregator>,
+    ids: Arc<IdResolver>,
+    library: Arc<LibraryService>,
+    events: EventBus,
+    state_file: PathBuf,
+}
+
+impl CalendarService {
+    pub fn new(
+        config: CalendarConfig,
+        metadata: Arc<MetadataAggregator>,
+        ids: Arc<IdResolver>,
+        library: Arc<LibraryService>,
+        events: EventBus,
+        state_file: &Path,
+    ) -> Self {
+        Self {
+            config,
+            metadata,
+            ids,
+            library,
+            events,
+            state_file: state_file.to_path_buf(),
+        }
+    }
+
+    pub async fn calendar(&self, args: &CalendarArgs) -> Calendar {
+        let today = Local::now().date_naive();
+        let (start, end) = period(args.view, args.date.unwrap_or(today));
+
+        let mut days: BTreeMap<NaiveDate, Vec<CalendarEpisode>> = BTreeMap::new();
+        for episode in self.episodes().await {
+            let date = episode.released.with_timezone(&Local).date_naive();
+            if (start..=end).contains(&date) {
+                days.entry(date).or_default().push(episode);
+            }
+        }
+
+        Calendar {
+            view: args.view,
+            start,
+            end,
+            days: days
+                .into_iter()
+                .map(|(date, mut episodes)| {
+                    episodes.sort_by_key(|e| e.released);
+                    CalendarDay { date, episodes }
+                })
+                .collect(),
+        }
+    }
+
+    /// Periodically raises `newEpisode` for episodes that aired since the
+    /// previous check. The first run only records the time, so installing
+    /// does not announce a series' whole back catalog.
+    pub fn spawn_checks(self: &Arc<Self>, runtime: &tokio::runtime::Runtime) {
+        if !self.config.notify {
+            return;
+        }
+        let calendar = Arc::downgrade(self);
+        let period = Duration::from_secs(self.config.check_interval_mins.max(1) * 60);
+        runtime.spawn(async move {
+            // Wait a full period first so the library scan has finished.
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                let Some(calendar) = calendar.upgrade() else {
+                    return;
+                };
+                if let Err(e) = calendar.check().await {
+                    warn!("New episode check failed: {}", e);
+                }
+            }
+        });
+    }
+
+    async fn check(&self) -> Result<()> {
+        let now = Utc::now();
+        let state = self.load_state();
+        if let Some(since) = state.last_checked {
+            let aired: Vec<CalendarEpisode> = self
+                .episodes()
+                .await
+                .into_iter()
+                .filter(|e| e.released > since && e.released <= now)
+                .collect();
+            debug!("{} episodes aired since {}", aired.len(), since);
+            for episode in aired {
+                info!(
+                    "New episode of {}: {}",
+                    episode.series_title, episode.video_id
+                );
+                self.events.emit(NEW_EPISODE_EVENT, &episode);
+            }
+        }
+        self.save_state(&CheckState {
+            last_checked: Some(now),
+        })
+    }
+
+    /// Every dated episode of every matched series in the library.
+    async fn episodes(&self) -> Vec<CalendarEpisode> {
+        let mut series: Vec<MediaItem> = self
+            .library
+            .catalog()
+            .into_iter()
+            .filter(|item| item.media_type == "series" && !item.id.starts_with("local:"))
+            .collect();
+        // Shows matched by NFO may carry TMDB or TVDB ids.
+        let canonical = futures::future::join_all(
+            series
+                .iter()
+                .map(|s| self.ids.canonical(&s.id, Some("series"))),
+        )
+        .await;
+        for (item, id) in series.iter_mut().zip(canonical) {
+            item.id = id;
+        }
+        let mut seen = HashSet::new();
+        series.retain(|item| seen.insert(item.id.clone()));
+        let metas =
+            futures::future::join_all(series.iter().map(|s| self.metadata.meta(&s.id))).await;
+
+        let now = Utc::now();
+        let mut episodes = Vec::new();
+        for (series, meta) in series.iter().zip(metas) {
+            let Some(meta) = meta else {
+                continue;
+            };
+            for video in meta.videos {
+                let Some(released) = video.released else {
+                    continue;
+                };
+                episodes.push(CalendarEpisode {
+                    series_id: series.id.clone(),
+                    series_title: meta.title.clone(),
+                    poster: meta.poster.clone(),
+                    video_id: video.id,
+                    title: video.title,
+                    season: video.season,
+                    episode: video.episode,
+                    released,
+                    aired: released <= now,
+                });
+            }
+        }
+        episodes
+    }
+
+    fn load_state(&self) -> CheckState {
+        std::fs::read_to_string(&self.state_file)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save_state(&self, state: &CheckState) -> Result<()> {
+        if let Some(dir) = self.state_file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.state_file, serde_json::to_string(state)?)?;
+        Ok(())
+    }
+}
+
+/// First and last day of the month or week containing `date`.
+fn period(view: CalendarView, date: NaiveDate) -> (NaiveDate, NaiveDate) {
+    match view {
+        CalendarView::Month => {
+            let start = date.with_day(1).unwrap_or(date);
+            let end = start
+                .checked_add_months(Months::new(1))
+                .and_then(|next| next.pred_opt())
+                .unwrap_or(date);
+            (start, end)
+        }
+        CalendarView::Week => {
+            let start = date - Days::new(date.weekday().num_days_from_monday().into());
+            (start, start + Days::new(6))
+        }
+    }
+}
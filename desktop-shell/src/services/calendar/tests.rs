@@ -0,0 +1,184 @@
+//! Calendar periods, and new episode checks over a library matched by NFO.
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use serde_json::Value;
+
+use super::{period, CalendarService, CalendarView, CheckState, NEW_EPISODE_EVENT};
+use crate::config::{CalendarConfig, LibraryConfig, MetadataConfig};
+use crate::services::events::EventBus;
+use crate::services::library::{
+    nfo::{self, NfoKind},
+    LibraryService,
+};
+use crate::services::metadata::{
+    aggregator::MetadataAggregator, ids::IdResolver, MediaItem, MetadataProvider, Video,
+};
+
+fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+#[test]
+fn spans_whole_months() {
+    let cases = [
+        (date(2024, 2, 15), (date(2024, 2, 1), date(2024, 2, 29))),
+        (date(2023, 2, 1), (date(2023, 2, 1), date(2023, 2, 28))),
+        (date(2024, 1, 31), (date(2024, 1, 1), date(2024, 1, 31))),
+        (date(2023, 12, 31), (date(2023, 12, 1), date(2023, 12, 31))),
+    ];
+    for (day, expected) in cases {
+        assert_eq!(period(CalendarView::Month, day), expected, "{}", day);
+    }
+}
+
+#[test]
+fn starts_weeks_on_monday() {
+    let cases = [
+        // A Monday starts its own week, and the Sunday after ends it.
+        (date(2024, 1, 1), (date(2024, 1, 1), date(2024, 1, 7))),
+        (date(2024, 1, 7), (date(2024, 1, 1), date(2024, 1, 7))),
+        (date(2023, 12, 31), (date(2023, 12, 25), date(2023, 12, 31))),
+        (date(2024, 2, 29), (date(2024, 2, 26), date(2024, 3, 3))),
+    ];
+    for (day, expected) in cases {
+        assert_eq!(period(CalendarView::Week, day), expected, "{}", day);
+    }
+}
+
+/// Lists one series whose episodes are released at the given times.
+struct StubProvider {
+    series: MediaItem,
+}
+
+#[async_trait]
+impl MetadataProvider for StubProvider {
+    fn id(&self) -> &str {
+        "stub"
+    }
+
+    async fn catalog(&self) -> Result<Vec<MediaItem>> {
+        Ok(vec![self.series.clone()])
+    }
+
+    async fn meta(&self, id: &str) -> Result<Option<MediaItem>> {
+        Ok((id == self.series.id).then(|| self.series.clone()))
+    }
+}
+
+/// A calendar over a library holding one episode of a show its
+/// `tvshow.nfo` matches, with the events it raises.
+fn calendar(
+    name: &str,
+    released: &[DateTime<Utc>],
+) -> (CalendarService, Arc<Mutex<Vec<Value>>>, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("calendar-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let show = dir.join("Breaking Bad");
+    std::fs::create_dir_all(show.join("Season 1")).unwrap();
+    std::fs::write(show.join("Season 1/Breaking.Bad.S01E01.mkv"), b"").unwrap();
+
+    let series = MediaItem {
+        id: "tt0903747".into(),
+        media_type: "series".into(),
+        title: "Breaking Bad".into(),
+        videos: released
+            .iter()
+            .zip(1..)
+            .map(|(released, episode)| Video {
+                id: format!("tt0903747:1:{}", episode),
+                season: Some(1),
+                episode: Some(episode),
+                released: Some(*released),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+    nfo::write(&show.join("tvshow.nfo"), NfoKind::TvShow, &series).unwrap();
+
+    let mut metadata = MetadataAggregator::new(
+        MetadataConfig {
+            provider_priority: vec!["stub".into()],
+            ..Default::default()
+        },
+        None,
+    );
+    metadata.register(Arc::new(StubProvider { series }));
+    let metadata = Arc::new(metadata);
+    let events = EventBus::default();
+    let library = LibraryService::new(
+        LibraryConfig {
+            folders: vec![dir.clone()],
+            watch: false,
+            ..Default::default()
+        },
+        metadata.clone(),
+        None,
+        events.clone(),
+        tokio::runtime::Handle::current(),
+    );
+    library.scan().unwrap();
+
+    // Attached after the scan so only the calendar's events are kept.
+    let raised = Arc::new(Mutex::new(Vec::new()));
+    let sink = raised.clone();
+    events.attach(move |message| {
+        sink.lock()
+            .unwrap()
+            .push(serde_json::from_str(&message).unwrap())
+    });
+    let calendar = CalendarService::new(
+        CalendarConfig::default(),
+        metadata,
+        Arc::new(IdResolver::new(None, Duration::from_secs(1))),
+        Arc::new(library),
+        events,
+        &dir.join("calendar_state.json"),
+    );
+    (calendar, raised, dir)
+}
+
+/// Video ids of the `newEpisode` events raised so far.
+fn announced(raised: &Mutex<Vec<Value>>) -> Vec<String> {
+    raised
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|message| message["event"] == NEW_EPISODE_EVENT)
+        .map(|message| message["data"]["videoId"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn announces_only_episodes_aired_since_the_last_check() {
+    let now = Utc::now();
+    let hours = |hours| now + TimeDelta::hours(hours);
+    let (calendar, raised, dir) = calendar("check", &[hours(-48), hours(-3), hours(-2), hours(24)]);
+
+    // The first run only records when it ran, so the back catalog stays quiet.
+    calendar.check().await.unwrap();
+    assert!(announced(&raised).is_empty());
+    assert!(calendar.load_state().last_checked.unwrap() >= now);
+
+    // Episodes aired after the previous check and up to now are announced;
+    // one released exactly at the previous check was already covered.
+    calendar
+        .save_state(&CheckState {
+            last_checked: Some(hours(-3)),
+        })
+        .unwrap();
+    calendar.check().await.unwrap();
+    assert_eq!(announced(&raised), ["tt0903747:1:3"]);
+
+    calendar.check().await.unwrap();
+    assert_eq!(announced(&raised), ["tt0903747:1:3"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
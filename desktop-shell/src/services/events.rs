@@ -0,0 +1,55 @@
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+type Sink = Arc<dyn Fn(String) + Send + Sync>;
+
+/// Pushes messages to the page that are not replies to a request. They are
+/// sent as `{"event": <name>, "data": ...}`, so request handlers waiting for
+/// a `requestId` ignore them.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    sink: Arc<RwLock<Option<Sink>>>,
+}
+
+impl EventBus {
+    /// Sets where events go. Events raised before this are dropped.
+    pub fn attach(&self, sink: impl Fn(String) + Send + Sync + 'static) {
+        let mut guard = match self.sink.write() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *guard = Some(Arc::new(sink));
+    }
+
+    pub fn emit(&self, event: &str, data: impl Serialize) {
+        match serde_json::to_value(data) {
+            Ok(data) => self.send(event, json!({ "event": event, "data": data })),
+            Err(e) => log::error!("Failed to serialize '{}' event: {}", event, e),
+        }
+    }
+
+    /// Replies to a request answered off the UI thread, in the same shape as
+    /// a direct reply.
+    pub fn reply(&self, request_id: &str, result: Result<Value>) {
+        let message = match result {
+            Ok(data) => json!({ "requestId": request_id, "success": true, "data": data }),
+            Err(e) => json!({ "requestId": request_id, "success": false, "error": e.to_string() }),
+        };
+        self.send(request_id, message);
+    }
+
+    fn send(&self, name: &str, message: Value) {
+        let sink = match self.sink.read() {
+            Ok(guard) => guard.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+        let Some(sink) = sink else {
+            log::debug!("Dropping '{}': no window attached", name);
+            return;
+        };
+        sink(message.to_string());
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use image::{imageops::FilterType, ImageFormat};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::config::ImageCacheConfig;
+
+/// URI scheme the page uses to load cached artwork, e.g.
+/// `westream-img://image/?src=https%3A%2F%2F...&w=342`.
+pub const SCHEME: &str = "westream-img";
+
+/// When each cached file was last served, by file name, so the LRU order
+/// survives restarts without touching the files themselves.
+const INDEX_FILE: &str = "index.json";
+
+pub struct CachedImage {
+    pub bytes: Vec<u8>,
+    pub content_type: &'static str,
+}
+
+struct Entry {
+    size: u64,
+    accessed: SystemTime,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Index {
+    accessed: HashMap<String, SystemTime>,
+}
+
+/// Downloads artwork once, keeps it on disk and evicts the least recently
+/// served files when the cache grows past `max_size_mb`.
+pub struct ImageCache {
+    dir: PathBuf,
+    client: reqwest::Client,
+    config: ImageCacheConfig,
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+impl ImageCache {
+    pub fn open(dir: &Path, config: ImageCacheConfig) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.download_timeout_ms))
+            .build()?;
+
+        let index: Index = fs::read(dir.join(INDEX_FILE))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        let mut entries = HashMap::new();
+        for file in fs::read_dir(dir)?.flatten() {
+            let Ok(meta) = file.metadata() else { continue };
+            if meta.is_file() && file.file_name() != INDEX_FILE {
+                // Files the index misses rank by when they were written.
+                let accessed = file
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| index.accessed.get(name).copied())
+                    .or(meta.modified().ok())
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+                entries.insert(
+                    file.path(),
+                    Entry {
+                        size: meta.len(),
+                        accessed,
+                    },
+                );
+            }
+        }
+        info!("Image cache at {:?} holds {} files", dir, entries.len());
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            client,
+            config,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Resolves a `westream-img://` URI to image bytes, downloading and
+    /// resizing on a miss.
+    pub async fn get(self: &Arc<Self>, uri: &str) -> Result<CachedImage> {
+        let (source, width) = parse_uri(uri)?;
+        let width = width.and_then(|w| self.snap_width(w));
+        let key = hex::encode(Sha256::digest(source.as_str().as_bytes()));
+
+        let original_path = self.dir.join(&key);
+        let original = match self.read(&original_path) {
+            Some(bytes) => bytes,
+            None => {
+                let bytes = self.download(&source).await?;
+                self.write(original_path, bytes).await?
+            }
+        };
+
+        let bytes = match width {
+            Some(width) => {
+                let resized_path = self.dir.join(format!("{}_w{}", key, width));
+                match self.read(&resized_path) {
+                    Some(bytes) => bytes,
+                    None => {
+                        let bytes =
+                            tokio::task::spawn_blocking(move || resize(&original, width)).await??;
+                        self.write(resized_path, bytes).await?
+                    }
+                }
+            }
+            None => original,
+        };
+
+        let content_type = image::guess_format(&bytes)
+            .map(|format| format.to_mime_type())
+            .unwrap_or("application/octet-stream");
+        Ok(CachedImage {
+            bytes,
+            content_type,
+        })
+    }
+
+    async fn download(&self, source: &Url) -> Result<Vec<u8>> {
+        debug!("Downloading image {}", source);
+        let mut response = self.client.get(source.clone()).send().await?;
+        if !response.status().is_success() {
+            bail!("Image download failed with status {}", response.status());
+        }
+        let max = self.config.max_download_mb * 1024 * 1024;
+        let too_large = || anyhow!("Image is larger than {} MB", self.config.max_download_mb);
+        if response.content_length().is_some_and(|len| len > max) {
+            return Err(too_large());
+        }
+        // Read in chunks, as the length may be missing or wrong.
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if (bytes.len() + chunk.len()) as u64 > max {
+                return Err(too_large());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        image::guess_format(&bytes).context("Downloaded file is not an image")?;
+        Ok(bytes)
+    }
+
+    /// Picks the smallest configured width that is at least `requested`.
+    fn snap_width(&self, requested: u32) -> Option<u32> {
+        let mut widths = self.config.thumbnail_widths.clone();
+        widths.sort_unstable();
+        widths
+            .iter()
+            .copied()
+            .find(|w| *w >= requested)
+            .or(widths.last().copied())
+    }
+
+    fn read(&self, path: &Path) -> Option<Vec<u8>> {
+        let bytes = fs::read(path).ok()?;
+        let now = SystemTime::now();
+        self.lock()
+            .entry(path.to_path_buf())
+            .or_insert(Entry {
+                size: bytes.len() as u64,
+                accessed: now,
+            })
+            .accessed = now;
+        Some(bytes)
+    }
+
+    /// Stores `bytes` off the runtime, as writing them, evicting and saving
+    /// the index all touch the disk. Returns the bytes for serving.
+    async fn write(self: &Arc<Self>, path: PathBuf, bytes: Vec<u8>) -> Result<Vec<u8>> {
+        let cache = self.clone();
+        tokio::task::spawn_blocking(move || {
+            cache.write_blocking(&path, &bytes)?;
+            Ok(bytes)
+        })
+        .await?
+    }
+
+    fn write_blocking(&self, path: &Path, bytes: &[u8]) -> Result<()> {
+        fs::write(path, bytes)?;
+        let mut entries = self.lock();
+        entries.insert(
+            path.to_path_buf(),
+            Entry {
+                size: bytes.len() as u64,
+                accessed: SystemTime::now(),
+            },
+        );
+        self.evict(&mut entries);
+        self.save_index(&entries);
+        Ok(())
+    }
+
+    /// Records the access times, including those of reads since the last
+    /// write.
+    fn save_index(&self, entries: &HashMap<PathBuf, Entry>) {
+        let index = Index {
+            accessed: entries
+                .iter()
+                .filter_map(|(path, entry)| {
+                    let name = path.file_name()?.to_str()?;
+                    Some((name.to_string(), entry.accessed))
+                })
+                .collect(),
+        };
+        let result = serde_json::to_vec(&index)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(fs::write(self.dir.join(INDEX_FILE), bytes)?));
+        if let Err(e) = result {
+            warn!("Failed to save the image cache index: {}", e);
+        }
+    }
+
+    fn evict(&self, entries: &mut HashMap<PathBuf, Entry>) {
+        let max = self.config.max_size_mb * 1024 * 1024;
+        let mut total: u64 = entries.values().map(|e| e.size).sum();
+        if total <= max {
+            return;
+        }
+
+        let mut by_age: Vec<(PathBuf, SystemTime)> = entries
+            .iter()
+            .map(|(path, e)| (path.clone(), e.accessed))
+            .collect();
+        by_age.sort_by_key(|(_, accessed)| *accessed);
+        for (path, _) in by_age {
+            if total <= max {
+                break;
+            }
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("Failed to evict cached image {:?}: {}", path, e);
+            }
+            if let Some(entry) = entries.remove(&path) {
+                total -= entry.size;
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<PathBuf, Entry>> {
+        match self.entries.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => {
+                log::error!("Mutex poisoned! Attempting recovery");
+                poisoned.into_inner()
+            }
+        }
+    }
+}
+
+fn parse_uri(uri: &str) -> Result<(Url, Option<u32>)> {
+    let uri = Url::parse(uri).context("Invalid image URI")?;
+    if uri.scheme() != SCHEME {
+        bail!("Unexpected image URI scheme: {}", uri.scheme());
+    }
+
+    let mut source = None;
+    let mut width = None;
+    for (key, value) in uri.query_pairs() {
+        match key.as_ref() {
+            "src" => source = Some(Url::parse(&value).context("Invalid image source")?),
+            "w" => width = value.parse().ok(),
+            _ => {}
+        }
+    }
+
+    let source = source.context("Image URI has no src")?;
+    if !matches!(source.scheme(), "http" | "https") {
+        bail!("Unsupported image source scheme: {}", source.scheme());
+    }
+    Ok((source, width))
+}
+
+/// Scales the image down to `width`, keeping its format. Images that are
+/// already narrower are returned unchanged.
+fn resize(bytes: &[u8], width: u32) -> Result<Vec<u8>> {
+    let format = image::guess_format(bytes)?;
+    let decoded = image::load_from_memory_with_format(bytes, format)?;
+    if decoded.width() <= width {
+        return Ok(bytes.to_vec());
+    }
+
+    let height = (decoded.height() as u64 * width as u64 / decoded.width() as u64).max(1) as u32;
+    let resized = decoded.resize_exact(width, height, FilterType::Triangle);
+    let format = match format {
+        ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP => format,
+        _ => ImageFormat::Png,
+    };
+
+    let mut out = Cursor::new(Vec::new());
+    resized.write_to(&mut out, format)?;
+    Ok(out.into_inner())
+}
@@ -0,0 +1,121 @@
+use serde::Serialize;
+
+use super::parser::ParsedName;
+use crate::services::metadata::{aggregator::MetadataAggregator, search::tokenize, MediaItem};
+
+const TITLE_WEIGHT: f32 = 0.7;
+const YEAR_WEIGHT: f32 = 0.2;
+const TYPE_WEIGHT: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MatchSource {
+    /// Fuzzy title search against the metadata index.
+    Search,
+    /// A Kodi NFO next to the file.
+    Nfo,
+    /// Picked by the user with `setLibraryMatch`.
+    Manual,
+}
+
+/// A metadata item a local file was matched to.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataMatch {
+    pub id: String,
+    pub title: String,
+    /// 0.0 to 1.0; only matches at or above `LibraryConfig::min_confidence`
+    /// are kept.
+    pub confidence: f32,
+    pub source: MatchSource,
+    #[serde(skip)]
+    pub item: MediaItem,
+}
+
+impl MetadataMatch {
+    /// A match that is taken as given rather than scored.
+    pub fn certain(item: MediaItem, source: MatchSource) -> Self {
+        Self {
+            id: item.id.clone(),
+            title: item.title.clone(),
+            confidence: 1.0,
+            source,
+            item,
+        }
+    }
+}
+
+/// Finds the indexed item that best fits a parsed file name.
+pub fn best_match(
+    metadata: &MetadataAggregator,
+    parsed: &ParsedName,
+    min_confidence: f32,
+) -> Option<MetadataMatch> {
+    let hits = metadata.search(wanted(parsed).0, 10);
+    best_of(hits.into_iter().map(|hit| hit.item), parsed, min_confidence)
+}
+
+/// Like `best_match`, but asks the metadata providers, for files the index
+/// has nothing confident for.
+pub async fn remote_match(
+    metadata: &MetadataAggregator,
+    parsed: &ParsedName,
+    min_confidence: f32,
+) -> Option<MetadataMatch> {
+    let items = metadata.search_remote(wanted(parsed).0).await;
+    best_of(items, parsed, min_confidence)
+}
+
+fn best_of(
+    items: impl IntoIterator<Item = MediaItem>,
+    parsed: &ParsedName,
+    min_confidence: f32,
+) -> Option<MetadataMatch> {
+    items
+        .into_iter()
+        .map(|item| (confidence(parsed, &item), item))
+        .filter(|(confidence, _)| *confidence >= min_confidence)
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(confidence, item)| MetadataMatch {
+            id: item.id.clone(),
+            title: item.title.clone(),
+            confidence,
+            source: MatchSource::Search,
+            item,
+        })
+}
+
+/// The title, year and media type to look for.
+fn wanted(parsed: &ParsedName) -> (&str, Option<i32>, &'static str) {
+    match parsed {
+        ParsedName::Movie { title, year } => (title, *year, "movie"),
+        ParsedName::Episode { show, .. } => (show, None, "series"),
+    }
+}
+
+/// How well `item` fits the parsed name, from 0.0 to 1.0.
+pub fn confidence(parsed: &ParsedName, item: &MediaItem) -> f32 {
+    let (title, year, media_type) = wanted(parsed);
+    let wanted = tokenize(title).join(" ");
+    let title_score = std::iter::once(&item.title)
+        .chain(item.original_title.as_ref())
+        .map(|candidate| strsim::normalized_levenshtein(&wanted, &tokenize(candidate).join(" ")))
+        .fold(0.0, f64::max) as f32;
+
+    // Unknown years neither help nor hurt.
+    let year_score = match year {
+        Some(year) if item.year > 0 => match (year - item.year).abs() {
+            0 => 1.0,
+            1 => 0.5,
+            _ => 0.0,
+        },
+        _ => 0.5,
+    };
+    let type_score = if item.media_type == media_type {
+        1.0
+    } else {
+        0.0
+    };
+
+    title_score * TITLE_WEIGHT + year_score * YEAR_WEIGHT + type_score * TYPE_WEIGHT
+}
@@ -0,0 +1,81 @@
+use std::{
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use log::debug;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::services::metadata::MediaItem;
+
+/// Matches picked with `setLibraryMatch`, keyed by file path, so they
+/// outlive rescans, watcher events and restarts. The item is kept whole so
+/// restoring a match needs no provider.
+pub struct MatchStore {
+    conn: Mutex<Connection>,
+}
+
+impl MatchStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open library matches at {:?}", path))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS manual_matches (
+                path TEXT PRIMARY KEY,
+                item TEXT NOT NULL,
+                matched_at INTEGER NOT NULL
+            );",
+        )?;
+        debug!("Opened library matches at {:?}", path);
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn get(&self, path: &Path) -> Result<Option<MediaItem>> {
+        let item: Option<String> = self
+            .lock()
+            .query_row(
+                "SELECT item FROM manual_matches WHERE path = ?1",
+                params![path.to_string_lossy()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match item {
+            Some(item) => Ok(Some(serde_json::from_str(&item)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put(&self, path: &Path, item: &MediaItem) -> Result<()> {
+        self.lock().execute(
+            "INSERT OR REPLACE INTO manual_matches (path, item, matched_at) VALUES (?1, ?2, ?3)",
+            params![path.to_string_lossy(), serde_json::to_string(item)?, now()],
+        )?;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Connection> {
+        match self.conn.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => {
+                log::error!("Mutex poisoned! Attempting recovery");
+                poisoned.into_inner()
+            }
+        }
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,482 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Instant,
+};
+
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::config::LibraryConfig;
+use crate::services::events::EventBus;
+use crate::services::metadata::{aggregator::MetadataAggregator, search::tokenize, MediaItem};
+use matcher::{MatchSource, MetadataMatch};
+use matches::MatchStore;
+use nfo::{Nfo, NfoKind, Sidecars};
+use parser::ParsedName;
+
+pub mod matcher;
+pub mod matches;
+pub mod nfo;
+pub mod parser;
+
+#[cfg(test)]
+mod tests;
+
+/// Catalog id under which `getCatalog` lists the local library.
+pub const LOCAL_CATALOG: &str = "local";
+
+pub const SCAN_PROGRESS_EVENT: &str = "libraryScanProgress";
+pub const SCAN_DONE_EVENT: &str = "libraryScanDone";
+
+/// Files between two `libraryScanProgress` events.
+const PROGRESS_INTERVAL: usize = 100;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalFile {
+    pub path: PathBuf,
+    pub size: u64,
+    pub parsed: ParsedName,
+    #[serde(rename = "match")]
+    pub matched: Option<MetadataMatch>,
+    /// `movie.nfo` or `tvshow.nfo` that overrode the match.
+    pub nfo: Option<Nfo>,
+    /// `<file>.nfo` for an episode; its season and episode override the
+    /// parsed ones.
+    pub episode_nfo: Option<Nfo>,
+    pub episode_title: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ScanProgress<'a> {
+    folder: &'a Path,
+    files: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanSummary {
+    pub files: usize,
+    pub matched: usize,
+    pub duration_ms: u128,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NfoExport {
+    pub written: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// Keeps an index of media files in the configured folders, matched to
+/// metadata ids where possible.
+pub struct LibraryService {
+    config: LibraryConfig,
+    metadata: Arc<MetadataAggregator>,
+    files: RwLock<BTreeMap<PathBuf, LocalFile>>,
+    watcher: Mutex<Option<RecommendedWatcher>>,
+    /// Without it, manual matches only last until the file is rescanned.
+    matches: Option<MatchStore>,
+    events: EventBus,
+    /// Provider lookups for files the search index has no match for block on
+    /// this; `inspect` only runs on blocking threads.
+    runtime: tokio::runtime::Handle,
+    scanning: AtomicBool,
+}
+
+impl LibraryService {
+    pub fn new(
+        config: LibraryConfig,
+        metadata: Arc<MetadataAggregator>,
+        matches: Option<MatchStore>,
+        events: EventBus,
+        runtime: tokio::runtime::Handle,
+    ) -> Self {
+        Self {
+            config,
+            metadata,
+            files: RwLock::default(),
+            watcher: Mutex::new(None),
+            matches,
+            events,
+            runtime,
+            scanning: AtomicBool::new(false),
+        }
+    }
+
+    pub fn watch_enabled(&self) -> bool {
+        self.config.watch
+    }
+
+    /// Walks every configured folder and replaces the index, emitting
+    /// `libraryScanProgress` along the way and `libraryScanDone` at the end.
+    /// Blocks on disk and provider lookups, so it must run on a blocking
+    /// thread. Fails if a scan is already running.
+    pub fn scan(&self) -> Result<ScanSummary> {
+        if self.scanning.swap(true, Ordering::AcqRel) {
+            bail!("A library scan is already running");
+        }
+        let summary = self.scan_folders();
+        self.scanning.store(false, Ordering::Release);
+        self.events.emit(SCAN_DONE_EVENT, &summary);
+        Ok(summary)
+    }
+
+    fn scan_folders(&self) -> ScanSummary {
+        let started = Instant::now();
+        let mut files = BTreeMap::new();
+        for folder in &self.config.folders {
+            if !folder.is_dir() {
+                warn!("Library folder {:?} does not exist", folder);
+                continue;
+            }
+            for (path, file) in self.walk(folder) {
+                files.insert(path, file);
+                if files.len() % PROGRESS_INTERVAL == 0 {
+                    let progress = ScanProgress {
+                        folder,
+                        files: files.len(),
+                    };
+                    self.events.emit(SCAN_PROGRESS_EVENT, &progress);
+                }
+            }
+        }
+
+        let summary = ScanSummary {
+            files: files.len(),
+            matched: files.values().filter(|f| f.matched.is_some()).count(),
+            duration_ms: started.elapsed().as_millis(),
+        };
+        info!(
+            "Library scan found {} files ({} matched) in {}ms",
+            summary.files, summary.matched, summary.duration_ms
+        );
+        *self.write() = files;
+        self.reindex();
+        summary
+    }
+
+    /// Starts watching the configured folders so the index follows changes
+    /// without a full rescan.
+    pub fn watch(self: &Arc<Self>) -> Result<()> {
+        let library = Arc::downgrade(self);
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let Some(library) = library.upgrade() else {
+                return;
+            };
+            match event {
+                Ok(event) => library.apply_event(event),
+                Err(e) => warn!("Library watcher error: {}", e),
+            }
+        })?;
+
+        for folder in &self.config.folders {
+            if folder.is_dir() {
+                watcher.watch(folder, RecursiveMode::Recursive)?;
+                debug!("Watching library folder {:?}", folder);
+            }
+        }
+        *self.watcher.lock().unwrap_or_else(|p| p.into_inner()) = Some(watcher);
+        Ok(())
+    }
+
+    pub fn files(&self) -> Vec<LocalFile> {
+        self.read().values().cloned().collect()
+    }
+
+    /// Builds the "Local Files" catalog: one entry per movie and one per
+    /// series, using matched metadata when there is a confident match.
+    pub fn catalog(&self) -> Vec<MediaItem> {
+        let files = self.read();
+        let mut items: HashMap<String, MediaItem> = HashMap::new();
+        for file in files.values() {
+            let item = match &file.matched {
+                Some(matched) => matched.item.clone(),
+                None => local_item(&file.path, &file.parsed),
+            };
+            items.entry(item.id.clone()).or_insert(item);
+        }
+
+        let mut items: Vec<MediaItem> = items.into_values().collect();
+        items.sort_by_cached_key(|item| item.title.to_lowercase());
+        items
+    }
+
+    /// Replaces a file's match with one the user picked and remembers it.
+    /// With `write_nfo` enabled it is also written to an NFO, though an
+    /// existing one is only replaced when `overwrite_nfo` is set.
+    pub fn set_match(
+        &self,
+        path: &Path,
+        item: MediaItem,
+        overwrite_nfo: bool,
+    ) -> Result<LocalFile> {
+        match &self.matches {
+            Some(matches) => matches.put(path, &item)?,
+            None => warn!("Match for {:?} will not survive a rescan", path),
+        }
+        let file = {
+            let mut files = self.write();
+            let file = files.get_mut(path).context("File is not in the library")?;
+            file.matched = Some(MetadataMatch::certain(item, MatchSource::Manual));
+            file.clone()
+        };
+        self.reindex();
+        info!(
+            "Matched {:?} to {}",
+            path,
+            file.matched.as_ref().map_or("", |m| &m.id)
+        );
+
+        if self.config.write_nfo {
+            if let Some(target) = nfo::item_path(&file.path, &file.parsed) {
+                if target.exists() && !overwrite_nfo {
+                    info!("Keeping existing NFO {:?}", target);
+                } else {
+                    let matched = file.matched.as_ref().context("File has no match")?;
+                    nfo::write(&target, nfo_kind(&file.parsed), &matched.item)?;
+                }
+            }
+        }
+        Ok(file)
+    }
+
+    /// Writes item-level NFOs for every matched file. Existing NFOs are only
+    /// replaced when `overwrite` is set.
+    pub fn export_nfo(&self, overwrite: bool) -> NfoExport {
+        let mut export = NfoExport::default();
+        let mut seen = HashSet::new();
+        for file in self.files() {
+            let Some(matched) = &file.matched else {
+                continue;
+            };
+            let Some(target) = nfo::item_path(&file.path, &file.parsed) else {
+                export.skipped += 1;
+                continue;
+            };
+            // Episodes of one show share a `tvshow.nfo`.
+            if !seen.insert(target.clone()) {
+                continue;
+            }
+            if target.exists() && !overwrite {
+                export.skipped += 1;
+                continue;
+            }
+            match nfo::write(&target, nfo_kind(&file.parsed), &matched.item) {
+                Ok(()) => export.written += 1,
+                Err(e) => {
+                    warn!("Failed to export NFO for {:?}: {}", file.path, e);
+                    export.failed += 1;
+                }
+            }
+        }
+        info!(
+            "Exported NFOs: {} written, {} skipped, {} failed",
+            export.written, export.skipped, export.failed
+        );
+        export
+    }
+
+    fn apply_event(&self, event: Event) {
+        if !matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ) {
+            return;
+        }
+        for path in &event.paths {
+            self.refresh(path);
+        }
+    }
+
+    /// Re-reads one changed path: whatever was indexed at or below it is
+    /// dropped and whatever exists there now is added back.
+    fn refresh(&self, path: &Path) {
+        // A changed NFO affects the media next to it, and for `tvshow.nfo`
+        // everything below it.
+        let is_nfo = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("nfo"));
+        let path = match path.parent() {
+            Some(parent) if is_nfo => parent,
+            _ => path,
+        };
+
+        let fresh: Vec<(PathBuf, LocalFile)> = if path.is_dir() {
+            self.walk(path).collect()
+        } else {
+            self.inspect(path)
+                .map(|file| (path.to_path_buf(), file))
+                .into_iter()
+                .collect()
+        };
+
+        {
+            let mut files = self.write();
+            let before = files.len();
+            files.retain(|indexed, _| !indexed.starts_with(path));
+            let removed = before - files.len();
+            let added = fresh.len();
+            files.extend(fresh);
+            debug!(
+                "Library change at {:?}: {} removed, {} added",
+                path, removed, added
+            );
+        }
+        self.reindex();
+    }
+
+    /// Puts the library's catalog in the search index.
+    fn reindex(&self) {
+        self.metadata.index_library(self.catalog());
+    }
+
+    fn walk<'a>(&'a self, folder: &Path) -> impl Iterator<Item = (PathBuf, LocalFile)> + 'a {
+        WalkDir::new(folder)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .filter_map(|entry| {
+                let file = self.inspect(entry.path())?;
+                Some((entry.into_path(), file))
+            })
+    }
+
+    fn inspect(&self, path: &Path) -> Option<LocalFile> {
+        if !self.is_media(path) {
+            return None;
+        }
+        let meta = fs::metadata(path).ok()?;
+        if !meta.is_file() {
+            return None;
+        }
+        let mut parsed = parser::parse(path)?;
+        let sidecars = Sidecars::find(path, &parsed);
+        let episode_title = sidecars.episode.as_ref().and_then(|nfo| {
+            nfo.apply_episode(&mut parsed);
+            nfo.title.clone()
+        });
+        let matched = match (self.manual_match(path), &sidecars.item) {
+            (Some(item), _) => Some(MetadataMatch::certain(item, MatchSource::Manual)),
+            (None, Some(nfo)) => Some(self.nfo_match(path, &parsed, nfo)),
+            (None, None) => self.search_match(&parsed),
+        };
+        Some(LocalFile {
+            path: path.to_path_buf(),
+            size: meta.len(),
+            parsed,
+            matched,
+            nfo: sidecars.item,
+            episode_nfo: sidecars.episode,
+            episode_title,
+        })
+    }
+
+    /// The item the user matched this file to, if any.
+    fn manual_match(&self, path: &Path) -> Option<MediaItem> {
+        match self.matches.as_ref()?.get(path) {
+            Ok(item) => item,
+            Err(e) => {
+                warn!("Failed to read the match for {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Searches the index first and only asks the providers when it has
+    /// nothing confident.
+    fn search_match(&self, parsed: &ParsedName) -> Option<MetadataMatch> {
+        let min_confidence = self.config.min_confidence;
+        matcher::best_match(&self.metadata, parsed, min_confidence).or_else(|| {
+            self.runtime.block_on(matcher::remote_match(
+                &self.metadata,
+                parsed,
+                min_confidence,
+            ))
+        })
+    }
+
+    /// Starts from the item the NFO's id points at (or the best search match
+    /// when it has none) and lets the NFO override it.
+    fn nfo_match(&self, path: &Path, parsed: &ParsedName, nfo: &Nfo) -> MetadataMatch {
+        let mut item = match nfo.metadata_id() {
+            Some(id) => self
+                .metadata
+                .indexed(&id)
+                .unwrap_or_else(|| local_item(path, parsed)),
+            None => self
+                .search_match(parsed)
+                .map(|matched| matched.item)
+                .unwrap_or_else(|| local_item(path, parsed)),
+        };
+        nfo.apply(&mut item);
+        MetadataMatch::certain(item, MatchSource::Nfo)
+    }
+
+    fn is_media(&self, path: &Path) -> bool {
+        let Some(extension) = path.extension().and_then(|e| e.to_str()) else {
+            return false;
+        };
+        let is_sample = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .is_some_and(|stem| tokenize(stem).iter().any(|t| t == "sample"));
+        !is_sample
+            && self
+                .config
+                .extensions
+                .iter()
+                .any(|e| e.eq_ignore_ascii_case(extension))
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, BTreeMap<PathBuf, LocalFile>> {
+        self.files.read().unwrap_or_else(|p| p.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, BTreeMap<PathBuf, LocalFile>> {
+        self.files.write().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+/// Catalog entry for a file without a metadata match.
+fn local_item(path: &Path, parsed: &ParsedName) -> MediaItem {
+    match parsed {
+        ParsedName::Movie { title, year } => MediaItem {
+            id: format!("local:{}", short_hash(&path.to_string_lossy())),
+            media_type: "movie".into(),
+            title: title.clone(),
+            year: year.unwrap_or_default(),
+            ..Default::default()
+        },
+        ParsedName::Episode { show, .. } => MediaItem {
+            id: format!("local:series:{}", tokenize(show).join("-")),
+            media_type: "series".into(),
+            title: show.clone(),
+            ..Default::default()
+        },
+    }
+}
+
+fn nfo_kind(parsed: &ParsedName) -> NfoKind {
+    match parsed {
+        ParsedName::Movie { .. } => NfoKind::Movie,
+        ParsedName::Episode { .. } => NfoKind::TvShow,
+    }
+}
+
+fn short_hash(value: &str) -> String {
+    hex::encode(&Sha256::digest(value.as_bytes())[..8])
+}
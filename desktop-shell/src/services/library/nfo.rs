@@ -0,0 +1,406 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use quick_xml::{events::Event, se::Serializer, Reader};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::parser::{self, ParsedName};
+use crate::services::metadata::{ids::ExternalId, MediaItem};
+
+// IMDb and TMDB links in URL-only NFOs.
+static IMDB_ID: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\btt\d{7,}\b").unwrap());
+static TMDB_URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"themoviedb\.org/(?:movie|tv)/(\d+)").unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NfoKind {
+    Movie,
+    TvShow,
+    Episode,
+}
+
+impl NfoKind {
+    fn root(self) -> &'static str {
+        match self {
+            NfoKind::Movie => "movie",
+            NfoKind::TvShow => "tvshow",
+            NfoKind::Episode => "episodedetails",
+        }
+    }
+}
+
+/// The parts of a Kodi NFO file the library understands.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Nfo {
+    pub path: PathBuf,
+    pub kind: NfoKind,
+    /// Provider ids keyed by type (`imdb`, `tmdb`, `tvdb`, ...).
+    pub ids: BTreeMap<String, String>,
+    /// Type of the id marked `default="true"`, which names the item.
+    pub default_id: Option<String>,
+    pub title: Option<String>,
+    pub original_title: Option<String>,
+    pub year: Option<i32>,
+    pub plot: Option<String>,
+    pub genres: Vec<String>,
+    pub cast: Vec<String>,
+    pub poster: Option<String>,
+    pub season: Option<u32>,
+    pub episode: Option<u32>,
+    pub aired: Option<String>,
+}
+
+/// NFOs found next to a media file.
+#[derive(Debug, Default)]
+pub struct Sidecars {
+    /// `movie.nfo`/`<file>.nfo` for movies, `tvshow.nfo` for episodes.
+    pub item: Option<Nfo>,
+    /// `<file>.nfo` describing a single episode.
+    pub episode: Option<Nfo>,
+}
+
+impl Sidecars {
+    pub fn find(path: &Path, parsed: &ParsedName) -> Self {
+        match parsed {
+            ParsedName::Movie { .. } => Self {
+                item: movie_candidates(path)
+                    .iter()
+                    .find_map(|candidate| read(candidate, NfoKind::Movie)),
+                episode: None,
+            },
+            ParsedName::Episode { show, .. } => Self {
+                item: parser::show_dir(path, show)
+                    .and_then(|dir| read(&dir.join("tvshow.nfo"), NfoKind::TvShow)),
+                episode: read(&path.with_extension("nfo"), NfoKind::Episode),
+            },
+        }
+    }
+}
+
+impl Nfo {
+    /// The id metadata uses for this item: the default one, else IMDb when
+    /// present, else the first other provider as `<type>:<id>`. TMDB ids are
+    /// qualified by the NFO's kind as `tmdb:movie:<id>` or `tmdb:tv:<id>`.
+    pub fn metadata_id(&self) -> Option<String> {
+        let (kind, id) = self
+            .default_id
+            .as_ref()
+            .and_then(|kind| self.ids.get_key_value(kind))
+            .or_else(|| self.ids.get_key_value("imdb"))
+            .or_else(|| self.ids.iter().next())?;
+        Some(match kind.as_str() {
+            "imdb" => id.clone(),
+            "tmdb" if self.kind == NfoKind::Movie => format!("tmdb:movie:{}", id),
+            "tmdb" => format!("tmdb:tv:{}", id),
+            _ => format!("{}:{}", kind, id),
+        })
+    }
+
+    /// Overrides matched metadata with whatever the NFO specifies.
+    pub fn apply(&self, item: &mut MediaItem) {
+        if let Some(id) = self.metadata_id() {
+            item.id = id;
+        }
+        if let Some(title) = &self.title {
+            item.title = title.clone();
+        }
+        if self.original_title.is_some() {
+            item.original_title = self.original_title.clone();
+        }
+        if let Some(year) = self.year {
+            item.year = year;
+        }
+        if self.plot.is_some() {
+            item.description = self.plot.clone();
+        }
+        if !self.genres.is_empty() {
+            item.genres = self.genres.clone();
+        }
+        if !self.cast.is_empty() {
+            item.cast = self.cast.clone();
+        }
+        if let Some(poster) = &self.poster {
+            item.poster = poster.clone();
+        }
+    }
+
+    /// Corrects an episode's numbering with the episode NFO's, e.g. for
+    /// files named by absolute number.
+    pub fn apply_episode(&self, parsed: &mut ParsedName) {
+        if let ParsedName::Episode {
+            season, episode, ..
+        } = parsed
+        {
+            if let Some(nfo_season) = self.season {
+                *season = nfo_season;
+            }
+            if let Some(nfo_episode) = self.episode {
+                *episode = nfo_episode;
+            }
+        }
+    }
+}
+
+/// Where the item-level NFO for a media file lives, or would be written.
+pub fn item_path(path: &Path, parsed: &ParsedName) -> Option<PathBuf> {
+    match parsed {
+        ParsedName::Movie { .. } => {
+            let candidates = movie_candidates(path);
+            let existing = candidates.iter().find(|candidate| candidate.is_file());
+            Some(existing.unwrap_or(&candidates[0]).clone())
+        }
+        ParsedName::Episode { show, .. } => {
+            parser::show_dir(path, show).map(|dir| dir.join("tvshow.nfo"))
+        }
+    }
+}
+
+/// Writes a `movie` or `tvshow` NFO for `item`. Elements the library does
+/// not model are not preserved when an existing file is overwritten.
+pub fn write(path: &Path, kind: NfoKind, item: &MediaItem) -> Result<()> {
+    let xml = NfoXml::from_item(item);
+    let mut body = String::new();
+    let mut serializer = Serializer::with_root(&mut body, Some(kind.root()))?;
+    serializer.indent(' ', 2);
+    xml.serialize(serializer)?;
+
+    let content = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\" ?>\n{}\n",
+        body
+    );
+    let tmp = path.with_extension("nfo.tmp");
+    fs::write(&tmp, content).with_context(|| format!("Failed to write {:?}", tmp))?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to replace {:?}", path))?;
+    debug!("Wrote NFO {:?}", path);
+    Ok(())
+}
+
+/// Reads an NFO if it exists and describes the expected kind of item.
+/// Kodi also accepts NFOs holding just a provider URL, so those are read for
+/// their ids.
+fn read(path: &Path, kind: NfoKind) -> Option<Nfo> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut nfo = Nfo {
+        path: path.to_path_buf(),
+        kind,
+        ids: BTreeMap::new(),
+        default_id: None,
+        title: None,
+        original_title: None,
+        year: None,
+        plot: None,
+        genres: Vec::new(),
+        cast: Vec::new(),
+        poster: None,
+        season: None,
+        episode: None,
+        aired: None,
+    };
+
+    let root = root_element(&content);
+    match root.as_deref() {
+        Some(root) if root == kind.root() => match quick_xml::de::from_str::<NfoXml>(&content) {
+            Ok(xml) => xml.fill(&mut nfo),
+            Err(e) => {
+                warn!("Ignoring malformed NFO {:?}: {}", path, e);
+                return None;
+            }
+        },
+        Some(root) => {
+            debug!(
+                "Ignoring {:?}: expected <{}>, found <{}>",
+                path,
+                kind.root(),
+                root
+            );
+            return None;
+        }
+        None => {}
+    }
+
+    // URL-only NFOs, and URLs trailing the XML in "hybrid" ones.
+    if let Some(imdb) = IMDB_ID.find(&content) {
+        nfo.ids.entry("imdb".into()).or_insert(imdb.as_str().into());
+    }
+    if let Some(tmdb) = TMDB_URL.captures(&content) {
+        nfo.ids.entry("tmdb".into()).or_insert(tmdb[1].into());
+    }
+    (root.is_some() || !nfo.ids.is_empty()).then_some(nfo)
+}
+
+fn movie_candidates(path: &Path) -> [PathBuf; 2] {
+    [path.with_extension("nfo"), path.with_file_name("movie.nfo")]
+}
+
+fn root_element(content: &str) -> Option<String> {
+    let mut reader = Reader::from_str(content);
+    loop {
+        match reader.read_event().ok()? {
+            Event::Start(e) | Event::Empty(e) => {
+                return Some(String::from_utf8_lossy(e.name().as_ref()).into_owned())
+            }
+            Event::Eof => return None,
+            _ => {}
+        }
+    }
+}
+
+/// Kodi's NFO schema, shared by `<movie>`, `<tvshow>` and `<episodedetails>`.
+/// Numbers are kept as text because hand-edited files often leave them empty.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct NfoXml {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    originaltitle: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    year: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plot: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    genre: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    uniqueid: Vec<UniqueId>,
+    /// Pre-v17 single id element.
+    #[serde(skip_serializing)]
+    id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    actor: Vec<Actor>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    thumb: Vec<Thumb>,
+    #[serde(skip_serializing)]
+    season: Option<String>,
+    #[serde(skip_serializing)]
+    episode: Option<String>,
+    #[serde(skip_serializing)]
+    aired: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct UniqueId {
+    #[serde(rename = "@type", default, skip_serializing_if = "Option::is_none")]
+    kind: Option<String>,
+    #[serde(rename = "@default", default, skip_serializing_if = "Option::is_none")]
+    default: Option<String>,
+    #[serde(rename = "$text", default)]
+    value: String,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct Actor {
+    name: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Thumb {
+    #[serde(rename = "@aspect", default, skip_serializing_if = "Option::is_none")]
+    aspect: Option<String>,
+    #[serde(rename = "$text", default)]
+    url: String,
+}
+
+impl NfoXml {
+    fn fill(self, nfo: &mut Nfo) {
+        let text = |value: Option<String>| {
+            value
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+
+        for id in self.uniqueid {
+            let value = id.value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            let kind = id.kind.as_deref().unwrap_or("imdb").to_lowercase();
+            if id
+                .default
+                .is_some_and(|default| default.trim().eq_ignore_ascii_case("true"))
+            {
+                nfo.default_id = Some(kind.clone());
+            }
+            nfo.ids.insert(kind, value.to_string());
+        }
+        if let Some(id) = text(self.id) {
+            let kind = if id.starts_with("tt") { "imdb" } else { "tmdb" };
+            nfo.ids.entry(kind.into()).or_insert(id);
+        }
+
+        nfo.title = text(self.title);
+        nfo.original_title = text(self.originaltitle);
+        nfo.year = text(self.year).and_then(|y| y.parse().ok());
+        nfo.plot = text(self.plot);
+        nfo.genres = self
+            .genre
+            .into_iter()
+            .filter_map(|g| text(Some(g)))
+            .collect();
+        nfo.cast = self
+            .actor
+            .into_iter()
+            .filter_map(|a| text(Some(a.name)))
+            .collect();
+        nfo.poster = self
+            .thumb
+            .into_iter()
+            .filter(|t| t.aspect.as_deref().is_none_or(|a| a == "poster"))
+            .find_map(|t| text(Some(t.url)));
+        nfo.season = text(self.season).and_then(|s| s.parse().ok());
+        nfo.episode = text(self.episode).and_then(|e| e.parse().ok());
+        nfo.aired = text(self.aired);
+    }
+
+    fn from_item(item: &MediaItem) -> Self {
+        // The NFO's root says whether a TMDB id is a movie or a show, so only
+        // the number is written.
+        let id = match ExternalId::parse(&item.id) {
+            Some(id) => Some((id.scheme.as_str().to_string(), id.value)),
+            // Local ids mean the item is unmatched; there is nothing to record.
+            None if item.id.starts_with("local:") => None,
+            None => item
+                .id
+                .split_once(':')
+                .map(|(kind, value)| (kind.to_string(), value.to_string())),
+        };
+        let uniqueid = id
+            .map(|(kind, value)| UniqueId {
+                kind: Some(kind),
+                default: Some("true".into()),
+                value,
+            })
+            .into_iter()
+            .collect();
+        Self {
+            title: Some(item.title.clone()),
+            originaltitle: item.original_title.clone(),
+            year: (item.year > 0).then(|| item.year.to_string()),
+            plot: item.description.clone(),
+            genre: item.genres.clone(),
+            uniqueid,
+            actor: item
+                .cast
+                .iter()
+                .map(|name| Actor { name: name.clone() })
+                .collect(),
+            thumb: (!item.poster.is_empty())
+                .then(|| Thumb {
+                    aspect: Some("poster".into()),
+                    url: item.poster.clone(),
+                })
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
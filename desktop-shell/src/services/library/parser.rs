@@ -0,0 +1,183 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use chrono::Datelike;
+use regex::Regex;
+use serde::Serialize;
+
+/// What a file name says about its contents.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ParsedName {
+    Movie {
+        title: String,
+        year: Option<i32>,
+    },
+    Episode {
+        show: String,
+        season: u32,
+        episode: u32,
+    },
+}
+
+// `S02E05`, `s2.e5`, `2x05`
+static EPISODE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(?:^|[ ._\-\[(])(?:s(\d{1,2})[ ._\-]?e(\d{1,3})|(\d{1,2})x(\d{2,3}))(?:$|[ ._\-\])e])")
+        .unwrap()
+});
+// `Season 2`, `S02`, `Series 2`
+static SEASON_FOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^(?:season|series|s)[ ._\-]?(\d{1,2})$").unwrap());
+// `05 - Title`, `E05`, `Episode 5`
+static EPISODE_NUMBER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^(?:e|ep|episode)?[ ._\-]?(\d{1,3})(?:$|[ ._\-])").unwrap());
+// A release year, optionally in brackets: `(2010)`, `.2010.`
+static YEAR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[ ._\-\[(]((?:19|20)\d{2})(?:$|[ ._\-\])])").unwrap());
+// Leading release-group tags such as `[YTS]`
+static LEADING_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*\[[^\]]*\]\s*").unwrap());
+
+/// Tokens that mark the end of a title in scene-style names.
+const QUALITY_TAGS: &[&str] = &[
+    "2160p", "1080p", "1080i", "720p", "576p", "480p", "4k", "uhd", "hdr", "hdr10", "dv", "bluray",
+    "blu-ray", "brrip", "bdrip", "remux", "web-dl", "webdl", "webrip", "web", "hdtv", "dvdrip",
+    "dvd", "x264", "x265", "h264", "h265", "hevc", "xvid", "aac", "ac3", "dts", "proper", "repack",
+    "extended", "unrated", "internal", "multi",
+];
+
+/// Folder names that say nothing about the title, so the parent is used.
+const GENERIC_NAMES: &[&str] = &["movie", "film", "video", "sample"];
+
+/// Parses a media file's name, falling back to its folders for the show name
+/// (`Show/Season 2/05.mkv`) or movie title (`Movie (2010)/movie.mkv`).
+pub fn parse(path: &Path) -> Option<ParsedName> {
+    let stem = path.file_stem()?.to_str()?;
+    let stem = LEADING_TAG.replace(stem, "");
+    let parent = path.parent().and_then(folder_name);
+    let grandparent = path.parent().and_then(Path::parent).and_then(folder_name);
+
+    if let Some(caps) = EPISODE.captures(&stem) {
+        let season = caps.get(1).or(caps.get(3))?.as_str().parse().ok()?;
+        let episode = caps.get(2).or(caps.get(4))?.as_str().parse().ok()?;
+        let mut show = clean_title(&stem[..caps.get(0)?.start()]);
+        if show.is_empty() {
+            // `Show/Season 2/S02E05.mkv`
+            let folder = match parent {
+                Some(p) if SEASON_FOLDER.is_match(p) => grandparent,
+                other => other,
+            };
+            show = clean_title(folder?);
+        }
+        return (!show.is_empty()).then_some(ParsedName::Episode {
+            show,
+            season,
+            episode,
+        });
+    }
+
+    if let (Some(parent), Some(show_folder)) = (parent, grandparent) {
+        if let Some(season) = SEASON_FOLDER.captures(parent) {
+            if let Some(episode) = EPISODE_NUMBER.captures(&stem) {
+                let show = clean_title(show_folder);
+                if !show.is_empty() {
+                    return Some(ParsedName::Episode {
+                        show,
+                        season: season[1].parse().ok()?,
+                        episode: episode[1].parse().ok()?,
+                    });
+                }
+            }
+        }
+    }
+
+    let (title, year) = parse_movie(&stem);
+    if title.is_empty() || GENERIC_NAMES.contains(&title.to_lowercase().as_str()) {
+        let (title, folder_year) = parse_movie(parent?);
+        return (!title.is_empty()).then_some(ParsedName::Movie {
+            title,
+            year: year.or(folder_year),
+        });
+    }
+    Some(ParsedName::Movie { title, year })
+}
+
+/// The folder holding a show's files (and its `tvshow.nfo`), if the layout
+/// has one: `Show/Season 2/05.mkv` or `Show/Show.S02E05.mkv`, but not a loose
+/// episode in a shared `TV/` folder.
+pub fn show_dir(path: &Path, show: &str) -> Option<PathBuf> {
+    let mut dir = path.parent()?;
+    if folder_name(dir).is_some_and(|name| SEASON_FOLDER.is_match(name)) {
+        dir = dir.parent()?;
+    }
+    let (title, _) = parse_movie(folder_name(dir)?);
+    title.eq_ignore_ascii_case(show).then(|| dir.to_path_buf())
+}
+
+/// Splits `Title (2010) 1080p` into its title and year.
+fn parse_movie(name: &str) -> (String, Option<i32>) {
+    let name = LEADING_TAG.replace(name, "");
+    // Only what comes before the quality tags can hold the year, and the last
+    // plausible one wins so `2001 A Space Odyssey (1968)` and
+    // `Blade.Runner.2049.1080p` both work.
+    let head = &name[..quality_start(&name)];
+    let mut year = None;
+    let mut at = 0;
+    // Each match consumes the separator after it, which may also start the
+    // next year (`2049.2017`), so searching resumes right after the digits.
+    while let Some(caps) = YEAR.captures_at(head, at) {
+        let (Some(whole), Some(digits)) = (caps.get(0), caps.get(1)) else {
+            break;
+        };
+        at = digits.end();
+        let found = digits.as_str().parse().unwrap_or_default();
+        if is_plausible_year(found) {
+            year = Some((whole.start(), found));
+        }
+    }
+    let (title, year) = match year {
+        Some((start, year)) => (&name[..start], Some(year)),
+        None => (name.as_ref(), None),
+    };
+    (clean_title(title), year)
+}
+
+/// Byte offset of the first quality tag, or the length of `name`.
+fn quality_start(name: &str) -> usize {
+    let mut offset = 0;
+    for word in name.split([' ', '.', '_']) {
+        let tag = word.trim_matches(|c| c == '[' || c == ']' || c == '(' || c == ')');
+        if QUALITY_TAGS.contains(&tag.to_lowercase().as_str()) {
+            return offset;
+        }
+        offset += word.len() + 1;
+    }
+    name.len()
+}
+
+/// Release years from the first films to next year's announced titles.
+fn is_plausible_year(year: i32) -> bool {
+    (1900..=chrono::Local::now().year() + 1).contains(&year)
+}
+
+/// Turns `The.Dark_Knight.1080p` into `The Dark Knight`.
+fn clean_title(raw: &str) -> String {
+    let spaced = raw.replace(['.', '_'], " ");
+    let mut words = Vec::new();
+    for word in spaced.split_whitespace() {
+        let lower = word.trim_matches(|c| c == '[' || c == ']' || c == '(' || c == ')');
+        if QUALITY_TAGS.contains(&lower.to_lowercase().as_str()) {
+            break;
+        }
+        words.push(word);
+    }
+    words
+        .join(" ")
+        .trim_matches(|c: char| c == '-' || c == '(' || c == '[' || c.is_whitespace())
+        .to_string()
+}
+
+fn folder_name(path: &Path) -> Option<&str> {
+    path.file_name()?.to_str()
+}
@@ -0,0 +1,289 @@
+//! Table-driven checks of file name parsing and match scoring, and NFO
+//! round trips through a temporary folder.
+
+use std::path::{Path, PathBuf};
+
+use super::matcher::confidence;
+use super::nfo::{self, NfoKind, Sidecars};
+use super::parser::{parse, ParsedName};
+use crate::services::metadata::MediaItem;
+
+fn movie(title: &str, year: Option<i32>) -> Option<ParsedName> {
+    Some(ParsedName::Movie {
+        title: title.to_string(),
+        year,
+    })
+}
+
+fn episode(show: &str, season: u32, episode: u32) -> Option<ParsedName> {
+    Some(ParsedName::Episode {
+        show: show.to_string(),
+        season,
+        episode,
+    })
+}
+
+fn item(media_type: &str, title: &str, year: i32) -> MediaItem {
+    MediaItem {
+        id: "tt1".into(),
+        media_type: media_type.to_string(),
+        title: title.to_string(),
+        year,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn parses_episode_names() {
+    let cases = [
+        ("TV/The.Office.S02E05.720p.mkv", episode("The Office", 2, 5)),
+        ("TV/the_office_s2.e5.mkv", episode("the office", 2, 5)),
+        (
+            "TV/The Office - 2x05 - Halloween.mkv",
+            episode("The Office", 2, 5),
+        ),
+        (
+            "TV/[Group] Cowboy Bebop S01E12.mkv",
+            episode("Cowboy Bebop", 1, 12),
+        ),
+        (
+            "The Office/Season 2/S02E05.mkv",
+            episode("The Office", 2, 5),
+        ),
+        ("The Office/S02E05.mkv", episode("The Office", 2, 5)),
+    ];
+    for (path, expected) in cases {
+        assert_eq!(parse(Path::new(path)), expected, "{}", path);
+    }
+}
+
+#[test]
+fn parses_episodes_from_season_folders() {
+    let cases = [
+        (
+            "The Office/Season 2/05 - Halloween.mkv",
+            episode("The Office", 2, 5),
+        ),
+        ("The Office/Season 02/E05.mkv", episode("The Office", 2, 5)),
+        (
+            "The Office/Series 3/Episode 12.mkv",
+            episode("The Office", 3, 12),
+        ),
+        ("The Office/S1/01.mkv", episode("The Office", 1, 1)),
+    ];
+    for (path, expected) in cases {
+        assert_eq!(parse(Path::new(path)), expected, "{}", path);
+    }
+}
+
+#[test]
+fn parses_movie_names() {
+    let cases = [
+        (
+            "Movies/Inception (2010).mkv",
+            movie("Inception", Some(2010)),
+        ),
+        (
+            "Movies/Inception.2010.1080p.BluRay.x264.mkv",
+            movie("Inception", Some(2010)),
+        ),
+        (
+            "Movies/The_Dark_Knight_2008_720p.mkv",
+            movie("The Dark Knight", Some(2008)),
+        ),
+        (
+            "Movies/[YTS] Heat [1995] 1080p.mp4",
+            movie("Heat", Some(1995)),
+        ),
+        ("Movies/Amelie.mkv", movie("Amelie", None)),
+        ("Movies/Amelie.1080p.mkv", movie("Amelie", None)),
+        ("Inception (2010)/movie.mkv", movie("Inception", Some(2010))),
+        (
+            "Movies/Inception (2010)/Film.mkv",
+            movie("Inception", Some(2010)),
+        ),
+    ];
+    for (path, expected) in cases {
+        assert_eq!(parse(Path::new(path)), expected, "{}", path);
+    }
+}
+
+#[test]
+fn takes_last_plausible_year_before_quality_tags() {
+    let cases = [
+        (
+            "Movies/Blade.Runner.2049.1080p.mkv",
+            movie("Blade Runner 2049", None),
+        ),
+        (
+            "Movies/Blade.Runner.2049.2017.1080p.mkv",
+            movie("Blade Runner 2049", Some(2017)),
+        ),
+        (
+            "Movies/2001 A Space Odyssey (1968).mkv",
+            movie("2001 A Space Odyssey", Some(1968)),
+        ),
+        ("Movies/1917.2019.2160p.mkv", movie("1917", Some(2019))),
+        (
+            "Movies/Heat.1995.1080p.x264-GRP2012.mkv",
+            movie("Heat", Some(1995)),
+        ),
+        ("Movies/Heat.1080p.2012.mkv", movie("Heat", None)),
+    ];
+    for (path, expected) in cases {
+        assert_eq!(parse(Path::new(path)), expected, "{}", path);
+    }
+}
+
+#[test]
+fn ignores_names_without_a_title() {
+    for path in ["1080p.mkv", "Season 1/S01E01.mkv"] {
+        assert_eq!(parse(Path::new(path)), None, "{}", path);
+    }
+}
+
+#[test]
+fn scores_candidates() {
+    let inception = movie("Inception", Some(2010)).unwrap();
+    let office = episode("The Office", 2, 5).unwrap();
+    let cases = [
+        (&inception, item("movie", "Inception", 2010), 1.0),
+        (&inception, item("movie", "Inception", 2011), 0.9),
+        (&inception, item("movie", "Inception", 2014), 0.8),
+        (&inception, item("movie", "Inception", 0), 0.9),
+        (&inception, item("series", "Inception", 2010), 0.9),
+        (&office, item("series", "The Office", 2005), 0.9),
+        (&office, item("series", "The Office!", 2005), 0.9),
+        (&office, item("series", "The Offices", 2005), 0.83),
+        (&office, item("movie", "Office Space", 1999), 0.28),
+    ];
+    for (parsed, item, expected) in cases {
+        let score = confidence(parsed, &item);
+        assert!(
+            (score - expected).abs() < 0.01,
+            "{:?} vs {:?}: {} != {}",
+            parsed,
+            item.title,
+            score,
+            expected
+        );
+    }
+}
+
+#[test]
+fn scores_original_title() {
+    let parsed = movie("Le Fabuleux Destin d Amelie Poulain", Some(2001)).unwrap();
+    let translated = item("movie", "Amelie", 2001);
+    let original = MediaItem {
+        original_title: Some("Le Fabuleux Destin d'Amélie Poulain".into()),
+        ..translated.clone()
+    };
+    assert!(confidence(&parsed, &translated) < 0.75);
+    assert!((confidence(&parsed, &original) - 1.0).abs() < 0.01);
+}
+
+/// A fresh folder for one test's media files.
+fn media_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("library-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes the item-level NFO for `file` and reads it back.
+fn round_trip(file: &Path, kind: NfoKind, item: &MediaItem) -> Option<nfo::Nfo> {
+    std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+    let parsed = parse(file).unwrap();
+    let target = nfo::item_path(file, &parsed).unwrap();
+    nfo::write(&target, kind, item).unwrap();
+    Sidecars::find(file, &parsed).item
+}
+
+#[test]
+fn round_trips_nfo_ids() {
+    let dir = media_dir("nfo-ids");
+    let show = dir.join("Breaking Bad/Season 1/Breaking.Bad.S01E01.mkv");
+    let movie = dir.join("Movies/Inception (2010).mkv");
+    let local = dir.join("Movies/Home Video (2019).mkv");
+    let cases = [
+        (&show, NfoKind::TvShow, "tmdb:tv:1396", Some("tmdb:tv:1396")),
+        (
+            &movie,
+            NfoKind::Movie,
+            "tmdb:movie:27205",
+            Some("tmdb:movie:27205"),
+        ),
+        (&movie, NfoKind::Movie, "tt1375666", Some("tt1375666")),
+        (&show, NfoKind::TvShow, "kitsu:1376", Some("kitsu:1376")),
+        (&local, NfoKind::Movie, "local:0123abcd", None),
+    ];
+    for (file, kind, id, expected) in cases {
+        let item = MediaItem {
+            id: id.to_string(),
+            ..item("movie", "Anything", 2010)
+        };
+        let read = round_trip(file, kind, &item).unwrap();
+        assert_eq!(read.metadata_id().as_deref(), expected, "{}", id);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn round_trips_nfo_fields() {
+    let dir = media_dir("nfo-fields");
+    let file = dir.join("Amelie (2001)/Amelie.2001.1080p.mkv");
+    let written = MediaItem {
+        id: "tt0211915".into(),
+        original_title: Some("Le Fabuleux Destin d'Amélie Poulain".into()),
+        description: Some("A shy waitress <decides> to help & meddle.".into()),
+        genres: vec!["Comedy".into(), "Romance".into()],
+        cast: vec!["Audrey Tautou".into()],
+        poster: "https://img.test/amelie.jpg".into(),
+        ..item("movie", "Amélie", 2001)
+    };
+
+    let nfo = round_trip(&file, NfoKind::Movie, &written).unwrap();
+    let mut read = item("movie", "Amelie", 0);
+    nfo.apply(&mut read);
+    assert_eq!(read.id, written.id);
+    assert_eq!(read.title, written.title);
+    assert_eq!(read.original_title, written.original_title);
+    assert_eq!(read.year, written.year);
+    assert_eq!(read.description, written.description);
+    assert_eq!(read.genres, written.genres);
+    assert_eq!(read.cast, written.cast);
+    assert_eq!(read.poster, written.poster);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn prefers_the_default_nfo_id() {
+    let dir = media_dir("nfo-default");
+    let file = dir.join("Cowboy Bebop/Season 1/Cowboy.Bebop.S01E01.mkv");
+    std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+    let parsed = parse(&file).unwrap();
+    let cases = [
+        (
+            r#"<uniqueid type="anidb">23</uniqueid>
+               <uniqueid type="tmdb" default="true">30991</uniqueid>"#,
+            "tmdb:tv:30991",
+        ),
+        (
+            r#"<uniqueid type="tmdb">30991</uniqueid>
+               <uniqueid type="imdb">tt0213338</uniqueid>"#,
+            "tt0213338",
+        ),
+        (
+            r#"<uniqueid type="tmdb" default="false">30991</uniqueid>
+               <uniqueid type="anidb">23</uniqueid>"#,
+            "anidb:23",
+        ),
+    ];
+    for (ids, expected) in cases {
+        let xml = format!("<tvshow><title>Cowboy Bebop</title>{}</tvshow>", ids);
+        std::fs::write(dir.join("Cowboy Bebop/tvshow.nfo"), xml).unwrap();
+        let nfo = Sidecars::find(&file, &parsed).item.unwrap();
+        assert_eq!(nfo.metadata_id().as_deref(), Some(expected), "{}", ids);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,483 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use anyhow::Result;
+use futures::future::{join_all, BoxFuture, FutureExt};
+use log::{debug, warn};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::cache::{CacheKind, Lookup, MetadataCache};
+use super::search::{SearchHit, SearchIndex};
+use super::{Alternative, MediaItem, MetadataProvider};
+use crate::config::{MergeRule, MetadataConfig};
+
+/// Fans requests out to every registered provider and merges the answers
+/// field by field according to `MetadataConfig::merge`.
+pub struct MetadataAggregator {
+    providers: Vec<Arc<dyn MetadataProvider>>,
+    config: MetadataConfig,
+    cache: Option<Arc<MetadataCache>>,
+    revalidating: Arc<Mutex<HashSet<String>>>,
+    index: RwLock<SearchIndex>,
+    /// Ids the library put in the index that no provider supplied.
+    library_ids: Mutex<HashSet<String>>,
+}
+
+type Candidate = (String, MediaItem);
+type Fetch<T> = fn(Arc<dyn MetadataProvider>, Arc<str>) -> BoxFuture<'static, Result<T>>;
+
+const CATALOG_KEY: &str = "default";
+
+impl MetadataAggregator {
+    pub fn new(config: MetadataConfig, cache: Option<Arc<MetadataCache>>) -> Self {
+        let aggregator = Self {
+            providers: Vec::new(),
+            config,
+            cache,
+            revalidating: Arc::default(),
+            index: RwLock::default(),
+            library_ids: Mutex::default(),
+        };
+        aggregator.seed_index();
+        aggregator
+    }
+
+    /// Fills the search index from whatever the cache already holds.
+    fn seed_index(&self) {
+        let Some(cache) = &self.cache else { return };
+        let mut items = Vec::new();
+        match cache.values(CacheKind::Catalog) {
+            Ok(values) => items.extend(
+                values
+                    .iter()
+                    .filter_map(|v| serde_json::from_str::<Vec<MediaItem>>(v).ok())
+                    .flatten(),
+            ),
+            Err(e) => warn!("Failed to read cached catalogs: {}", e),
+        }
+        match cache.values(CacheKind::Meta) {
+            Ok(values) => items.extend(
+                values
+                    .iter()
+                    .filter_map(|v| serde_json::from_str::<Option<MediaItem>>(v).ok())
+                    .flatten(),
+            ),
+            Err(e) => warn!("Failed to read cached metadata: {}", e),
+        }
+        debug!("Seeding search index with {} cached items", items.len());
+        self.index_items(items);
+    }
+
+    fn index_items(&self, items: impl IntoIterator<Item = MediaItem>) {
+        let mut index = match self.index.write() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        for item in items {
+            index.upsert(item);
+        }
+    }
+
+    /// Makes the local library searchable. Items only the library knows,
+    /// such as unmatched files, are dropped once they leave it; items the
+    /// providers supplied are left as they are.
+    pub fn index_library(&self, items: Vec<MediaItem>) {
+        let mut index = match self.index.write() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let mut library_ids = match self.library_ids.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let mut current = HashSet::new();
+        for item in items {
+            if library_ids.contains(&item.id) || index.get(&item.id).is_none() {
+                current.insert(item.id.clone());
+                index.upsert(item);
+            }
+        }
+        for id in library_ids.difference(&current) {
+            index.remove(id);
+        }
+        debug!("Indexed {} library-only items", current.len());
+        *library_ids = current;
+    }
+
+    /// Searches local data only, so it answers immediately.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchHit> {
+        match self.index.read() {
+            Ok(index) => index.search(query, limit),
+            Err(poisoned) => poisoned.into_inner().search(query, limit),
+        }
+    }
+
+    /// Looks up an item already in the search index without fetching.
+    pub fn indexed(&self, id: &str) -> Option<MediaItem> {
+        match self.index.read() {
+            Ok(index) => index.get(id).cloned(),
+            Err(poisoned) => poisoned.into_inner().get(id).cloned(),
+        }
+    }
+
+    pub fn register(&mut self, provider: Arc<dyn MetadataProvider>) {
+        debug!("Registering metadata provider '{}'", provider.id());
+        self.providers.push(provider);
+    }
+
+    pub fn cache(&self) -> Option<&MetadataCache> {
+        self.cache.as_deref()
+    }
+
+    pub async fn catalog(&self) -> Vec<MediaItem> {
+        let results = self
+            .fan_out(CacheKind::Catalog, CATALOG_KEY, |provider, _| {
+                async move { provider.catalog().await }.boxed()
+            })
+            .await;
+        let catalog = self.merge_lists(results);
+        self.index_items(catalog.iter().cloned());
+        catalog
+    }
+
+    /// Asks every provider for items matching `query`. Unlike `search`, this
+    /// goes to the network; the results are added to the search index.
+    pub async fn search_remote(&self, query: &str) -> Vec<MediaItem> {
+        let query = query.trim();
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let results = self
+            .fan_out(CacheKind::Search, query, |provider, query| {
+                async move { provider.search(&query).await }.boxed()
+            })
+            .await;
+        let items = self.merge_lists(results);
+        self.index_items(items.iter().cloned());
+        items
+    }
+
+    /// Groups per-provider lists by id, keeping first-seen order, and merges
+    /// each group.
+    fn merge_lists(&self, results: Vec<(String, Vec<MediaItem>)>) -> Vec<MediaItem> {
+        let mut order = Vec::new();
+        let mut groups: HashMap<String, Vec<Candidate>> = HashMap::new();
+        for (source, items) in results {
+            for item in items {
+                groups
+                    .entry(item.id.clone())
+                    .or_insert_with(|| {
+                        order.push(item.id.clone());
+                        Vec::new()
+                    })
+                    .push((source.clone(), item));
+            }
+        }
+
+        order
+            .into_iter()
+            .filter_map(|id| groups.remove(&id))
+            .map(|candidates| self.merge(candidates))
+            .collect()
+    }
+
+    pub async fn meta(&self, id: &str) -> Option<MediaItem> {
+        let candidates: Vec<Candidate> = self
+            .fan_out(CacheKind::Meta, id, |provider, id| {
+                async move { provider.meta(&id).await }.boxed()
+            })
+            .await
+            .into_iter()
+            .filter_map(|(source, item)| item.map(|item| (source, item)))
+            .collect();
+
+        if candidates.is_empty() {
+            return None;
+        }
+        let merged = self.merge(candidates);
+        self.index_items([merged.clone()]);
+        Some(merged)
+    }
+
+    /// Queries every provider concurrently and returns the successful results
+    /// in priority order. Cached answers are used when present; stale ones are
+    /// returned immediately and refreshed in the background. Failures and
+    /// timeouts are logged and dropped.
+    async fn fan_out<T>(&self, kind: CacheKind, key: &str, fetch: Fetch<T>) -> Vec<(String, T)>
+    where
+        T: Serialize + DeserializeOwned + Send + 'static,
+    {
+        let key: Arc<str> = key.into();
+        let requests = self.ranked().into_iter().map(|provider| {
+            let source = provider.id().to_string();
+            let key = key.clone();
+            async move {
+                let cache = self.cache.as_ref().filter(|_| provider.cacheable());
+                if let Some(cache) = cache {
+                    match cache.get(kind, &key, &source) {
+                        Lookup::Fresh(value) => return Some((source, value)),
+                        Lookup::Stale(value) => {
+                            self.revalidate(kind, key, provider, fetch);
+                            return Some((source, value));
+                        }
+                        Lookup::Miss => {}
+                    }
+                }
+                let value =
+                    fetch_and_store(cache.cloned(), self.timeout(), kind, key, provider, fetch)
+                        .await?;
+                Some((source, value))
+            }
+        });
+
+        join_all(requests).await.into_iter().flatten().collect()
+    }
+
+    fn revalidate<T>(
+        &self,
+        kind: CacheKind,
+        key: Arc<str>,
+        provider: Arc<dyn MetadataProvider>,
+        fetch: Fetch<T>,
+    ) where
+        T: Serialize + Send + 'static,
+    {
+        let marker = format!("{}:{}:{}", kind.as_str(), provider.id(), key);
+        {
+            let mut revalidating = match self.revalidating.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            if !revalidating.insert(marker.clone()) {
+                return;
+            }
+        }
+
+        debug!("Revalidating stale metadata entry {}", marker);
+        let cache = self.cache.clone();
+        let timeout = self.timeout();
+        let revalidating = self.revalidating.clone();
+        tokio::spawn(async move {
+            let _ = fetch_and_store(cache, timeout, kind, key, provider, fetch).await;
+            if let Ok(mut revalidating) = revalidating.lock() {
+                revalidating.remove(&marker);
+            }
+        });
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.config.provider_timeout_ms)
+    }
+
+    fn ranked(&self) -> Vec<Arc<dyn MetadataProvider>> {
+        let mut providers = self.providers.clone();
+        providers.sort_by_key(|provider| {
+            self.config
+                .provider_priority
+                .iter()
+                .position(|id| id == provider.id())
+                .unwrap_or(usize::MAX)
+        });
+        providers
+    }
+
+    /// Merges candidates that are already sorted by provider priority.
+    fn merge(&self, mut candidates: Vec<Candidate>) -> MediaItem {
+        if candidates.len() == 1 {
+            return candidates.remove(0).1;
+        }
+
+        let rules = &self.config.merge;
+        let mut alternatives = BTreeMap::new();
+        let mut merged = candidates[0].1.clone();
+
+        if let Some(i) = self.pick(
+            &candidates,
+            rules.title,
+            "title",
+            &mut alternatives,
+            |item| Some(item.title.clone()).filter(|title| !title.is_empty()),
+        ) {
+            merged.title = candidates[i].1.title.clone();
+            merged.language = candidates[i].1.language.clone();
+        }
+        if let Some(i) = self.pick(
+            &candidates,
+            rules.poster,
+            "poster",
+            &mut alternatives,
+            |item| Some(item.poster.clone()).filter(|poster| !poster.is_empty()),
+        ) {
+            merged.poster = candidates[i].1.poster.clone();
+            merged.poster_width = candidates[i].1.poster_width;
+        }
+        if let Some(i) = self.pick(&candidates, rules.year, "year", &mut alternatives, |item| {
+            Some(item.year).filter(|year| *year > 0)
+        }) {
+            merged.year = candidates[i].1.year;
+        }
+        if let Some(i) = self.pick(
+            &candidates,
+            rules.description,
+            "description",
+            &mut alternatives,
+            |item| item.description.clone(),
+        ) {
+            merged.description = candidates[i].1.description.clone();
+        }
+        merged.original_title = candidates
+            .iter()
+            .find_map(|(_, item)| item.original_title.clone());
+        merged.genres = self.pick_list(
+            &candidates,
+            rules.genres,
+            "genres",
+            &mut alternatives,
+            |item| &item.genres,
+        );
+        merged.cast = self.pick_list(&candidates, rules.cast, "cast", &mut alternatives, |item| {
+            &item.cast
+        });
+        // Episode lists are not merged; the highest-ranked list wins.
+        if merged.videos.is_empty() {
+            if let Some((_, item)) = candidates.iter().find(|(_, item)| !item.videos.is_empty()) {
+                merged.videos = item.videos.clone();
+            }
+        }
+
+        merged.alternatives = alternatives;
+        merged
+    }
+
+    /// Chooses which candidate supplies a scalar field and records the values
+    /// that lost. Returns `None` when no candidate has the field.
+    fn pick<T, F>(
+        &self,
+        candidates: &[Candidate],
+        rule: MergeRule,
+        field: &str,
+        alternatives: &mut BTreeMap<String, Vec<Alternative>>,
+        get: F,
+    ) -> Option<usize>
+    where
+        T: PartialEq + Serialize,
+        F: Fn(&MediaItem) -> Option<T>,
+    {
+        let values: Vec<(usize, T)> = candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (_, item))| get(item).map(|value| (i, value)))
+            .collect();
+        let (chosen, chosen_value) = values.get(self.choose(candidates, &values, rule))?;
+
+        let losers: Vec<Alternative> = values
+            .iter()
+            .filter(|(_, value)| value != chosen_value)
+            .filter_map(|(i, value)| {
+                Some(Alternative {
+                    source: candidates[*i].0.clone(),
+                    value: serde_json::to_value(value).ok()?,
+                })
+            })
+            .collect();
+        if !losers.is_empty() {
+            alternatives.insert(field.to_string(), losers);
+        }
+        Some(*chosen)
+    }
+
+    fn pick_list<F>(
+        &self,
+        candidates: &[Candidate],
+        rule: MergeRule,
+        field: &str,
+        alternatives: &mut BTreeMap<String, Vec<Alternative>>,
+        get: F,
+    ) -> Vec<String>
+    where
+        F: Fn(&MediaItem) -> &Vec<String>,
+    {
+        if rule == MergeRule::Union {
+            let mut union: Vec<String> = Vec::new();
+            for (_, item) in candidates {
+                for value in get(item) {
+                    if !union.iter().any(|v| v.eq_ignore_ascii_case(value)) {
+                        union.push(value.clone());
+                    }
+                }
+            }
+            return union;
+        }
+
+        self.pick(candidates, rule, field, alternatives, |item| {
+            Some(get(item).clone()).filter(|list| !list.is_empty())
+        })
+        .map(|i| get(&candidates[i].1).clone())
+        .unwrap_or_default()
+    }
+
+    /// Returns the position in `values` of the winning value.
+    fn choose<T>(&self, candidates: &[Candidate], values: &[(usize, T)], rule: MergeRule) -> usize {
+        match rule {
+            MergeRule::Priority | MergeRule::Union => 0,
+            MergeRule::PreferredLanguage => self
+                .config
+                .preferred_languages
+                .iter()
+                .find_map(|language| {
+                    values.iter().position(|(i, _)| {
+                        candidates[*i]
+                            .1
+                            .language
+                            .as_deref()
+                            .is_some_and(|l| l.eq_ignore_ascii_case(language))
+                    })
+                })
+                .unwrap_or(0),
+            // Resolution is whatever the source reported for its poster.
+            MergeRule::HighestResolution => values
+                .iter()
+                .enumerate()
+                .max_by(|(a_pos, (a, _)), (b_pos, (b, _))| {
+                    let a_width = candidates[*a].1.poster_width.unwrap_or(0);
+                    let b_width = candidates[*b].1.poster_width.unwrap_or(0);
+                    // Prefer the earlier (higher-priority) entry on ties.
+                    a_width.cmp(&b_width).then(b_pos.cmp(a_pos))
+                })
+                .map(|(pos, _)| pos)
+                .unwrap_or(0),
+        }
+    }
+}
+
+async fn fetch_and_store<T: Serialize>(
+    cache: Option<Arc<MetadataCache>>,
+    timeout: Duration,
+    kind: CacheKind,
+    key: Arc<str>,
+    provider: Arc<dyn MetadataProvider>,
+    fetch: Fetch<T>,
+) -> Option<T> {
+    let source = provider.id().to_string();
+    let value = match tokio::time::timeout(timeout, fetch(provider, key.clone())).await {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            warn!("Metadata provider '{}' failed: {}", source, e);
+            return None;
+        }
+        Err(_) => {
+            warn!("Metadata provider '{}' timed out", source);
+            return None;
+        }
+    };
+
+    if let Some(cache) = cache {
+        if let Err(e) = cache.put(kind, &key, &source, &value, cache.ttl(kind)) {
+            warn!("Failed to cache metadata from '{}': {}", source, e);
+        }
+    }
+    Some(value)
+}
@@ -0,0 +1,336 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::config::CacheConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKind {
+    Catalog,
+    Meta,
+    Search,
+}
+
+impl CacheKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CacheKind::Catalog => "catalog",
+            CacheKind::Meta => "meta",
+            CacheKind::Search => "search",
+        }
+    }
+}
+
+pub enum Lookup<T> {
+    Fresh(T),
+    /// Past its TTL but inside the stale window; serve it and refresh.
+    Stale(T),
+    Miss,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub path: PathBuf,
+    pub entries: u64,
+    pub fresh_entries: u64,
+    pub stale_entries: u64,
+    pub size_bytes: u64,
+    pub max_size_bytes: u64,
+    pub hits: u64,
+    pub stale_hits: u64,
+    pub misses: u64,
+}
+
+/// On-disk cache of provider responses keyed by kind, item id and source.
+pub struct MetadataCache {
+    conn: Mutex<Connection>,
+    path: PathBuf,
+    config: CacheConfig,
+    hits: AtomicU64,
+    stale_hits: AtomicU64,
+    misses: AtomicU64,
+    /// Total length of the stored values, kept up to date on every write so
+    /// the size limit does not need a table scan.
+    size_bytes: AtomicI64,
+}
+
+impl MetadataCache {
+    pub fn open(path: &Path, config: CacheConfig) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open metadata cache at {:?}", path))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS entries (
+                kind TEXT NOT NULL,
+                id TEXT NOT NULL,
+                source TEXT NOT NULL,
+                value TEXT NOT NULL,
+                fetched_at INTEGER NOT NULL,
+                expires_at INTEGER NOT NULL,
+                stale_until INTEGER NOT NULL,
+                accessed_at INTEGER NOT NULL,
+                PRIMARY KEY (kind, id, source)
+            );
+            CREATE INDEX IF NOT EXISTS entries_accessed_at ON entries (accessed_at);",
+        )?;
+        let size_bytes = total_size(&conn)?;
+        debug!("Opened metadata cache at {:?} ({} bytes)", path, size_bytes);
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+            path: path.to_path_buf(),
+            config,
+            hits: AtomicU64::new(0),
+            stale_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            size_bytes: AtomicI64::new(size_bytes),
+        })
+    }
+
+    pub fn ttl(&self, kind: CacheKind) -> Duration {
+        Duration::from_secs(match kind {
+            CacheKind::Catalog | CacheKind::Search => self.config.catalog_ttl_secs,
+            CacheKind::Meta => self.config.meta_ttl_secs,
+        })
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, kind: CacheKind, id: &str, source: &str) -> Lookup<T> {
+        let lookup = self.lookup(kind, id, source).unwrap_or_else(|e| {
+            warn!("Metadata cache read failed: {}", e);
+            Lookup::Miss
+        });
+        let counter = match lookup {
+            Lookup::Fresh(_) => &self.hits,
+            Lookup::Stale(_) => &self.stale_hits,
+            Lookup::Miss => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        lookup
+    }
+
+    fn lookup<T: DeserializeOwned>(
+        &self,
+        kind: CacheKind,
+        id: &str,
+        source: &str,
+    ) -> Result<Lookup<T>> {
+        let conn = self.lock();
+        let row: Option<(String, i64, i64)> = conn
+            .query_row(
+                "SELECT value, expires_at, stale_until FROM entries
+                 WHERE kind = ?1 AND id = ?2 AND source = ?3",
+                params![kind.as_str(), id, source],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+        let Some((value, expires_at, stale_until)) = row else {
+            return Ok(Lookup::Miss);
+        };
+
+        let now = now();
+        let value = match serde_json::from_str(&value) {
+            Ok(value) if now < stale_until => value,
+            _ => {
+                conn.execute(
+                    "DELETE FROM entries WHERE kind = ?1 AND id = ?2 AND source = ?3",
+                    params![kind.as_str(), id, source],
+                )?;
+                self.size_bytes
+                    .fetch_sub(value.len() as i64, Ordering::Relaxed);
+                return Ok(Lookup::Miss);
+            }
+        };
+        conn.execute(
+            "UPDATE entries SET accessed_at = ?4 WHERE kind = ?1 AND id = ?2 AND source = ?3",
+            params![kind.as_str(), id, source, now],
+        )?;
+
+        Ok(if now < expires_at {
+            Lookup::Fresh(value)
+        } else {
+            Lookup::Stale(value)
+        })
+    }
+
+    /// Stores a provider's answer for `ttl`. Empty answers (`null` or `[]`)
+    /// are kept for `negative_ttl_secs` at most and never served stale, so
+    /// an item that appears later is picked up soon.
+    pub fn put<T: Serialize>(
+        &self,
+        kind: CacheKind,
+        id: &str,
+        source: &str,
+        value: &T,
+        ttl: Duration,
+    ) -> Result<()> {
+        let value = serde_json::to_string(value)?;
+        let now = now();
+        let (expires_at, stale_until) = if value == "null" || value == "[]" {
+            let expires_at = now + ttl.as_secs().min(self.config.negative_ttl_secs) as i64;
+            (expires_at, expires_at)
+        } else {
+            let expires_at = now + ttl.as_secs() as i64;
+            (expires_at, expires_at + self.config.stale_ttl_secs as i64)
+        };
+
+        let conn = self.lock();
+        let replaced: i64 = conn
+            .query_row(
+                "SELECT LENGTH(CAST(value AS BLOB)) FROM entries
+                 WHERE kind = ?1 AND id = ?2 AND source = ?3",
+                params![kind.as_str(), id, source],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or_default();
+        conn.execute(
+            "INSERT OR REPLACE INTO entries
+             (kind, id, source, value, fetched_at, expires_at, stale_until, accessed_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?5)",
+            params![
+                kind.as_str(),
+                id,
+                source,
+                value,
+                now,
+                expires_at,
+                stale_until
+            ],
+        )?;
+        self.size_bytes
+            .fetch_add(value.len() as i64 - replaced, Ordering::Relaxed);
+        self.enforce_size_limit(&conn)
+    }
+
+    /// Returns the raw JSON of every live entry of `kind`.
+    pub fn values(&self, kind: CacheKind) -> Result<Vec<String>> {
+        let conn = self.lock();
+        let mut stmt =
+            conn.prepare("SELECT value FROM entries WHERE kind = ?1 AND stale_until > ?2")?;
+        let values = stmt
+            .query_map(params![kind.as_str(), now()], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(values)
+    }
+
+    /// Removes every entry, or only those from `source`. Returns the number removed.
+    pub fn clear(&self, source: Option<&str>) -> Result<usize> {
+        let conn = self.lock();
+        let removed = match source {
+            Some(source) => conn.execute("DELETE FROM entries WHERE source = ?1", [source])?,
+            None => conn.execute("DELETE FROM entries", [])?,
+        };
+        conn.execute_batch("VACUUM")?;
+        self.size_bytes.store(total_size(&conn)?, Ordering::Relaxed);
+        Ok(removed)
+    }
+
+    pub fn stats(&self) -> Result<CacheStats> {
+        let conn = self.lock();
+        let now = now();
+        let (entries, fresh_entries, size_bytes): (i64, i64, i64) = conn.query_row(
+            "SELECT COUNT(*),
+                    COALESCE(SUM(expires_at > ?1), 0),
+                    COALESCE(SUM(LENGTH(CAST(value AS BLOB))), 0)
+             FROM entries WHERE stale_until > ?1",
+            [now],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+
+        Ok(CacheStats {
+            path: self.path.clone(),
+            entries: entries as u64,
+            fresh_entries: fresh_entries as u64,
+            stale_entries: (entries - fresh_entries) as u64,
+            size_bytes: size_bytes as u64,
+            max_size_bytes: self.max_size_bytes(),
+            hits: self.hits.load(Ordering::Relaxed),
+            stale_hits: self.stale_hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Once the stored values outgrow `max_size_mb`, drops expired entries
+    /// and then least recently used ones until they fit again.
+    fn enforce_size_limit(&self, conn: &Connection) -> Result<()> {
+        let max = self.max_size_bytes() as i64;
+        if self.size_bytes.load(Ordering::Relaxed) <= max {
+            return Ok(());
+        }
+
+        let expired: i64 = conn.query_row(
+            "SELECT COALESCE(SUM(LENGTH(CAST(value AS BLOB))), 0) FROM entries
+             WHERE stale_until <= ?1",
+            [now()],
+            |row| row.get(0),
+        )?;
+        conn.execute("DELETE FROM entries WHERE stale_until <= ?1", [now()])?;
+        let total = self.size_bytes.fetch_sub(expired, Ordering::Relaxed) - expired;
+        if total <= max {
+            return Ok(());
+        }
+
+        let mut excess = total - max;
+        let mut stmt = conn.prepare(
+            "SELECT rowid, LENGTH(CAST(value AS BLOB)) FROM entries ORDER BY accessed_at ASC",
+        )?;
+        let mut victims = Vec::new();
+        for row in stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))? {
+            let (rowid, size) = row?;
+            victims.push((rowid, size));
+            excess -= size;
+            if excess <= 0 {
+                break;
+            }
+        }
+        for (rowid, size) in &victims {
+            conn.execute("DELETE FROM entries WHERE rowid = ?1", [rowid])?;
+            self.size_bytes.fetch_sub(*size, Ordering::Relaxed);
+        }
+        debug!("Evicted {} metadata cache entries", victims.len());
+        Ok(())
+    }
+
+    fn max_size_bytes(&self) -> u64 {
+        self.config.max_size_mb * 1024 * 1024
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Connection> {
+        match self.conn.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => {
+                log::error!("Mutex poisoned! Attempting recovery");
+                poisoned.into_inner()
+            }
+        }
+    }
+}
+
+fn total_size(conn: &Connection) -> Result<i64> {
+    Ok(conn.query_row(
+        "SELECT COALESCE(SUM(LENGTH(CAST(value AS BLOB))), 0) FROM entries",
+        [],
+        |row| row.get(0),
+    )?)
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+use super::MediaItem;
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 200;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CatalogSort {
+    /// Provider order, as merged by the aggregator.
+    #[default]
+    Default,
+    Title,
+    Year,
+}
+
+/// Arguments accepted by `getCatalog`. Every field is optional.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CatalogQuery {
+    /// Which catalog to list; omitted means the aggregated one.
+    pub catalog: Option<String>,
+    pub skip: usize,
+    pub limit: Option<usize>,
+    pub genre: Option<String>,
+    pub year: Option<i32>,
+    pub year_from: Option<i32>,
+    pub year_to: Option<i32>,
+    pub sort: CatalogSort,
+    pub descending: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CatalogPage {
+    pub items: Vec<MediaItem>,
+    pub skip: usize,
+    pub total: usize,
+    pub has_more: bool,
+    /// Value to pass as `skip` for the next page, if there is one.
+    pub next_skip: Option<usize>,
+}
+
+impl CatalogQuery {
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    fn matches(&self, item: &MediaItem) -> bool {
+        if let Some(genre) = &self.genre {
+            if !item.genres.iter().any(|g| g.eq_ignore_ascii_case(genre)) {
+                return false;
+            }
+        }
+        if self.year.is_some_and(|year| item.year != year) {
+            return false;
+        }
+        if self.year_from.is_some_and(|from| item.year < from) {
+            return false;
+        }
+        if self.year_to.is_some_and(|to| item.year > to) {
+            return false;
+        }
+        true
+    }
+
+    /// Filters, sorts and slices a full catalog into one page.
+    pub fn apply(&self, items: Vec<MediaItem>) -> CatalogPage {
+        let mut items: Vec<MediaItem> = items.into_iter().filter(|i| self.matches(i)).collect();
+
+        match self.sort {
+            CatalogSort::Default => {}
+            CatalogSort::Title => items.sort_by_cached_key(|item| item.title.to_lowercase()),
+            CatalogSort::Year => items.sort_by_key(|item| item.year),
+        }
+        if self.descending {
+            items.reverse();
+        }
+
+        let total = items.len();
+        let limit = self.limit();
+        let items: Vec<MediaItem> = items.into_iter().skip(self.skip).take(limit).collect();
+        let end = self.skip + items.len();
+        let has_more = end < total;
+
+        CatalogPage {
+            items,
+            skip: self.skip,
+            total,
+            has_more,
+            next_skip: has_more.then_some(end),
+        }
+    }
+}
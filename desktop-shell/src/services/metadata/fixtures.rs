@@ -0,0 +1,312 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use log::{debug, info};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{Map, Value};
+
+use super::{search::tokenize, MediaItem, MetadataProvider};
+use crate::services::addons::{
+    manifest::{ExtraProperty, Manifest, ManifestCatalog, ManifestResource},
+    native::Addon,
+    protocol::{CacheHints, CatalogResponse, MetaItem, MetaResponse, ResourceRequest},
+};
+
+/// Catalog entries per page when served as an addon.
+const PAGE_SIZE: usize = 100;
+
+/// Served when no fixture directory is configured, e.g. in release builds.
+const BUILTIN_CATALOG: &str = include_str!("../../../fixtures/builtin/catalog.json");
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FixtureFile<T> {
+    Wrapped(Wrapped<T>),
+    Plain(T),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct Wrapped<T> {
+    latency_ms: Option<u64>,
+    error: Option<String>,
+    data: Option<T>,
+}
+
+struct LoadedFile {
+    modified: SystemTime,
+    value: Value,
+}
+
+/// Metadata provider backed by JSON files on disk, for exercising the UI
+/// against hand-written data.
+///
+/// Layout of the fixture directory:
+///
+/// - `catalog.json`: array of media items
+/// - `meta/<id>.json`: a single media item; ids are sanitized so `kitsu:1`
+///   becomes `kitsu_1.json`. Items without a meta file fall back to their
+///   catalog entry.
+///
+/// Any fixture may instead be wrapped as
+/// `{ "latencyMs": 1500, "error": "Simulated outage", "data": ... }` to delay
+/// or fail the request. Files are re-read whenever their mtime changes.
+///
+/// Without a directory, a small catalog compiled into the binary is served
+/// instead.
+///
+/// The same fixtures are served as the native addon `native://fixtures`.
+pub struct FixtureProvider {
+    dir: Option<PathBuf>,
+    files: Mutex<HashMap<PathBuf, LoadedFile>>,
+}
+
+impl FixtureProvider {
+    pub fn new(dir: &Path) -> Self {
+        info!("Loading metadata fixtures from {:?}", dir);
+        Self {
+            dir: Some(dir.to_path_buf()),
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Serves the built-in catalog.
+    pub fn builtin() -> Self {
+        Self {
+            dir: None,
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reads a fixture, applying its injected latency and error. Returns
+    /// `Ok(None)` when the file does not exist.
+    async fn load<T: DeserializeOwned>(&self, path: &Path) -> Result<Option<T>> {
+        let Some(value) = self.read(path)? else {
+            return Ok(None);
+        };
+
+        let fixture: FixtureFile<T> =
+            serde_json::from_value(value).with_context(|| format!("Invalid fixture {:?}", path))?;
+        let (latency_ms, error, data) = match fixture {
+            FixtureFile::Wrapped(w) => (w.latency_ms, w.error, w.data),
+            FixtureFile::Plain(data) => (None, None, Some(data)),
+        };
+
+        if let Some(latency_ms) = latency_ms {
+            tokio::time::sleep(Duration::from_millis(latency_ms)).await;
+        }
+        if let Some(error) = error {
+            return Err(anyhow!("{} (injected by {:?})", error, path));
+        }
+        Ok(data)
+    }
+
+    /// Returns the parsed JSON for `path`, re-reading it if it changed.
+    fn read(&self, path: &Path) -> Result<Option<Value>> {
+        let modified = match std::fs::metadata(path) {
+            Ok(meta) => meta.modified()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut files = match self.files.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(file) = files.get(path) {
+            if file.modified == modified {
+                return Ok(Some(file.value.clone()));
+            }
+            info!("Reloading changed fixture {:?}", path);
+        } else {
+            debug!("Loading fixture {:?}", path);
+        }
+
+        let content = std::fs::read_to_string(path)?;
+        let value: Value = serde_json::from_str(&content)
+            .with_context(|| format!("Fixture {:?} is not valid JSON", path))?;
+        files.insert(
+            path.to_path_buf(),
+            LoadedFile {
+                modified,
+                value: value.clone(),
+            },
+        );
+        Ok(Some(value))
+    }
+
+    fn meta_path(&self, dir: &Path, id: &str) -> PathBuf {
+        let name: String = id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        dir.join("meta").join(format!("{}.json", name))
+    }
+}
+
+#[async_trait]
+impl MetadataProvider for FixtureProvider {
+    fn id(&self) -> &str {
+        "fixtures"
+    }
+
+    // Fixtures are local and hot-reloaded; caching would hide edits.
+    fn cacheable(&self) -> bool {
+        false
+    }
+
+    async fn catalog(&self) -> Result<Vec<MediaItem>> {
+        let Some(dir) = &self.dir else {
+            return serde_json::from_str(BUILTIN_CATALOG).context("Invalid built-in catalog");
+        };
+        Ok(self
+            .load(&dir.join("catalog.json"))
+            .await?
+            .unwrap_or_default())
+    }
+
+    async fn meta(&self, id: &str) -> Result<Option<MediaItem>> {
+        if let Some(dir) = &self.dir {
+            if let Some(item) = self.load::<MediaItem>(&self.meta_path(dir, id)).await? {
+                return Ok(Some(item));
+            }
+        }
+        Ok(MetadataProvider::catalog(self)
+            .await?
+            .into_iter()
+            .find(|item| item.id == id))
+    }
+
+    async fn search(&self, query: &str) -> Result<Vec<MediaItem>> {
+        let wanted = tokenize(query);
+        Ok(MetadataProvider::catalog(self)
+            .await?
+            .into_iter()
+            .filter(|item| {
+                let title = tokenize(&item.title);
+                wanted
+                    .iter()
+                    .all(|token| title.iter().any(|word| word.starts_with(token.as_str())))
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl Addon for FixtureProvider {
+    fn manifest(&self) -> Manifest {
+        let catalog = |media_type: &str| ManifestCatalog {
+            media_type: media_type.to_string(),
+            id: "fixtures".to_string(),
+            name: Some("Fixtures".to_string()),
+            extra: ["search", "skip"]
+                .into_iter()
+                .map(|name| ExtraProperty {
+                    name: name.to_string(),
+                    is_required: false,
+                    options: Vec::new(),
+                    options_limit: None,
+                })
+                .collect(),
+            extra_supported: Vec::new(),
+            extra_required: Vec::new(),
+        };
+        Manifest {
+            id: "fixtures".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            name: "Fixtures".to_string(),
+            description: match &self.dir {
+                Some(dir) => format!("Hand-written metadata from {:?}", dir),
+                None => "Built-in sample catalog".to_string(),
+            },
+            logo: None,
+            background: None,
+            types: vec!["movie".to_string(), "series".to_string()],
+            resources: vec![
+                ManifestResource::Short("catalog".to_string()),
+                ManifestResource::Short("meta".to_string()),
+            ],
+            catalogs: vec![catalog("movie"), catalog("series")],
+            id_prefixes: None,
+            behavior_hints: Default::default(),
+            config: Vec::new(),
+            other: Map::new(),
+        }
+    }
+
+    async fn catalog(&self, request: &ResourceRequest) -> Result<CatalogResponse> {
+        let extra = |name: &str| {
+            request
+                .extra
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let search = extra("search").map(str::to_lowercase);
+        let skip = extra("skip")
+            .and_then(|skip| skip.parse().ok())
+            .unwrap_or(0);
+
+        let metas = MetadataProvider::catalog(self)
+            .await?
+            .into_iter()
+            .filter(|item| item.media_type == request.media_type)
+            .filter(|item| {
+                search
+                    .as_ref()
+                    .is_none_or(|search| item.title.to_lowercase().contains(search))
+            })
+            .skip(skip)
+            .take(PAGE_SIZE)
+            .map(meta_item)
+            .collect();
+        Ok(CatalogResponse {
+            metas,
+            cache: CacheHints::default(),
+        })
+    }
+
+    async fn meta(&self, request: &ResourceRequest) -> Result<MetaResponse> {
+        let item = MetadataProvider::meta(self, &request.id)
+            .await?
+            .with_context(|| format!("No fixture for {:?}", request.id))?;
+        Ok(MetaResponse {
+            meta: meta_item(item),
+            cache: CacheHints::default(),
+        })
+    }
+}
+
+fn meta_item(item: MediaItem) -> MetaItem {
+    let mut other = Map::new();
+    if !item.videos.is_empty() {
+        other.insert(
+            "videos".to_string(),
+            serde_json::to_value(&item.videos).unwrap_or_default(),
+        );
+    }
+    MetaItem {
+        id: item.id,
+        media_type: item.media_type,
+        name: item.title,
+        poster: Some(item.poster).filter(|poster| !poster.is_empty()),
+        background: None,
+        description: item.description,
+        release_info: (item.year > 0).then(|| item.year.to_string()),
+        genres: item.genres,
+        cast: item.cast,
+        other,
+    }
+}
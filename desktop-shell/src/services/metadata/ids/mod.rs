@@ -0,0 +1,363 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use serde::Serialize;
+
+pub mod providers;
+pub mod store;
+
+#[cfg(test)]
+mod tests;
+
+use store::IdStore;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdScheme {
+    Imdb,
+    Tmdb,
+    Tvdb,
+    Kitsu,
+    Anilist,
+}
+
+impl IdScheme {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IdScheme::Imdb => "imdb",
+            IdScheme::Tmdb => "tmdb",
+            IdScheme::Tvdb => "tvdb",
+            IdScheme::Kitsu => "kitsu",
+            IdScheme::Anilist => "anilist",
+        }
+    }
+
+    fn from_prefix(prefix: &str) -> Option<Self> {
+        match prefix.to_ascii_lowercase().as_str() {
+            "imdb" => Some(IdScheme::Imdb),
+            "tmdb" => Some(IdScheme::Tmdb),
+            "tvdb" => Some(IdScheme::Tvdb),
+            "kitsu" => Some(IdScheme::Kitsu),
+            "anilist" => Some(IdScheme::Anilist),
+            _ => None,
+        }
+    }
+}
+
+/// Whether an id names a movie or a show. TMDB numbers the two separately,
+/// so a TMDB id means nothing without it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Movie,
+    Tv,
+}
+
+impl MediaKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MediaKind::Movie => "movie",
+            MediaKind::Tv => "tv",
+        }
+    }
+
+    /// From TMDB's path segment or a Stremio type.
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind.to_ascii_lowercase().as_str() {
+            "movie" => Some(MediaKind::Movie),
+            "tv" | "series" => Some(MediaKind::Tv),
+            _ => None,
+        }
+    }
+}
+
+/// An id in any supported scheme, optionally pointing at one episode.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExternalId {
+    pub scheme: IdScheme,
+    /// Only kept for TMDB ids.
+    pub kind: Option<MediaKind>,
+    pub value: String,
+    pub season: Option<u32>,
+    /// Seasonal when `season` is set, absolute otherwise.
+    pub episode: Option<u32>,
+}
+
+impl ExternalId {
+    /// Parses Stremio-style ids: `tt0903747`, `tt0903747:2:5`,
+    /// `tmdb:tv:1396`, `tmdb:1396`, `kitsu:7442:13` (absolute episode 13).
+    pub fn parse(raw: &str) -> Option<Self> {
+        let mut parts = raw.trim().split(':');
+        let first = parts.next()?;
+        let is_imdb = first.len() > 2
+            && first.starts_with("tt")
+            && first[2..].bytes().all(|b| b.is_ascii_digit());
+        let (scheme, mut value) = if is_imdb {
+            (IdScheme::Imdb, first)
+        } else {
+            (IdScheme::from_prefix(first)?, parts.next()?)
+        };
+        let mut kind = None;
+        if scheme == IdScheme::Tmdb {
+            if let Some(parsed) = MediaKind::parse(value) {
+                kind = Some(parsed);
+                value = parts.next()?;
+            }
+        }
+        if value.is_empty() {
+            return None;
+        }
+
+        let numbers: Vec<u32> = parts.map(|p| p.parse().ok()).collect::<Option<_>>()?;
+        let (season, episode) = match numbers[..] {
+            [] => (None, None),
+            [episode] => (None, Some(episode)),
+            [season, episode] => (Some(season), Some(episode)),
+            _ => return None,
+        };
+        // Only shows have episodes.
+        if scheme == IdScheme::Tmdb && episode.is_some() {
+            kind = Some(MediaKind::Tv);
+        }
+        Some(Self {
+            scheme,
+            kind,
+            value: value.to_string(),
+            season,
+            episode,
+        })
+    }
+
+    /// What the id map stores the mapping under: the value, qualified by
+    /// the kind when there is one.
+    pub fn key(&self) -> String {
+        match self.kind {
+            Some(kind) => format!("{}:{}", kind.as_str(), self.value),
+            None => self.value.clone(),
+        }
+    }
+}
+
+/// Where a non-IMDb id points in IMDb terms. Anime databases list each
+/// season separately, so their entries also name the season.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub imdb: String,
+    pub season: Option<u32>,
+}
+
+/// An id resolved to the canonical IMDb scheme.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Resolved {
+    /// `tt...` for titles, `tt...:<season>:<episode>` for episodes.
+    pub id: String,
+    pub imdb: String,
+    pub season: Option<u32>,
+    pub episode: Option<u32>,
+    /// `canonical`, or the provider the mapping came from.
+    pub source: String,
+}
+
+/// Maps ids in other schemes to IMDb.
+#[async_trait]
+pub trait IdProvider: Send + Sync {
+    fn id(&self) -> &str;
+    async fn lookup(&self, id: &ExternalId) -> Result<Option<Mapping>>;
+    /// `(season, episode count)` for each season of an IMDb series.
+    async fn seasons(&self, _imdb: &str) -> Result<Option<Vec<(u32, u32)>>> {
+        Ok(None)
+    }
+    /// Refreshes any bulk data the provider keeps locally.
+    async fn refresh(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Resolves ids through the local table first and providers after,
+/// remembering every provider answer.
+pub struct IdResolver {
+    store: Option<IdStore>,
+    providers: Vec<Arc<dyn IdProvider>>,
+    timeout: Duration,
+}
+
+impl IdResolver {
+    pub fn new(store: Option<IdStore>, timeout: Duration) -> Self {
+        Self {
+            store,
+            providers: Vec::new(),
+            timeout,
+        }
+    }
+
+    pub fn register(&mut self, provider: Arc<dyn IdProvider>) {
+        debug!("Registering id provider '{}'", provider.id());
+        self.providers.push(provider);
+    }
+
+    pub async fn refresh(&self) {
+        for provider in &self.providers {
+            if let Err(e) = provider.refresh().await {
+                warn!("Id provider '{}' failed to refresh: {}", provider.id(), e);
+            }
+        }
+    }
+
+    /// Resolves `raw`, taking a TMDB id without a kind to be of
+    /// `media_type` (a Stremio type) when given.
+    pub async fn resolve(&self, raw: &str, media_type: Option<&str>) -> Result<Resolved> {
+        let mut id =
+            ExternalId::parse(raw).with_context(|| format!("Unrecognized id {:?}", raw))?;
+        if id.scheme == IdScheme::Tmdb && id.kind.is_none() {
+            id.kind = media_type.and_then(MediaKind::parse);
+            if id.kind.is_none() {
+                bail!("TMDB id {:?} needs a type, as in tmdb:movie:<id>", raw);
+            }
+        }
+        let (mapping, source) = match id.scheme {
+            IdScheme::Imdb => (
+                Mapping {
+                    imdb: id.value.clone(),
+                    season: None,
+                },
+                "canonical".to_string(),
+            ),
+            _ => self
+                .mapping(&id)
+                .await?
+                .with_context(|| format!("No IMDb mapping for {:?}", raw))?,
+        };
+
+        let (season, episode) = match (id.season, id.episode, mapping.season) {
+            (Some(season), Some(episode), _) => (Some(season), Some(episode)),
+            // Per-season anime entries count episodes from 1 within the season.
+            (None, Some(episode), Some(season)) => (Some(season), Some(episode)),
+            (None, Some(absolute), None) => {
+                let (season, episode) = self.seasonal(&mapping.imdb, absolute).await?;
+                (Some(season), Some(episode))
+            }
+            _ => (None, None),
+        };
+
+        let id = match (season, episode) {
+            (Some(season), Some(episode)) => format!("{}:{}:{}", mapping.imdb, season, episode),
+            _ => mapping.imdb.clone(),
+        };
+        Ok(Resolved {
+            id,
+            imdb: mapping.imdb,
+            season,
+            episode,
+            source,
+        })
+    }
+
+    /// The canonical form of `raw`, or `raw` itself when it cannot be mapped.
+    pub async fn canonical(&self, raw: &str, media_type: Option<&str>) -> String {
+        match self.resolve(raw, media_type).await {
+            Ok(resolved) => resolved.id,
+            Err(e) => {
+                debug!("Keeping id {:?} as given: {}", raw, e);
+                raw.to_string()
+            }
+        }
+    }
+
+    async fn mapping(&self, id: &ExternalId) -> Result<Option<(Mapping, String)>> {
+        if let Some(store) = &self.store {
+            match store.get(id.scheme, &id.key()) {
+                Ok(Some(found)) => return Ok(Some(found)),
+                Ok(None) => {}
+                Err(e) => warn!("Id map read failed: {}", e),
+            }
+        }
+
+        for provider in &self.providers {
+            let mapping = match tokio::time::timeout(self.timeout, provider.lookup(id)).await {
+                Ok(Ok(Some(mapping))) => mapping,
+                Ok(Ok(None)) => continue,
+                Ok(Err(e)) => {
+                    warn!("Id provider '{}' failed: {}", provider.id(), e);
+                    continue;
+                }
+                Err(_) => {
+                    warn!("Id provider '{}' timed out", provider.id());
+                    continue;
+                }
+            };
+            info!(
+                "Mapped {}:{} to {} via '{}'",
+                id.scheme.as_str(),
+                id.key(),
+                mapping.imdb,
+                provider.id()
+            );
+            if let Some(store) = &self.store {
+                if let Err(e) = store.put(id.scheme, &id.key(), &mapping, provider.id()) {
+                    warn!("Id map write failed: {}", e);
+                }
+            }
+            return Ok(Some((mapping, provider.id().to_string())));
+        }
+        Ok(None)
+    }
+
+    /// Converts an absolute episode number into `(season, episode)`. Stored
+    /// season lengths are re-fetched when the episode lies beyond them, since
+    /// a running show keeps growing.
+    async fn seasonal(&self, imdb: &str, absolute: u32) -> Result<(u32, u32)> {
+        if absolute == 0 {
+            bail!("Absolute episode numbers start at 1");
+        }
+        let stored = match &self.store {
+            Some(store) => store.seasons(imdb).unwrap_or_default(),
+            None => Vec::new(),
+        };
+        if let Some(found) = split_absolute(&stored, absolute) {
+            return Ok(found);
+        }
+
+        for provider in &self.providers {
+            let seasons = match tokio::time::timeout(self.timeout, provider.seasons(imdb)).await {
+                Ok(Ok(Some(seasons))) => seasons,
+                Ok(Ok(None)) => continue,
+                Ok(Err(e)) => {
+                    warn!("Id provider '{}' failed: {}", provider.id(), e);
+                    continue;
+                }
+                Err(_) => {
+                    warn!("Id provider '{}' timed out", provider.id());
+                    continue;
+                }
+            };
+            if let Some(store) = &self.store {
+                if let Err(e) = store.put_seasons(imdb, &seasons) {
+                    warn!("Id map write failed: {}", e);
+                }
+            }
+            if let Some(found) = split_absolute(&seasons, absolute) {
+                return Ok(found);
+            }
+        }
+        Err(anyhow!(
+            "Cannot place absolute episode {} of {}: season lengths unknown",
+            absolute,
+            imdb
+        ))
+    }
+}
+
+fn split_absolute(seasons: &[(u32, u32)], absolute: u32) -> Option<(u32, u32)> {
+    let mut remaining = absolute;
+    for &(season, episodes) in seasons.iter().filter(|(season, _)| *season > 0) {
+        if remaining <= episodes {
+            return Some((season, remaining));
+        }
+        remaining -= episodes;
+    }
+    None
+}
@@ -0,0 +1,274 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::RwLock;
+
+use super::{ExternalId, IdProvider, IdScheme, Mapping};
+
+/// Maps Kitsu and AniList ids using the community anime-lists dataset
+/// (`anime-list-full.json`), which lists each anime season with its IMDb id
+/// and TVDB season number. The list is kept on disk and re-downloaded when
+/// older than the configured interval.
+pub struct AnimeListProvider {
+    url: String,
+    file: PathBuf,
+    max_age: Duration,
+    client: reqwest::Client,
+    entries: RwLock<HashMap<(IdScheme, String), Mapping>>,
+}
+
+#[derive(Deserialize)]
+struct AnimeEntry {
+    imdb_id: Option<String>,
+    kitsu_id: Option<u64>,
+    anilist_id: Option<u64>,
+    season: Option<AnimeSeason>,
+}
+
+#[derive(Deserialize)]
+struct AnimeSeason {
+    tvdb: Option<u32>,
+}
+
+impl AnimeListProvider {
+    pub fn new(url: &str, file: &Path, max_age: Duration) -> Result<Self> {
+        Ok(Self {
+            url: url.to_string(),
+            file: file.to_path_buf(),
+            max_age,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(120))
+                .build()?,
+            entries: RwLock::new(HashMap::new()),
+        })
+    }
+
+    fn is_fresh(&self) -> bool {
+        std::fs::metadata(&self.file)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age < self.max_age)
+    }
+
+    async fn download(&self) -> Result<()> {
+        info!("Downloading anime id list from {}", self.url);
+        let body = self
+            .client
+            .get(&self.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        // Make sure it parses before replacing a working copy.
+        serde_json::from_slice::<Vec<Value>>(&body).context("Anime id list is not a JSON array")?;
+
+        if let Some(dir) = self.file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = self.file.with_extension("json.tmp");
+        std::fs::write(&tmp, &body)?;
+        std::fs::rename(&tmp, &self.file)?;
+        Ok(())
+    }
+
+    async fn load(&self) -> Result<()> {
+        let content = std::fs::read(&self.file)?;
+        // Entries are parsed one by one so a single odd record is skipped
+        // rather than failing the whole list.
+        let raw: Vec<Value> = serde_json::from_slice(&content)?;
+        let mut entries = HashMap::new();
+        for entry in raw {
+            let Ok(entry) = serde_json::from_value::<AnimeEntry>(entry) else {
+                continue;
+            };
+            // Movies compiled from several parts list every IMDb id.
+            let Some(imdb) = entry
+                .imdb_id
+                .as_deref()
+                .and_then(|ids| ids.split(',').next())
+                .map(str::trim)
+                .filter(|id| id.starts_with("tt"))
+            else {
+                continue;
+            };
+            let mapping = Mapping {
+                imdb: imdb.to_string(),
+                season: entry.season.and_then(|s| s.tvdb),
+            };
+            if let Some(kitsu) = entry.kitsu_id {
+                entries.insert((IdScheme::Kitsu, kitsu.to_string()), mapping.clone());
+            }
+            if let Some(anilist) = entry.anilist_id {
+                entries.insert((IdScheme::Anilist, anilist.to_string()), mapping);
+            }
+        }
+        info!("Loaded {} anime id mappings", entries.len());
+        *self.entries.write().await = entries;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl IdProvider for AnimeListProvider {
+    fn id(&self) -> &str {
+        "anime-lists"
+    }
+
+    async fn lookup(&self, id: &ExternalId) -> Result<Option<Mapping>> {
+        if !matches!(id.scheme, IdScheme::Kitsu | IdScheme::Anilist) {
+            return Ok(None);
+        }
+        Ok(self
+            .entries
+            .read()
+            .await
+            .get(&(id.scheme, id.value.clone()))
+            .cloned())
+    }
+
+    async fn refresh(&self) -> Result<()> {
+        if !self.is_fresh() {
+            if let Err(e) = self.download().await {
+                // An old copy still beats nothing.
+                if !self.file.exists() {
+                    return Err(e);
+                }
+                warn!("Keeping previous anime id list: {}", e);
+            }
+        }
+        self.load().await
+    }
+}
+
+const TMDB_API: &str = "https://api.themoviedb.org/3";
+
+/// Maps TMDB and TVDB ids through the TMDB API, and supplies season lengths
+/// for absolute episode numbering.
+pub struct TmdbProvider {
+    api_key: String,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct ExternalIds {
+    imdb_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FindResults {
+    #[serde(default)]
+    tv_results: Vec<FindResult>,
+}
+
+#[derive(Deserialize)]
+struct FindResult {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct TvDetails {
+    #[serde(default)]
+    seasons: Vec<TvSeason>,
+}
+
+#[derive(Deserialize)]
+struct TvSeason {
+    season_number: u32,
+    episode_count: u32,
+}
+
+impl TmdbProvider {
+    pub fn new(api_key: &str) -> Self {
+        Self {
+            api_key: api_key.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// GETs a TMDB endpoint; `None` when TMDB has no such resource.
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Option<T>> {
+        let response = self
+            .client
+            .get(format!("{}{}", TMDB_API, path))
+            .query(&[("api_key", self.api_key.as_str())])
+            .query(query)
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(response.error_for_status()?.json().await?))
+    }
+
+    async fn imdb_for(&self, kind: &str, tmdb: &str) -> Result<Option<String>> {
+        let ids: Option<ExternalIds> = self
+            .get(&format!("/{}/{}/external_ids", kind, tmdb), &[])
+            .await?;
+        Ok(ids
+            .and_then(|ids| ids.imdb_id)
+            .filter(|id| id.starts_with("tt")))
+    }
+
+    async fn find_tv(&self, external_id: &str, source: &str) -> Result<Option<u64>> {
+        let found: Option<FindResults> = self
+            .get(
+                &format!("/find/{}", external_id),
+                &[("external_source", source)],
+            )
+            .await?;
+        Ok(found.and_then(|f| f.tv_results.first().map(|r| r.id)))
+    }
+}
+
+#[async_trait]
+impl IdProvider for TmdbProvider {
+    fn id(&self) -> &str {
+        "tmdb"
+    }
+
+    async fn lookup(&self, id: &ExternalId) -> Result<Option<Mapping>> {
+        let imdb = match id.scheme {
+            // Movie and TV ids overlap on TMDB, so one without a kind could
+            // be either.
+            IdScheme::Tmdb => match id.kind {
+                Some(kind) => self.imdb_for(kind.as_str(), &id.value).await?,
+                None => None,
+            },
+            IdScheme::Tvdb => match self.find_tv(&id.value, "tvdb_id").await? {
+                Some(tmdb) => self.imdb_for("tv", &tmdb.to_string()).await?,
+                None => None,
+            },
+            _ => return Ok(None),
+        };
+        Ok(imdb.map(|imdb| Mapping { imdb, season: None }))
+    }
+
+    async fn seasons(&self, imdb: &str) -> Result<Option<Vec<(u32, u32)>>> {
+        let Some(tmdb) = self.find_tv(imdb, "imdb_id").await? else {
+            return Ok(None);
+        };
+        let details: Option<TvDetails> = self.get(&format!("/tv/{}", tmdb), &[]).await?;
+        let seasons: Vec<(u32, u32)> = details
+            .map(|d| d.seasons)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|s| (s.season_number, s.episode_count))
+            .collect();
+        debug!("TMDB lists {} seasons for {}", seasons.len(), imdb);
+        Ok((!seasons.is_empty()).then_some(seasons))
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use log::debug;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{IdScheme, Mapping};
+
+/// Persistent table of ids already mapped to IMDb, plus the season lengths
+/// needed to turn absolute episode numbers into seasonal ones.
+///
+/// Mappings are keyed by `ExternalId::key`, so TMDB movies and shows with
+/// the same number (`movie:603`, `tv:603`) are kept apart.
+pub struct IdStore {
+    conn: Mutex<Connection>,
+}
+
+impl IdStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open id map at {:?}", path))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS mappings (
+                scheme TEXT NOT NULL,
+                value TEXT NOT NULL,
+                imdb TEXT NOT NULL,
+                season INTEGER,
+                source TEXT NOT NULL,
+                updated_at INTEGER NOT NULL,
+                PRIMARY KEY (scheme, value)
+            );
+            CREATE TABLE IF NOT EXISTS seasons (
+                imdb TEXT NOT NULL,
+                season INTEGER NOT NULL,
+                episodes INTEGER NOT NULL,
+                PRIMARY KEY (imdb, season)
+            );",
+        )?;
+        debug!("Opened id map at {:?}", path);
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn get(&self, scheme: IdScheme, value: &str) -> Result<Option<(Mapping, String)>> {
+        let conn = self.lock();
+        let row = conn
+            .query_row(
+                "SELECT imdb, season, source FROM mappings WHERE scheme = ?1 AND value = ?2",
+                params![scheme.as_str(), value],
+                |row| {
+                    let mapping = Mapping {
+                        imdb: row.get(0)?,
+                        season: row.get(1)?,
+                    };
+                    Ok((mapping, row.get(2)?))
+                },
+            )
+            .optional()?;
+        Ok(row)
+    }
+
+    pub fn put(
+        &self,
+        scheme: IdScheme,
+        value: &str,
+        mapping: &Mapping,
+        source: &str,
+    ) -> Result<()> {
+        self.lock().execute(
+            "INSERT OR REPLACE INTO mappings (scheme, value, imdb, season, source, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                scheme.as_str(),
+                value,
+                mapping.imdb,
+                mapping.season,
+                source,
+                now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Episode counts of the regular seasons, in order. Specials (season 0)
+    /// are not part of absolute numbering and are never stored.
+    pub fn seasons(&self, imdb: &str) -> Result<Vec<(u32, u32)>> {
+        let conn = self.lock();
+        let mut stmt = conn.prepare(
+            "SELECT season, episodes FROM seasons WHERE imdb = ?1 AND season > 0 ORDER BY season",
+        )?;
+        let rows = stmt
+            .query_map(params![imdb], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rows)
+    }
+
+    pub fn put_seasons(&self, imdb: &str, seasons: &[(u32, u32)]) -> Result<()> {
+        let mut conn = self.lock();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM seasons WHERE imdb = ?1", params![imdb])?;
+        for (season, episodes) in seasons.iter().filter(|(season, _)| *season > 0) {
+            tx.execute(
+                "INSERT INTO seasons (imdb, season, episodes) VALUES (?1, ?2, ?3)",
+                params![imdb, season, episodes],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Connection> {
+        match self.conn.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => {
+                log::error!("Mutex poisoned! Attempting recovery");
+                poisoned.into_inner()
+            }
+        }
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
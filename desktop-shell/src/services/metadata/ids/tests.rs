@@ -0,0 +1,231 @@
+//! Id parsing and resolution against a stub id provider.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::store::IdStore;
+use super::{split_absolute, ExternalId, IdProvider, IdResolver, IdScheme, Mapping, MediaKind};
+
+fn id(
+    scheme: IdScheme,
+    kind: Option<MediaKind>,
+    value: &str,
+    season: Option<u32>,
+    episode: Option<u32>,
+) -> Option<ExternalId> {
+    Some(ExternalId {
+        scheme,
+        kind,
+        value: value.to_string(),
+        season,
+        episode,
+    })
+}
+
+/// Maps Kitsu 1 to a show split into seasons, Kitsu 2 to the third season
+/// of another and TMDB tv 1396 to Breaking Bad.
+struct StubProvider {
+    seasons: Mutex<Vec<(u32, u32)>>,
+    season_requests: AtomicUsize,
+}
+
+impl StubProvider {
+    fn new(seasons: Vec<(u32, u32)>) -> Arc<Self> {
+        Arc::new(Self {
+            seasons: Mutex::new(seasons),
+            season_requests: AtomicUsize::new(0),
+        })
+    }
+}
+
+#[async_trait]
+impl IdProvider for StubProvider {
+    fn id(&self) -> &str {
+        "stub"
+    }
+
+    async fn lookup(&self, id: &ExternalId) -> Result<Option<Mapping>> {
+        let mapping = |imdb: &str, season| Mapping {
+            imdb: imdb.to_string(),
+            season,
+        };
+        Ok(match (id.scheme, id.kind, id.value.as_str()) {
+            (IdScheme::Kitsu, _, "1") => Some(mapping("tt0388629", None)),
+            (IdScheme::Kitsu, _, "2") => Some(mapping("tt2560140", Some(3))),
+            (IdScheme::Tmdb, Some(MediaKind::Tv), "1396") => Some(mapping("tt0903747", None)),
+            _ => None,
+        })
+    }
+
+    async fn seasons(&self, _imdb: &str) -> Result<Option<Vec<(u32, u32)>>> {
+        self.season_requests.fetch_add(1, Ordering::Relaxed);
+        Ok(Some(self.seasons.lock().unwrap().clone()))
+    }
+}
+
+fn resolver(provider: Arc<StubProvider>, store: Option<IdStore>) -> IdResolver {
+    let mut resolver = IdResolver::new(store, Duration::from_secs(1));
+    resolver.register(provider);
+    resolver
+}
+
+#[test]
+fn parses_external_ids() {
+    use IdScheme::*;
+    let tv = Some(MediaKind::Tv);
+    let movie = Some(MediaKind::Movie);
+    let cases = [
+        ("tt0903747", id(Imdb, None, "tt0903747", None, None)),
+        (
+            " tt0903747:2:5 ",
+            id(Imdb, None, "tt0903747", Some(2), Some(5)),
+        ),
+        ("imdb:tt0903747", id(Imdb, None, "tt0903747", None, None)),
+        ("tmdb:tv:1396", id(Tmdb, tv, "1396", None, None)),
+        ("tmdb:movie:27205", id(Tmdb, movie, "27205", None, None)),
+        (
+            "TMDB:series:1396:1:2",
+            id(Tmdb, tv, "1396", Some(1), Some(2)),
+        ),
+        ("tmdb:1396", id(Tmdb, None, "1396", None, None)),
+        // Only shows have episodes.
+        ("tmdb:1396:1:2", id(Tmdb, tv, "1396", Some(1), Some(2))),
+        ("tvdb:81189", id(Tvdb, None, "81189", None, None)),
+        ("kitsu:7442:13", id(Kitsu, None, "7442", None, Some(13))),
+        (
+            "anilist:16498:1:3",
+            id(Anilist, None, "16498", Some(1), Some(3)),
+        ),
+    ];
+    for (raw, expected) in cases {
+        assert_eq!(ExternalId::parse(raw), expected, "{}", raw);
+    }
+}
+
+#[test]
+fn rejects_malformed_ids() {
+    for raw in [
+        "",
+        "tt",
+        "ttabc",
+        "the-office",
+        "mal:1",
+        "kitsu",
+        "kitsu:",
+        "tmdb:tv",
+        "kitsu:7442:x",
+        "tt0903747:1:2:3",
+    ] {
+        assert_eq!(ExternalId::parse(raw), None, "{}", raw);
+    }
+}
+
+#[test]
+fn keys_tmdb_ids_by_kind() {
+    let key = |raw: &str| ExternalId::parse(raw).unwrap().key();
+    assert_eq!(key("tmdb:tv:1396"), "tv:1396");
+    assert_eq!(key("tmdb:movie:1396"), "movie:1396");
+    assert_eq!(key("kitsu:7442"), "7442");
+}
+
+#[test]
+fn splits_absolute_episode_numbers() {
+    let seasons = [(0, 3), (1, 26), (2, 26), (3, 12)];
+    let cases = [
+        (1, Some((1, 1))),
+        (26, Some((1, 26))),
+        (27, Some((2, 1))),
+        (52, Some((2, 26))),
+        (64, Some((3, 12))),
+        (65, None),
+    ];
+    for (absolute, expected) in cases {
+        assert_eq!(split_absolute(&seasons, absolute), expected, "{}", absolute);
+    }
+    assert_eq!(split_absolute(&[], 1), None);
+}
+
+#[tokio::test]
+async fn resolves_absolute_anime_episodes_to_seasons() {
+    let resolver = resolver(StubProvider::new(vec![(0, 2), (1, 12), (2, 12)]), None);
+
+    let resolved = resolver.resolve("kitsu:1:13", None).await.unwrap();
+    assert_eq!(resolved.id, "tt0388629:2:1");
+    assert_eq!((resolved.season, resolved.episode), (Some(2), Some(1)));
+    assert_eq!(resolved.source, "stub");
+
+    // Explicit seasons are kept as given.
+    let resolved = resolver.resolve("kitsu:1:1:13", None).await.unwrap();
+    assert_eq!(resolved.id, "tt0388629:1:13");
+
+    assert!(resolver.resolve("kitsu:1:25", None).await.is_err());
+    assert!(resolver.resolve("kitsu:1:0", None).await.is_err());
+}
+
+#[tokio::test]
+async fn numbers_per_season_entries_within_their_season() {
+    let resolver = resolver(StubProvider::new(Vec::new()), None);
+
+    let resolved = resolver.resolve("kitsu:2:5", None).await.unwrap();
+    assert_eq!(resolved.id, "tt2560140:3:5");
+
+    let resolved = resolver.resolve("kitsu:2", None).await.unwrap();
+    assert_eq!(resolved.id, "tt2560140");
+    assert_eq!(resolved.season, None);
+}
+
+#[tokio::test]
+async fn refetches_season_lengths_for_later_episodes() {
+    let path = std::env::temp_dir().join(format!("id-map-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let provider = StubProvider::new(vec![(1, 12)]);
+    let resolver = resolver(provider.clone(), Some(IdStore::open(&path).unwrap()));
+
+    assert_eq!(resolver.canonical("kitsu:1:5", None).await, "tt0388629:1:5");
+    assert_eq!(resolver.canonical("kitsu:1:6", None).await, "tt0388629:1:6");
+    assert_eq!(provider.season_requests.load(Ordering::Relaxed), 1);
+
+    // A new season aired since the lengths were stored.
+    provider.seasons.lock().unwrap().push((2, 10));
+    assert_eq!(
+        resolver.canonical("kitsu:1:13", None).await,
+        "tt0388629:2:1"
+    );
+    assert_eq!(provider.season_requests.load(Ordering::Relaxed), 2);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn needs_a_kind_for_tmdb_ids() {
+    let resolver = resolver(StubProvider::new(Vec::new()), None);
+
+    assert!(resolver.resolve("tmdb:1396", None).await.is_err());
+    let resolved = resolver.resolve("tmdb:1396", Some("series")).await.unwrap();
+    assert_eq!(resolved.id, "tt0903747");
+    // The movie with the same number is a different title.
+    assert!(resolver.resolve("tmdb:movie:1396", None).await.is_err());
+    assert_eq!(
+        resolver.canonical("tmdb:tv:1396:1:2", None).await,
+        "tt0903747:1:2"
+    );
+    assert_eq!(resolver.canonical("tmdb:1396", None).await, "tmdb:1396");
+}
+
+#[tokio::test]
+async fn passes_imdb_ids_through() {
+    let resolver = resolver(StubProvider::new(Vec::new()), None);
+
+    let resolved = resolver.resolve("tt0903747:2:5", None).await.unwrap();
+    assert_eq!(resolved.id, "tt0903747:2:5");
+    assert_eq!(resolved.source, "canonical");
+    assert_eq!(resolver.canonical("kitsu:99", None).await, "kitsu:99");
+}
@@ -1,47 +1,97 @@
-use serde::Serialize;
+use std::collections::BTreeMap;
 
-#[derive(Serialize, Clone)]
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub mod aggregator;
+pub mod cache;
+pub mod catalog;
+pub mod fixtures;
+pub mod ids;
+pub mod search;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
 pub struct MediaItem {
     pub id: String,
+    #[serde(rename = "type", default = "default_media_type")]
+    pub media_type: String,
     pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_title: Option<String>,
+    #[serde(default)]
     pub year: i32,
+    #[serde(default)]
     pub poster: String,
+    /// Pixel width of `poster` when the source reports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poster_width: Option<u32>,
+    /// Language of `title`, as an ISO 639-1 code.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub genres: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cast: Vec<String>,
+    /// Episodes of a series.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub videos: Vec<Video>,
+    /// Values from other sources that lost the merge, keyed by field name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub alternatives: BTreeMap<String, Vec<Alternative>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Video {
+    /// `<series id>:<season>:<episode>` for episodes.
+    pub id: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub season: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub episode: Option<u32>,
+    /// Air date; missing while unannounced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub released: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overview: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
 }
 
-#[allow(dead_code)]
-pub struct MockMetadataService {
-    mock_catalog: Vec<MediaItem>,
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Alternative {
+    pub source: String,
+    pub value: serde_json::Value,
 }
 
-impl MockMetadataService {
-    pub fn new() -> Self {
-        // Initialize mock_catalog with test data
-
-        Self {
-            mock_catalog: vec![
-                MediaItem {
-                    id: "tt1375666".into(),
-                    title: "Inception".into(),
-                    year: 2010,
-                    poster: "https://m.media-amazon.com/images/M/MV5BMjAxMzY3NjcxNF5BMl5BanBnXkFtZTcwNTI5OTM0Mw@@._V1_FMjpg_UX1000_.jpg".into(),
-                },
-                MediaItem {
-                    id: "tt0816692".into(),
-                    title: "Interstellar".into(),
-                    year: 2014,
-                    poster: "https://m.media-amazon.com/images/M/MV5BZjdkOTU3MDktN2IxOS00OGEyLWFmMjktY2FiMmZkNWIyODZiXkEyXkFqcGdeQXVyMTMxODk2OTU@._V1_FMjpg_UX1000_.jpg".into(),
-                },
-                MediaItem {
-                    id: "tt0137523".into(),
-                    title: "Fight Club".into(),
-                    year: 1999,
-                    poster: "https://m.media-amazon.com/images/M/MV5BNDIzNDU0YzEtYzE5Ni00ZjlkLTk5ZjgtNjM3NWE4YzA3Nzk3XkEyXkFqcGdeQXVyMjUzOTY1NTc@._V1_FMjpg_UX1000_.jpg".into(),
-                }
-            ],
-        }
-    }
+fn default_media_type() -> String {
+    "movie".into()
+}
 
-    pub fn get_catalog(&self) -> &Vec<MediaItem> {
-        &self.mock_catalog
+/// A source of catalog entries and item details.
+#[async_trait]
+pub trait MetadataProvider: Send + Sync {
+    fn id(&self) -> &str;
+    /// Whether the aggregator may keep this provider's answers in the
+    /// metadata cache.
+    fn cacheable(&self) -> bool {
+        true
+    }
+    async fn catalog(&self) -> Result<Vec<MediaItem>>;
+    async fn meta(&self, id: &str) -> Result<Option<MediaItem>>;
+    /// Items whose title matches `query`. Providers without a search
+    /// endpoint return nothing.
+    async fn search(&self, _query: &str) -> Result<Vec<MediaItem>> {
+        Ok(Vec::new())
     }
 }
@@ -0,0 +1,262 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::Bound,
+};
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use super::MediaItem;
+
+#[cfg(test)]
+mod tests;
+
+const DEFAULT_LIMIT: usize = 20;
+const MAX_LIMIT: usize = 100;
+
+const TITLE_WEIGHT: f32 = 3.0;
+const CAST_WEIGHT: f32 = 1.5;
+const DESCRIPTION_WEIGHT: f32 = 1.0;
+
+/// Sent once the providers have answered a `search`, with the hits ranked
+/// again over everything now in the index.
+pub const SEARCH_RESULTS_EVENT: &str = "searchResults";
+
+#[derive(Debug, Deserialize)]
+pub struct SearchArgs {
+    pub query: String,
+    pub limit: Option<usize>,
+}
+
+impl SearchArgs {
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub item: MediaItem,
+    pub score: f32,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResults {
+    pub request_id: String,
+    pub query: String,
+    pub hits: Vec<SearchHit>,
+}
+
+struct Document {
+    item: MediaItem,
+    tokens: Vec<String>,
+}
+
+/// In-memory inverted index over everything the app has seen locally, so
+/// search answers without waiting on any provider.
+#[derive(Default)]
+pub struct SearchIndex {
+    documents: HashMap<String, Document>,
+    /// token -> item id -> best field weight the token appears in. Sorted so
+    /// prefixes are a range scan.
+    postings: BTreeMap<String, HashMap<String, f32>>,
+    /// token length in chars -> tokens, so typo matching only compares
+    /// tokens within the allowed distance
+    lengths: HashMap<usize, HashSet<String>>,
+}
+
+impl SearchIndex {
+    pub fn upsert(&mut self, item: MediaItem) {
+        self.remove(&item.id);
+
+        let mut weights: HashMap<String, f32> = HashMap::new();
+        let mut add = |text: &str, weight: f32| {
+            for token in tokenize(text) {
+                let entry = weights.entry(token).or_insert(0.0);
+                *entry = entry.max(weight);
+            }
+        };
+        add(&item.title, TITLE_WEIGHT);
+        if let Some(original_title) = &item.original_title {
+            add(original_title, TITLE_WEIGHT);
+        }
+        for name in &item.cast {
+            add(name, CAST_WEIGHT);
+        }
+        if let Some(description) = &item.description {
+            add(description, DESCRIPTION_WEIGHT);
+        }
+
+        for (token, weight) in &weights {
+            self.lengths
+                .entry(token.chars().count())
+                .or_default()
+                .insert(token.clone());
+            self.postings
+                .entry(token.clone())
+                .or_default()
+                .insert(item.id.clone(), *weight);
+        }
+        self.documents.insert(
+            item.id.clone(),
+            Document {
+                tokens: weights.into_keys().collect(),
+                item,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        let Some(document) = self.documents.remove(id) else {
+            return;
+        };
+        for token in document.tokens {
+            if let Some(ids) = self.postings.get_mut(&token) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    self.postings.remove(&token);
+                    let len = token.chars().count();
+                    if let Some(tokens) = self.lengths.get_mut(&len) {
+                        tokens.remove(&token);
+                        if tokens.is_empty() {
+                            self.lengths.remove(&len);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&MediaItem> {
+        self.documents.get(id).map(|document| &document.item)
+    }
+
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchHit> {
+        let terms = tokenize(query);
+        if terms.is_empty() {
+            return Vec::new();
+        }
+
+        // item id -> (score, number of query terms matched)
+        let mut scores: HashMap<&str, (f32, usize)> = HashMap::new();
+        for (i, term) in terms.iter().enumerate() {
+            let is_last = i == terms.len() - 1;
+            let mut best: HashMap<&str, f32> = HashMap::new();
+            for (token, quality) in self.matching_tokens(term, is_last) {
+                let Some(ids) = self.postings.get(token) else {
+                    continue;
+                };
+                for (id, weight) in ids {
+                    let score = best.entry(id.as_str()).or_insert(0.0);
+                    *score = score.max(quality * weight);
+                }
+            }
+            for (id, score) in best {
+                let entry = scores.entry(id).or_insert((0.0, 0));
+                entry.0 += score;
+                entry.1 += 1;
+            }
+        }
+
+        let normalized_query = terms.join(" ");
+        let mut hits: Vec<SearchHit> = scores
+            .into_iter()
+            .filter_map(|(id, (score, matched))| {
+                let item = &self.documents.get(id)?.item;
+                // Items matching only some of the terms sink quickly.
+                let coverage = matched as f32 / terms.len() as f32;
+                let mut score = score * coverage * coverage;
+                let title = tokenize(&item.title).join(" ");
+                if title == normalized_query {
+                    score *= 2.0;
+                } else if title.starts_with(&normalized_query) {
+                    score *= 1.5;
+                }
+                Some(SearchHit {
+                    item: item.clone(),
+                    score,
+                })
+            })
+            .collect();
+
+        hits.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.item.title.cmp(&b.item.title))
+        });
+        hits.truncate(limit);
+        hits
+    }
+
+    /// Indexed tokens that satisfy a query term, with how well they do:
+    /// exact matches score highest, then prefixes of the term being typed,
+    /// then small typos.
+    fn matching_tokens<'a>(&'a self, term: &str, is_last: bool) -> Vec<(&'a str, f32)> {
+        let mut found: Vec<(&str, f32)> = Vec::new();
+        if let Some((token, _)) = self.postings.get_key_value(term) {
+            found.push((token, 1.0));
+        }
+        let term_len = term.chars().count();
+        if is_last && term_len >= 2 {
+            found.extend(
+                self.postings
+                    .range::<str, _>((Bound::Excluded(term), Bound::Unbounded))
+                    .take_while(|(token, _)| token.starts_with(term))
+                    .map(|(token, _)| (token.as_str(), 0.8)),
+            );
+        }
+
+        let max_distance = match term_len {
+            0..=3 => return found,
+            4..=7 => 1,
+            _ => 2,
+        };
+        let term_bigrams = bigrams(term);
+        for len in term_len.saturating_sub(max_distance)..=term_len + max_distance {
+            let Some(tokens) = self.lengths.get(&len) else {
+                continue;
+            };
+            for token in tokens {
+                if token == term || (is_last && token.starts_with(term)) {
+                    continue;
+                }
+                // Each edit breaks at most three of the term's bigrams, so
+                // tokens missing more than that cannot be close enough.
+                let missing = term_bigrams
+                    .iter()
+                    .filter(|bigram| !token.contains(bigram.as_str()))
+                    .count();
+                if missing > 3 * max_distance {
+                    continue;
+                }
+                let distance = strsim::damerau_levenshtein(term, token);
+                if distance <= max_distance {
+                    found.push((token, 0.7 - 0.15 * distance as f32));
+                }
+            }
+        }
+        found
+    }
+}
+
+/// Lowercases, strips diacritics and splits on anything that is not a
+/// letter or digit.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let folded: String = text
+        .nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect();
+    folded
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The distinct two-character substrings of a token.
+fn bigrams(token: &str) -> HashSet<String> {
+    let chars: Vec<char> = token.chars().collect();
+    chars.windows(2).map(|pair| pair.iter().collect()).collect()
+}
@@ -0,0 +1,191 @@
+//! Ranking, typo tolerance and bookkeeping of the search index.
+
+use super::{bigrams, tokenize, SearchIndex};
+use crate::services::metadata::MediaItem;
+
+fn item(id: &str, title: &str) -> MediaItem {
+    MediaItem {
+        id: id.to_string(),
+        media_type: "movie".into(),
+        title: title.to_string(),
+        ..Default::default()
+    }
+}
+
+fn index() -> SearchIndex {
+    let mut index = SearchIndex::default();
+    index.upsert(item("tt1375666", "Inception"));
+    index.upsert(item("tt0816692", "Interstellar"));
+    index.upsert(item("tt0468569", "The Dark Knight"));
+    index.upsert(MediaItem {
+        cast: vec!["Audrey Tautou".into()],
+        ..item("tt0211915", "Amélie")
+    });
+    index
+}
+
+/// Ids of the hits for `query`, best first.
+fn search(index: &SearchIndex, query: &str) -> Vec<String> {
+    index
+        .search(query, 10)
+        .into_iter()
+        .map(|hit| hit.item.id)
+        .collect()
+}
+
+#[test]
+fn finds_titles_by_exact_prefix_and_misspelled_terms() {
+    let index = index();
+    let cases = [
+        ("Inception", "tt1375666"),
+        ("the dark knight", "tt0468569"),
+        // The term being typed matches as a prefix.
+        ("incep", "tt1375666"),
+        ("the dark kni", "tt0468569"),
+        // One typo in a medium term, two in a long one, transpositions
+        // counting as one.
+        ("incepton", "tt1375666"),
+        ("inecption", "tt1375666"),
+        ("intrestelar", "tt0816692"),
+        // Diacritics fold both ways.
+        ("amelie", "tt0211915"),
+        ("Ámélie", "tt0211915"),
+        ("tautou", "tt0211915"),
+    ];
+    for (query, expected) in cases {
+        let hits = search(&index, query);
+        assert_eq!(
+            hits.first().map(String::as_str),
+            Some(expected),
+            "{}",
+            query
+        );
+    }
+}
+
+#[test]
+fn rejects_terms_too_far_from_any_title() {
+    let index = index();
+    // Three typos are too many even for a long term.
+    assert!(search(&index, "intrestelr").is_empty());
+    // Terms of three letters or fewer must match exactly or as a prefix.
+    assert!(search(&index, "drk").is_empty());
+    // Only the term being typed matches as a prefix.
+    assert_eq!(search(&index, "ince knight"), ["tt0468569"]);
+    assert!(search(&index, "").is_empty());
+    assert!(search(&index, " - ").is_empty());
+}
+
+#[test]
+fn ranks_title_matches_above_cast_and_partial_matches() {
+    let mut index = index();
+    index.upsert(MediaItem {
+        cast: vec!["Christopher Inception".into()],
+        ..item("tt9999999", "Documentary")
+    });
+    index.upsert(item("tt0000001", "Inception Making Of"));
+    index.upsert(item("tt0083437", "Knight Rider"));
+    assert_eq!(
+        search(&index, "inception"),
+        ["tt1375666", "tt0000001", "tt9999999"]
+    );
+    // Hits covering every term outrank those covering one.
+    assert_eq!(search(&index, "dark knight"), ["tt0468569", "tt0083437"]);
+}
+
+#[test]
+fn forgets_removed_and_replaced_items() {
+    let mut index = index();
+    index.remove("tt1375666");
+    assert!(index.get("tt1375666").is_none());
+    for query in ["inception", "incep", "incepton"] {
+        assert!(search(&index, query).is_empty(), "{}", query);
+    }
+    // Tokens other items still use stay.
+    assert_eq!(search(&index, "interstellar"), ["tt0816692"]);
+
+    index.upsert(item("tt0816692", "Interstellar Redux"));
+    assert_eq!(search(&index, "redux"), ["tt0816692"]);
+    index.upsert(item("tt0816692", "Interstellar"));
+    assert!(search(&index, "redux").is_empty());
+    assert!(!index.postings.contains_key("redux"));
+    // Removing an unknown id is a no-op.
+    index.remove("tt0000000");
+}
+
+#[test]
+fn drops_postings_and_length_buckets_with_their_last_item() {
+    let mut index = index();
+    for id in ["tt1375666", "tt0816692", "tt0468569", "tt0211915"] {
+        index.remove(id);
+    }
+    assert!(index.documents.is_empty());
+    assert!(index.postings.is_empty());
+    assert!(index.lengths.is_empty());
+}
+
+/// Every single edit of `word`: deletions, substitutions, insertions and
+/// adjacent transpositions.
+fn edits(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut edits = Vec::new();
+    for i in 0..chars.len() {
+        let mut deleted = chars.clone();
+        deleted.remove(i);
+        edits.push(deleted);
+        let mut substituted = chars.clone();
+        substituted[i] = 'q';
+        edits.push(substituted);
+        if i + 1 < chars.len() {
+            let mut transposed = chars.clone();
+            transposed.swap(i, i + 1);
+            edits.push(transposed);
+        }
+    }
+    for i in 0..=chars.len() {
+        let mut inserted = chars.clone();
+        inserted.insert(i, 'q');
+        edits.push(inserted);
+    }
+    edits
+        .into_iter()
+        .map(|chars| chars.into_iter().collect())
+        .collect()
+}
+
+#[test]
+fn misses_at_most_three_bigrams_per_edit() {
+    let missing = |term: &str, token: &str| {
+        bigrams(term)
+            .iter()
+            .filter(|bigram| !token.contains(bigram.as_str()))
+            .count()
+    };
+    for word in ["matrix", "interstellar"] {
+        for once in edits(word) {
+            let distance = strsim::damerau_levenshtein(&once, word);
+            assert!(missing(&once, word) <= 3 * distance, "{}", once);
+            for twice in edits(&once) {
+                let distance = strsim::damerau_levenshtein(&twice, word);
+                assert!(missing(&twice, word) <= 3 * distance, "{}", twice);
+            }
+        }
+    }
+
+    // So the prefilter never hides a token within reach.
+    let mut index = SearchIndex::default();
+    index.upsert(item("tt0133093", "Matrix"));
+    index.upsert(item("tt0816692", "Interstellar"));
+    for (word, max_distance) in [("matrix", 1), ("interstellar", 2)] {
+        for once in edits(word) {
+            for term in std::iter::once(once.clone()).chain(edits(&once)) {
+                if strsim::damerau_levenshtein(&term, word) > max_distance {
+                    continue;
+                }
+                // Not the last term, so no prefix match helps.
+                let found = index.matching_tokens(&tokenize(&term)[0], false);
+                assert!(found.iter().any(|(token, _)| *token == word), "{}", term);
+            }
+        }
+    }
+}
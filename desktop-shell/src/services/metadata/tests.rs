@@ -0,0 +1,308 @@
+//! Merges answers from stub providers through the aggregator, and ages and
+//! evicts entries of an on-disk cache with short TTLs.
+
+use std::{path::PathBuf, sync::Arc, thread::sleep, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use super::aggregator::MetadataAggregator;
+use super::cache::{CacheKind, Lookup, MetadataCache};
+use super::{MediaItem, MetadataProvider};
+use crate::config::{CacheConfig, MetadataConfig};
+
+/// Answers every request from a fixed list of items.
+struct StubProvider {
+    id: &'static str,
+    items: Vec<MediaItem>,
+}
+
+#[async_trait]
+impl MetadataProvider for StubProvider {
+    fn id(&self) -> &str {
+        self.id
+    }
+
+    async fn catalog(&self) -> Result<Vec<MediaItem>> {
+        Ok(self.items.clone())
+    }
+
+    async fn meta(&self, id: &str) -> Result<Option<MediaItem>> {
+        Ok(self.items.iter().find(|item| item.id == id).cloned())
+    }
+}
+
+fn item(id: &str, title: &str) -> MediaItem {
+    MediaItem {
+        id: id.to_string(),
+        media_type: "movie".into(),
+        title: title.to_string(),
+        ..Default::default()
+    }
+}
+
+/// An aggregator over `providers`, ranked in the order given.
+fn aggregator(providers: Vec<(&'static str, Vec<MediaItem>)>) -> MetadataAggregator {
+    let config = MetadataConfig {
+        provider_priority: providers.iter().map(|(id, _)| id.to_string()).collect(),
+        preferred_languages: vec!["en".into()],
+        ..Default::default()
+    };
+    let mut aggregator = MetadataAggregator::new(config, None);
+    for (id, items) in providers {
+        aggregator.register(Arc::new(StubProvider { id, items }));
+    }
+    aggregator
+}
+
+#[tokio::test]
+async fn takes_title_in_preferred_language() {
+    let french = MediaItem {
+        language: Some("fr".into()),
+        ..item("tt1", "Le Fabuleux Destin")
+    };
+    let english = MediaItem {
+        language: Some("en".into()),
+        ..item("tt1", "Amelie")
+    };
+    let aggregator = aggregator(vec![("first", vec![french]), ("second", vec![english])]);
+
+    let merged = aggregator.meta("tt1").await.unwrap();
+    assert_eq!(merged.title, "Amelie");
+    assert_eq!(merged.language.as_deref(), Some("en"));
+    let titles = &merged.alternatives["title"];
+    assert_eq!(titles.len(), 1);
+    assert_eq!(titles[0].source, "first");
+    assert_eq!(titles[0].value, json!("Le Fabuleux Destin"));
+}
+
+#[tokio::test]
+async fn falls_back_to_priority_without_preferred_language() {
+    let german = MediaItem {
+        language: Some("de".into()),
+        ..item("tt1", "Die fabelhafte Welt")
+    };
+    let french = MediaItem {
+        language: Some("fr".into()),
+        ..item("tt1", "Le Fabuleux Destin")
+    };
+    let aggregator = aggregator(vec![("first", vec![german]), ("second", vec![french])]);
+
+    let merged = aggregator.meta("tt1").await.unwrap();
+    assert_eq!(merged.title, "Die fabelhafte Welt");
+}
+
+#[tokio::test]
+async fn takes_widest_poster_and_breaks_ties_by_priority() {
+    let poster = |url: &str, width| MediaItem {
+        poster: url.to_string(),
+        poster_width: Some(width),
+        ..item("tt1", "Amelie")
+    };
+    let aggregator = aggregator(vec![
+        ("first", vec![poster("https://a/small.jpg", 500)]),
+        ("second", vec![poster("https://b/large.jpg", 1000)]),
+        ("third", vec![poster("https://c/large.jpg", 1000)]),
+    ]);
+
+    let merged = aggregator.meta("tt1").await.unwrap();
+    assert_eq!(merged.poster, "https://b/large.jpg");
+    assert_eq!(merged.poster_width, Some(1000));
+    let sources: Vec<&str> = merged.alternatives["poster"]
+        .iter()
+        .map(|alternative| alternative.source.as_str())
+        .collect();
+    assert_eq!(sources, ["first", "third"]);
+}
+
+#[tokio::test]
+async fn unions_genres_in_first_seen_order() {
+    let genres = |genres: &[&str]| MediaItem {
+        genres: genres.iter().map(|genre| genre.to_string()).collect(),
+        ..item("tt1", "Amelie")
+    };
+    let aggregator = aggregator(vec![
+        ("first", vec![genres(&["Comedy", "Romance"])]),
+        ("second", vec![genres(&["romance", "Drama"])]),
+        ("third", vec![genres(&[])]),
+    ]);
+
+    let merged = aggregator.meta("tt1").await.unwrap();
+    assert_eq!(merged.genres, ["Comedy", "Romance", "Drama"]);
+    assert!(!merged.alternatives.contains_key("genres"));
+}
+
+#[tokio::test]
+async fn records_losing_values_as_alternatives() {
+    let described = |description: &str, year| MediaItem {
+        description: Some(description.to_string()),
+        year,
+        ..item("tt1", "Amelie")
+    };
+    let aggregator = aggregator(vec![
+        ("first", vec![described("A shy waitress.", 2001)]),
+        ("second", vec![described("A Parisian fairy tale.", 2001)]),
+        ("third", vec![item("tt1", "Amelie")]),
+    ]);
+
+    let merged = aggregator.meta("tt1").await.unwrap();
+    assert_eq!(merged.description.as_deref(), Some("A shy waitress."));
+    let descriptions = &merged.alternatives["description"];
+    assert_eq!(descriptions.len(), 1);
+    assert_eq!(descriptions[0].source, "second");
+    // Agreeing values and missing ones are not alternatives.
+    assert!(!merged.alternatives.contains_key("year"));
+    assert!(!merged.alternatives.contains_key("title"));
+}
+
+#[tokio::test]
+async fn merges_catalog_entries_by_id() {
+    let aggregator = aggregator(vec![
+        (
+            "first",
+            vec![item("tt1", "Amelie"), item("tt2", "Delicatessen")],
+        ),
+        (
+            "second",
+            vec![item("tt3", "Alien Resurrection"), item("tt1", "Amélie")],
+        ),
+    ]);
+
+    let catalog = aggregator.catalog().await;
+    let ids: Vec<&str> = catalog.iter().map(|item| item.id.as_str()).collect();
+    assert_eq!(ids, ["tt1", "tt2", "tt3"]);
+    assert_eq!(catalog[0].title, "Amelie");
+    assert_eq!(catalog[0].alternatives["title"][0].value, json!("Amélie"));
+}
+
+/// A cache in a fresh folder that evicts past 1 MB.
+fn cache(name: &str, config: CacheConfig) -> (MetadataCache, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("metadata-cache-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = CacheConfig {
+        max_size_mb: 1,
+        ..config
+    };
+    let cache = MetadataCache::open(&dir.join("cache.sqlite"), config).unwrap();
+    (cache, dir)
+}
+
+fn state(cache: &MetadataCache, id: &str) -> &'static str {
+    match cache.get::<Value>(CacheKind::Meta, id, "stub") {
+        Lookup::Fresh(_) => "fresh",
+        Lookup::Stale(_) => "stale",
+        Lookup::Miss => "miss",
+    }
+}
+
+/// Stores a value whose JSON is `size` bytes long.
+fn put_sized(cache: &MetadataCache, id: &str, size: usize) {
+    let value = "x".repeat(size - 2);
+    let ttl = Duration::from_secs(60);
+    cache.put(CacheKind::Meta, id, "stub", &value, ttl).unwrap();
+}
+
+#[test]
+fn serves_entries_fresh_then_stale_then_not_at_all() {
+    let (cache, dir) = cache(
+        "ages",
+        CacheConfig {
+            stale_ttl_secs: 2,
+            negative_ttl_secs: 2,
+            ..Default::default()
+        },
+    );
+    let ttl = Duration::from_secs(2);
+    cache
+        .put(CacheKind::Meta, "tt1", "stub", &item("tt1", "Amelie"), ttl)
+        .unwrap();
+    // Unknown ids are kept no longer than the negative TTL, however long
+    // they are stored for.
+    let unknown: Option<MediaItem> = None;
+    cache
+        .put(
+            CacheKind::Meta,
+            "tt2",
+            "stub",
+            &unknown,
+            Duration::from_secs(60),
+        )
+        .unwrap();
+    assert_eq!(state(&cache, "tt1"), "fresh");
+    assert_eq!(state(&cache, "tt2"), "fresh");
+
+    sleep(Duration::from_secs(2));
+    assert_eq!(state(&cache, "tt1"), "stale");
+    // Empty answers have no stale window.
+    assert_eq!(state(&cache, "tt2"), "miss");
+
+    sleep(Duration::from_secs(2));
+    assert_eq!(state(&cache, "tt1"), "miss");
+    let stats = cache.stats().unwrap();
+    assert_eq!((stats.hits, stats.stale_hits, stats.misses), (2, 1, 2));
+    assert_eq!((stats.entries, stats.size_bytes), (0, 0));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn evicts_least_recently_used_entries_first() {
+    let (cache, dir) = cache("evicts", CacheConfig::default());
+    put_sized(&cache, "tt1", 400_000);
+    put_sized(&cache, "tt2", 400_000);
+    // Access times have second resolution.
+    sleep(Duration::from_secs(1));
+    assert_eq!(state(&cache, "tt1"), "fresh");
+
+    // 1.2 MB is over the limit; dropping the oldest entry is enough.
+    put_sized(&cache, "tt3", 400_000);
+    assert_eq!(state(&cache, "tt2"), "miss");
+    assert_eq!(state(&cache, "tt1"), "fresh");
+    assert_eq!(state(&cache, "tt3"), "fresh");
+    assert_eq!(cache.stats().unwrap().size_bytes, 800_000);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn tracks_size_across_overwrites_and_clears() {
+    let (cache, dir) = cache("size", CacheConfig::default());
+    // Overwrites replace the old value's size rather than adding to it, so
+    // none of them pushes the cache over the limit.
+    for _ in 0..5 {
+        put_sized(&cache, "tt1", 400_000);
+    }
+    put_sized(&cache, "tt2", 400_000);
+    assert_eq!(state(&cache, "tt1"), "fresh");
+    assert_eq!(cache.stats().unwrap().size_bytes, 800_000);
+
+    put_sized(&cache, "tt1", 100);
+    assert_eq!(cache.stats().unwrap().size_bytes, 400_100);
+
+    // After clearing, the space is free again: 900 kB more fits without
+    // evicting anything.
+    assert_eq!(cache.clear(Some("other")).unwrap(), 0);
+    assert_eq!(cache.clear(None).unwrap(), 2);
+    assert_eq!(cache.stats().unwrap().size_bytes, 0);
+    for id in ["tt3", "tt4", "tt5"] {
+        put_sized(&cache, id, 300_000);
+    }
+    for id in ["tt3", "tt4", "tt5"] {
+        assert_eq!(state(&cache, id), "fresh", "{}", id);
+    }
+    assert_eq!(cache.stats().unwrap().size_bytes, 900_000);
+
+    // Reopening counts what is on disk.
+    drop(cache);
+    let config = CacheConfig {
+        max_size_mb: 1,
+        ..Default::default()
+    };
+    let reopened = MetadataCache::open(&dir.join("cache.sqlite"), config).unwrap();
+    put_sized(&reopened, "tt6", 200_000);
+    let evicted = ["tt3", "tt4", "tt5", "tt6"]
+        .iter()
+        .filter(|id| state(&reopened, id) == "miss")
+        .count();
+    assert_eq!(evicted, 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,28 +1,373 @@
-use std::sync::Mutex;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
 
 use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::{from_str, json, to_string, Value};
 mod addons;
+mod calendar;
+pub mod events;
+pub mod images;
+mod library;
 mod metadata;
 mod playback;
 
+use crate::config::AppConfig;
+
 #[allow(dead_code)]
 pub struct ServiceManager {
+    runtime: tokio::runtime::Runtime,
     playback: Mutex<playback::PlaybackService>,
-    addons: Mutex<addons::AddonManager>,
-    mock_metadata: Mutex<metadata::MockMetadataService>,
+    addons: Arc<addons::AddonManager>,
+    metadata: Arc<metadata::aggregator::MetadataAggregator>,
+    images: Option<Arc<images::ImageCache>>,
+    library: Arc<library::LibraryService>,
+    ids: Arc<metadata::ids::IdResolver>,
+    calendar: Arc<calendar::CalendarService>,
+    events: events::EventBus,
+}
+
+#[derive(Deserialize)]
+struct MetaArgs {
+    id: String,
+    /// Needed for TMDB ids without a kind.
+    #[serde(rename = "type", default)]
+    media_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AddonManifestArgs {
+    transport_url: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AddonRequestArgs {
+    transport_url: String,
+    #[serde(flatten)]
+    request: addons::protocol::ResourceRequest,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReorderAddonsArgs {
+    transport_urls: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConfigureAddonArgs {
+    transport_url: String,
+    #[serde(default)]
+    values: serde_json::Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetAddonEnabledArgs {
+    transport_url: String,
+    enabled: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct ExportAddonsArgs {
+    redact_configs: bool,
+}
+
+#[derive(Deserialize)]
+struct ImportAddonsArgs {
+    /// The exported document, as an object or text, or a share link.
+    source: Value,
+    #[serde(default)]
+    apply: bool,
+}
+
+#[derive(Deserialize)]
+struct ResolveIdArgs {
+    id: String,
+    #[serde(rename = "type", default)]
+    media_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetLibraryMatchArgs {
+    path: std::path::PathBuf,
+    id: String,
+    #[serde(rename = "type", default)]
+    media_type: Option<String>,
+    /// Replace an existing NFO when `write_nfo` is enabled.
+    #[serde(default)]
+    overwrite_nfo: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ExportNfoArgs {
+    overwrite: bool,
+}
+
+#[derive(Deserialize, Default)]
+struct ClearCacheArgs {
+    source: Option<String>,
+}
+
+/// Parses command arguments the frontend may omit entirely.
+fn optional_args<T: DeserializeOwned + Default>(args: &Value) -> Result<T> {
+    if args.is_null() {
+        Ok(T::default())
+    } else {
+        Ok(serde_json::from_value(args.clone())?)
+    }
+}
+
+/// The request addons are asked for an item. Ids whose prefix an addon
+/// lists, such as `kitsu:` ids for anime addons, are kept; others are mapped
+/// to IMDb, which most addons expect. Catalog ids name no item.
+async fn routed_request(
+    addons: &addons::AddonManager,
+    ids: &metadata::ids::IdResolver,
+    mut request: addons::protocol::ResourceRequest,
+) -> addons::protocol::ResourceRequest {
+    if request.resource != "catalog" && !addons.claims_id(&request) {
+        request.id = ids.canonical(&request.id, Some(&request.media_type)).await;
+    }
+    request
 }
 
 impl ServiceManager {
-    pub fn init() -> Result<Self> {
+    pub fn init(config: &AppConfig) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .context("Failed to start async runtime")?;
+        let events = events::EventBus::default();
+
+        let cache = if config.metadata.cache.enabled {
+            let path = crate::config::paths::metadata_cache_file()?;
+            match metadata::cache::MetadataCache::open(&path, config.metadata.cache.clone()) {
+                Ok(cache) => Some(Arc::new(cache)),
+                Err(e) => {
+                    log::error!("Metadata cache unavailable: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut metadata =
+            metadata::aggregator::MetadataAggregator::new(config.metadata.clone(), cache);
+        let mut native_addons: Vec<Arc<dyn addons::native::Addon>> = Vec::new();
+        let fixtures = Self::fixtures(config);
+        metadata.register(fixtures.clone());
+        native_addons.push(fixtures);
+        let metadata = Arc::new(metadata);
+
+        let matches = match crate::config::paths::library_matches_file()
+            .and_then(|path| library::matches::MatchStore::open(&path))
+        {
+            Ok(matches) => Some(matches),
+            Err(e) => {
+                log::error!("Library matches unavailable: {}", e);
+                None
+            }
+        };
+        let library = Arc::new(library::LibraryService::new(
+            config.library.clone(),
+            metadata.clone(),
+            matches,
+            events.clone(),
+            runtime.handle().clone(),
+        ));
+        {
+            let (metadata, library) = (metadata.clone(), library.clone());
+            runtime.spawn(async move {
+                // Loading the catalog fills the search index files are matched against.
+                metadata.catalog().await;
+                let scan = tokio::task::spawn_blocking(move || {
+                    if let Err(e) = library.scan() {
+                        log::warn!("Skipping startup library scan: {}", e);
+                    }
+                    if library.watch_enabled() {
+                        if let Err(e) = library.watch() {
+                            log::error!("Failed to watch library folders: {}", e);
+                        }
+                    }
+                });
+                if let Err(e) = scan.await {
+                    log::error!("Library scan failed: {}", e);
+                }
+            });
+        }
+
+        let ids = Arc::new(Self::init_ids(config));
+        {
+            let ids = ids.clone();
+            runtime.spawn(async move { ids.refresh().await });
+        }
+
+        let calendar = Arc::new(calendar::CalendarService::new(
+            config.calendar.clone(),
+            metadata.clone(),
+            ids.clone(),
+            library.clone(),
+            events.clone(),
+            &crate::config::paths::calendar_state_file()?,
+        ));
+        calendar.spawn_checks(&runtime);
+
+        let images = match crate::config::paths::image_cache_dir()
+            .and_then(|dir| images::ImageCache::open(&dir, config.images.clone()))
+        {
+            Ok(images) => Some(Arc::new(images)),
+            Err(e) => {
+                log::error!("Image cache unavailable: {}", e);
+                None
+            }
+        };
+
+        let addons = Arc::new(addons::AddonManager::new(
+            &config.addons,
+            &crate::config::paths::addon_collection_file()?,
+            &crate::config::paths::sandboxed_addons_dir()?,
+            &crate::config::paths::process_addons_dir()?,
+            native_addons.clone(),
+            events.clone(),
+        )?);
+        {
+            let addons = addons.clone();
+            runtime.spawn(async move { addons.install_builtin().await });
+        }
+        addons.spawn_update_checks(&runtime);
+        if config.addons.server.enabled {
+            let native = Arc::new(addons::native::NativeAddons::new(native_addons));
+            let port = config.addons.server.port;
+            runtime.spawn(async move {
+                let served = match addons::server::bind(port).await {
+                    Ok(listener) => addons::server::serve(listener, native).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = served {
+                    log::error!("Addon server unavailable: {:#}", e);
+                }
+            });
+        }
+
         Ok(Self {
+            runtime,
             playback: playback::PlaybackService::new().into(),
-            addons: addons::AddonManager::new().into(),
-            mock_metadata: metadata::MockMetadataService::new().into(), // Add this
+            addons,
+            metadata,
+            images,
+            library,
+            ids,
+            calendar,
+            events,
         })
     }
 
-    pub fn handle_web_message(&self, message: &str) -> Result<String> {
+    /// Serves the native addons over HTTP without the rest of the shell,
+    /// until the process is stopped. `port` overrides the configured one.
+    pub fn serve_addons(config: &AppConfig, port: Option<u16>) -> Result<()> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .context("Failed to start async runtime")?;
+        let native = Arc::new(addons::native::NativeAddons::new(vec![Self::fixtures(
+            config,
+        )]));
+        runtime.block_on(async {
+            let listener = addons::server::bind(port.unwrap_or(config.addons.server.port)).await?;
+            addons::server::serve(listener, native).await
+        })
+    }
+
+    /// The fixture directory's provider, or the built-in catalog without one.
+    fn fixtures(config: &AppConfig) -> Arc<metadata::fixtures::FixtureProvider> {
+        Arc::new(match config.metadata.fixtures_dir() {
+            Some(dir) => metadata::fixtures::FixtureProvider::new(&dir),
+            None => metadata::fixtures::FixtureProvider::builtin(),
+        })
+    }
+
+    /// Where services send events for the page.
+    pub fn events(&self) -> &events::EventBus {
+        &self.events
+    }
+
+    fn init_ids(config: &AppConfig) -> metadata::ids::IdResolver {
+        use metadata::ids::{providers, store::IdStore, IdResolver};
+
+        let ids_config = &config.metadata.ids;
+        let store = match crate::config::paths::id_map_file().and_then(|path| IdStore::open(&path))
+        {
+            Ok(store) => Some(store),
+            Err(e) => {
+                log::error!("Id map unavailable: {}", e);
+                None
+            }
+        };
+        let timeout = std::time::Duration::from_millis(config.metadata.provider_timeout_ms);
+        let mut ids = IdResolver::new(store, timeout);
+
+        if !ids_config.anime_list_url.is_empty() {
+            let max_age =
+                std::time::Duration::from_secs(ids_config.anime_list_refresh_hours * 3600);
+            match crate::config::paths::anime_list_file().and_then(|file| {
+                providers::AnimeListProvider::new(&ids_config.anime_list_url, &file, max_age)
+            }) {
+                Ok(provider) => ids.register(Arc::new(provider)),
+                Err(e) => log::error!("Anime id list unavailable: {}", e),
+            }
+        }
+        if let Some(key) = &ids_config.tmdb_api_key {
+            ids.register(Arc::new(providers::TmdbProvider::new(key)));
+        }
+        ids
+    }
+
+    /// Runs a command on the runtime, as it waits on the network, and sends
+    /// its reply through the event bus. Returns no direct reply.
+    fn reply_later<T: Serialize>(
+        &self,
+        request_id: &str,
+        command: impl Future<Output = Result<T>> + Send + 'static,
+    ) -> Result<Option<String>> {
+        let (events, request_id) = (self.events.clone(), request_id.to_string());
+        self.runtime.spawn(async move {
+            let reply = command
+                .await
+                .and_then(|data| Ok(serde_json::to_value(data)?));
+            events.reply(&request_id, reply);
+        });
+        Ok(None)
+    }
+
+    /// Serves a `westream-img://` request from the image cache on the
+    /// runtime, as a miss downloads and resizes. `done` is called with the
+    /// image from a runtime thread.
+    pub fn handle_image_request(
+        &self,
+        uri: String,
+        done: impl FnOnce(Result<images::CachedImage>) + Send + 'static,
+    ) {
+        let images = self.images.clone();
+        self.runtime.spawn(async move {
+            done(match images {
+                Some(images) => images.get(&uri).await,
+                None => Err(anyhow::anyhow!("Image cache unavailable")),
+            });
+        });
+    }
+
+    /// Handles a message from the page. Commands that wait on the network
+    /// reply later through the event bus and return `None` here.
+    pub fn handle_web_message(&self, message: &str) -> Result<Option<String>> {
         log::debug!("Received message: {}", message);
         let value: Value = from_str(message).map_err(|e| {
             log::error!("JSON parse error: {}", e);
@@ -47,27 +392,244 @@ impl ServiceManager {
 
         log::info!("Handling command: {}", cmd);
 
-        let guard = match self.mock_metadata.lock() {
-            Ok(guard) => guard,
-            Err(poisoned) => {
-                log::error!("Mutex poisoned! Attempting recovery");
-                poisoned.into_inner()
-            }
-        };
-
         let response = match cmd {
             "getCatalog" => {
                 log::debug!("Processing getCatalog command");
-                let catalog = guard.get_catalog();
-                serde_json::to_value(catalog)?
+                let query: metadata::catalog::CatalogQuery = optional_args(args)?;
+                let (library, metadata) = (self.library.clone(), self.metadata.clone());
+                return self.reply_later(request_id, async move {
+                    let catalog = match query.catalog.as_deref() {
+                        Some(library::LOCAL_CATALOG) => library.catalog(),
+                        _ => metadata.catalog().await,
+                    };
+                    Ok(query.apply(catalog))
+                });
+            }
+            "getMeta" => {
+                let args: MetaArgs = serde_json::from_value(args.clone())?;
+                let (ids, metadata) = (self.ids.clone(), self.metadata.clone());
+                return self.reply_later(request_id, async move {
+                    let id = ids.canonical(&args.id, args.media_type.as_deref()).await;
+                    let mut meta = metadata.meta(&id).await;
+                    if meta.is_none() && id != args.id {
+                        // Providers may index the item under the id as given.
+                        meta = metadata.meta(&args.id).await;
+                    }
+                    meta.context("Item not found")
+                });
+            }
+            "search" => {
+                let args: metadata::search::SearchArgs = serde_json::from_value(args.clone())?;
+                // An id, e.g. pasted from another app, finds its item directly.
+                if metadata::ids::ExternalId::parse(&args.query).is_some() {
+                    let (ids, metadata) = (self.ids.clone(), self.metadata.clone());
+                    return self.reply_later(request_id, async move {
+                        let id = ids.canonical(&args.query, None).await;
+                        let item = metadata.meta(&id).await;
+                        Ok(item
+                            .map(|item| metadata::search::SearchHit { item, score: 1.0 })
+                            .into_iter()
+                            .collect::<Vec<_>>())
+                    });
+                }
+                let hits = self.metadata.search(&args.query, args.limit());
+                // The providers are asked too; their answers arrive as a
+                // `searchResults` event ranked together with the local hits.
+                let (metadata, events) = (self.metadata.clone(), self.events.clone());
+                let request_id = request_id.to_string();
+                self.runtime.spawn(async move {
+                    if metadata.search_remote(&args.query).await.is_empty() {
+                        return;
+                    }
+                    let results = metadata::search::SearchResults {
+                        hits: metadata.search(&args.query, args.limit()),
+                        request_id,
+                        query: args.query,
+                    };
+                    events.emit(metadata::search::SEARCH_RESULTS_EVENT, &results);
+                });
+                serde_json::to_value(hits)?
+            }
+            "resolveId" => {
+                let args: ResolveIdArgs = serde_json::from_value(args.clone())?;
+                let ids = self.ids.clone();
+                return self.reply_later(request_id, async move {
+                    ids.resolve(&args.id, args.media_type.as_deref()).await
+                });
+            }
+            "getCalendar" => {
+                let args: calendar::CalendarArgs = optional_args(args)?;
+                let calendar = self.calendar.clone();
+                return self.reply_later(
+                    request_id,
+                    async move { Ok(calendar.calendar(&args).await) },
+                );
+            }
+            "scanLibrary" => {
+                let library = self.library.clone();
+                return self.reply_later(request_id, async move {
+                    tokio::task::spawn_blocking(move || library.scan()).await?
+                });
+            }
+            "getLibrary" => serde_json::to_value(self.library.files())?,
+            "setLibraryMatch" => {
+                let args: SetLibraryMatchArgs = serde_json::from_value(args.clone())?;
+                let (ids, metadata) = (self.ids.clone(), self.metadata.clone());
+                let library = self.library.clone();
+                return self.reply_later(request_id, async move {
+                    let id = ids.canonical(&args.id, args.media_type.as_deref()).await;
+                    let item = metadata.meta(&id).await.context("Item not found")?;
+                    library.set_match(&args.path, item, args.overwrite_nfo)
+                });
+            }
+            "exportNfo" => {
+                let args: ExportNfoArgs = optional_args(args)?;
+                serde_json::to_value(self.library.export_nfo(args.overwrite))?
+            }
+            "getAddonManifest" => {
+                let args: AddonManifestArgs = serde_json::from_value(args.clone())?;
+                let addons = self.addons.clone();
+                return self.reply_later(request_id, async move {
+                    addons.manifest(&args.transport_url).await
+                });
+            }
+            "validateAddon" => {
+                let args: AddonManifestArgs = serde_json::from_value(args.clone())?;
+                let addons = self.addons.clone();
+                return self.reply_later(request_id, async move {
+                    addons.validate(&args.transport_url).await
+                });
+            }
+            "listAddons" => serde_json::to_value(self.addons.collection().list())?,
+            "installAddon" => {
+                let args: AddonManifestArgs = serde_json::from_value(args.clone())?;
+                let addons = self.addons.clone();
+                return self.reply_later(request_id, async move {
+                    addons.install(&args.transport_url).await
+                });
+            }
+            "getAddonSettings" => {
+                let args: AddonManifestArgs = serde_json::from_value(args.clone())?;
+                let addons = self.addons.clone();
+                return self.reply_later(request_id, async move {
+                    addons.settings_form(&args.transport_url).await
+                });
+            }
+            "configureAddon" => {
+                let args: ConfigureAddonArgs = serde_json::from_value(args.clone())?;
+                let addons = self.addons.clone();
+                return self.reply_later(request_id, async move {
+                    addons.configure(&args.transport_url, &args.values).await
+                });
+            }
+            "exportAddonCollection" => {
+                let args: ExportAddonsArgs = optional_args(args)?;
+                serde_json::to_value(self.addons.export_collection(args.redact_configs))?
+            }
+            // Previews the changes unless `apply` is set.
+            "importAddonCollection" => {
+                let args: ImportAddonsArgs = serde_json::from_value(args.clone())?;
+                let addons = self.addons.clone();
+                return self.reply_later(request_id, async move {
+                    addons.import_collection(&args.source, args.apply).await
+                });
+            }
+            "browseAddonStore" => {
+                let query: addons::store::StoreQuery = optional_args(args)?;
+                let addons = self.addons.clone();
+                return self.reply_later(request_id, async move {
+                    Ok(addons.browse_store(&query).await)
+                });
+            }
+            "refreshAddonStore" => {
+                let query: addons::store::StoreQuery = optional_args(args)?;
+                let addons = self.addons.clone();
+                return self.reply_later(request_id, async move {
+                    Ok(addons.refresh_store(&query).await)
+                });
+            }
+            "checkAddonUpdates" => {
+                let addons = self.addons.clone();
+                return self
+                    .reply_later(request_id, async move { Ok(addons.check_updates().await) });
+            }
+            "rollbackAddon" => {
+                let args: AddonManifestArgs = serde_json::from_value(args.clone())?;
+                serde_json::to_value(self.addons.rollback(&args.transport_url)?)?
+            }
+            "getAddonHealth" => serde_json::to_value(self.addons.health())?,
+            "uninstallAddon" => {
+                let args: AddonManifestArgs = serde_json::from_value(args.clone())?;
+                serde_json::to_value(self.addons.uninstall(&args.transport_url)?)?
+            }
+            "reorderAddons" => {
+                let args: ReorderAddonsArgs = serde_json::from_value(args.clone())?;
+                serde_json::to_value(self.addons.collection().reorder(&args.transport_urls)?)?
+            }
+            "setAddonEnabled" => {
+                let args: SetAddonEnabledArgs = serde_json::from_value(args.clone())?;
+                let addon = self
+                    .addons
+                    .collection()
+                    .set_enabled(&args.transport_url, args.enabled)?;
+                serde_json::to_value(addon)?
+            }
+            // Asks every addon that handles the request. Results arrive as
+            // events tagged with this request's id; the reply lists the
+            // addons being asked.
+            "addonResource" => {
+                let request: addons::protocol::ResourceRequest =
+                    serde_json::from_value(args.clone())?;
+                let (addons, ids) = (self.addons.clone(), self.ids.clone());
+                let fan_out_id = request_id.to_string();
+                return self.reply_later(request_id, async move {
+                    let request = routed_request(&addons, &ids, request).await;
+                    let asked: Vec<_> = addons
+                        .who_handles(&request)
+                        .into_iter()
+                        .filter(|decision| decision.selected)
+                        .collect();
+                    tokio::spawn(async move {
+                        addons.fan_out(&fan_out_id, &request).await;
+                    });
+                    Ok(asked)
+                });
+            }
+            // Debugging aid for routing: why each addon is or isn't asked.
+            "whoHandles" => {
+                let request: addons::protocol::ResourceRequest =
+                    serde_json::from_value(args.clone())?;
+                let (addons, ids) = (self.addons.clone(), self.ids.clone());
+                return self.reply_later(request_id, async move {
+                    let request = routed_request(&addons, &ids, request).await;
+                    Ok(addons.who_handles(&request))
+                });
+            }
+            // Direct resource call, for inspecting what an addon returns.
+            "addonRequest" => {
+                let args: AddonRequestArgs = serde_json::from_value(args.clone())?;
+                let addons = self.addons.clone();
+                return self.reply_later(request_id, async move {
+                    addons.call(&args.transport_url, &args.request).await
+                });
+            }
+            "clearCache" => {
+                let args: ClearCacheArgs = optional_args(args)?;
+                let cache = self.metadata.cache().context("Metadata cache disabled")?;
+                let removed = cache.clear(args.source.as_deref())?;
+                json!({ "removed": removed })
+            }
+            "getCacheStats" => {
+                let cache = self.metadata.cache().context("Metadata cache disabled")?;
+                serde_json::to_value(cache.stats()?)?
             }
             _ => return Err(anyhow::anyhow!("Unknown command: {}", cmd)),
         };
         log::debug!("Sending response: {}", response);
-        Ok(to_string(&json!({
+        Ok(Some(to_string(&json!({
                 "requestId": value["requestId"].as_str().unwrap_or_default(),
                 "success": true,
                 "data": response
-        }))?)
+        }))?))
     }
 }
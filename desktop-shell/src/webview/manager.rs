@@ -1,22 +1,31 @@
-use crate::services::ServiceManager;
+use crate::services::{images, ServiceManager};
+use crate::window::{self, UiTask};
 use anyhow::{Context, Result};
 use serde_json::json;
+use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::Arc};
 use webview2_com::Microsoft::Web::WebView2::Win32::{
     CreateCoreWebView2EnvironmentWithOptions, ICoreWebView2, ICoreWebView2Controller,
-    ICoreWebView2Environment, ICoreWebView2WebMessageReceivedEventArgs,
-    ICoreWebView2WebMessageReceivedEventHandler, ICoreWebView2WebMessageReceivedEventHandler_Impl,
+    ICoreWebView2Deferral, ICoreWebView2Environment, ICoreWebView2EnvironmentOptions,
+    ICoreWebView2WebMessageReceivedEventArgs, ICoreWebView2WebMessageReceivedEventHandler,
+    ICoreWebView2WebMessageReceivedEventHandler_Impl, ICoreWebView2WebResourceRequestedEventArgs,
+    ICoreWebView2WebResourceRequestedEventHandler, COREWEBVIEW2_WEB_RESOURCE_CONTEXT_ALL,
 };
 use webview2_com::{
+    take_pwstr, CoreWebView2CustomSchemeRegistration, CoreWebView2EnvironmentOptions,
     CreateCoreWebView2ControllerCompletedHandler, CreateCoreWebView2EnvironmentCompletedHandler,
+    WebResourceRequestedEventHandler,
 };
 use windows::{
-    core::{HSTRING, PCWSTR},
+    core::{HSTRING, PCWSTR, PWSTR},
     Win32::{
         Foundation::{E_POINTER, HWND, RECT},
         System::{Com, WinRT::EventRegistrationToken},
-        UI::WindowsAndMessaging::{
-            DispatchMessageW, IsWindow, PeekMessageW, SetWindowLongPtrW,
-            TranslateMessage, GWLP_USERDATA, MSG, PM_REMOVE
+        UI::{
+            Shell::SHCreateMemStream,
+            WindowsAndMessaging::{
+                DispatchMessageW, IsWindow, PeekMessageW, SetWindowLongPtrW, TranslateMessage,
+                GWLP_USERDATA, MSG, PM_REMOVE,
+            },
         },
     },
 };
@@ -28,14 +37,18 @@ struct WebMessageHandler {
 }
 
 impl WebMessageHandler {
-    fn handle_message(&self, message: String) -> String {
+    /// The direct reply, if the command has one; others reply through the
+    /// event bus once done.
+    fn handle_message(&self, message: String) -> Option<String> {
         match self.service_manager.handle_web_message(&message) {
             Ok(r) => r,
-            Err(e) => serde_json::to_string(&json!({
-                "success": false,
-                "error": e.to_string()
-            }))
-            .unwrap_or_else(|_| r#"{"success":false}"#.into()),
+            Err(e) => Some(
+                serde_json::to_string(&json!({
+                    "success": false,
+                    "error": e.to_string()
+                }))
+                .unwrap_or_else(|_| r#"{"success":false}"#.into()),
+            ),
         }
     }
 }
@@ -85,7 +98,9 @@ impl ICoreWebView2WebMessageReceivedEventHandler_Impl for WebMessageHandler_Impl
 
         log::debug!("Returning response to UI thread");
 
-        let response = self.this.handle_message(message_str);
+        let Some(response) = self.this.handle_message(message_str) else {
+            return Ok(());
+        };
 
         let response_json = HSTRING::from(response);
         unsafe {
@@ -101,12 +116,107 @@ impl ICoreWebView2WebMessageReceivedEventHandler_Impl for WebMessageHandler_Impl
     }
 }
 
+/// A `westream-img://` request waiting on the image cache.
+struct PendingImage {
+    args: ICoreWebView2WebResourceRequestedEventArgs,
+    deferral: ICoreWebView2Deferral,
+}
+
+/// Requests handed to the runtime, by id. Only touched on the UI thread, as
+/// WebView2 objects must be.
+#[derive(Default)]
+struct PendingImages {
+    next_id: u64,
+    requests: HashMap<u64, PendingImage>,
+}
+
+/// Starts answering a `westream-img://` request from the image cache. The
+/// request is deferred while the cache works on the runtime, and completed
+/// on the UI thread by `WebViewManager::complete_image`.
+fn serve_image(
+    service_manager: &ServiceManager,
+    pending: &RefCell<PendingImages>,
+    post: &Arc<dyn Fn(UiTask) + Send + Sync>,
+    args: &ICoreWebView2WebResourceRequestedEventArgs,
+) -> Result<()> {
+    let uri = unsafe {
+        let mut uri = PWSTR::null();
+        args.Request()?.Uri(&mut uri)?;
+        take_pwstr(uri)
+    };
+    let deferral = unsafe { args.GetDeferral() }?;
+
+    let id = {
+        let mut pending = pending.borrow_mut();
+        pending.next_id += 1;
+        let id = pending.next_id;
+        pending.requests.insert(
+            id,
+            PendingImage {
+                args: args.clone(),
+                deferral,
+            },
+        );
+        id
+    };
+
+    let post = post.clone();
+    service_manager.handle_image_request(uri.clone(), move |result| {
+        post(Box::new(move |webview: &WebViewManager| {
+            webview.complete_image(id, &uri, result)
+        }));
+    });
+    Ok(())
+}
+
+/// Sets the response to an image request: the image, or a 404.
+fn respond_with_image(
+    environment: &ICoreWebView2Environment,
+    args: &ICoreWebView2WebResourceRequestedEventArgs,
+    uri: &str,
+    result: Result<images::CachedImage>,
+) -> Result<()> {
+    let response = match result {
+        Ok(image) => unsafe {
+            let stream = SHCreateMemStream(Some(&image.bytes));
+            let headers = format!(
+                "Content-Type: {}\r\nCache-Control: max-age=604800",
+                image.content_type
+            );
+            environment.CreateWebResourceResponse(
+                stream.as_ref(),
+                200,
+                &HSTRING::from("OK"),
+                &HSTRING::from(headers),
+            )?
+        },
+        Err(e) => {
+            log::warn!("Image request {} failed: {}", uri, e);
+            unsafe {
+                environment.CreateWebResourceResponse(
+                    None,
+                    404,
+                    &HSTRING::from("Not Found"),
+                    &HSTRING::from(""),
+                )?
+            }
+        }
+    };
+
+    unsafe { args.SetResponse(&response) }?;
+    Ok(())
+}
+
 pub struct WebViewManager {
     hwnd: HWND,
     _controller: ICoreWebView2Controller,
     webview: ICoreWebView2,
     _message_token: EventRegistrationToken,
     _handler: ICoreWebView2WebMessageReceivedEventHandler,
+    _resource_token: EventRegistrationToken,
+    _resource_handler: ICoreWebView2WebResourceRequestedEventHandler,
+    environment: ICoreWebView2Environment,
+    pending_images: Rc<RefCell<PendingImages>>,
 }
 
 impl WebViewManager {
@@ -137,11 +247,22 @@ impl WebViewManager {
             },
         ));
 
+        // Register the image scheme so the page can load cached artwork
+        let options = CoreWebView2EnvironmentOptions::default();
+        let image_scheme = CoreWebView2CustomSchemeRegistration::new(images::SCHEME.into());
+        unsafe {
+            image_scheme.set_treat_as_secure(true);
+            image_scheme.set_has_authority_component(true);
+            image_scheme.set_allowed_origins(vec!["*".into()]);
+            options.set_scheme_registrations(vec![Some(image_scheme.into())]);
+        }
+        let options: ICoreWebView2EnvironmentOptions = options.into();
+
         unsafe {
             CreateCoreWebView2EnvironmentWithOptions(
                 None,
                 PCWSTR::from_raw(user_data_path.as_ptr()),
-                None,
+                &options,
                 &env_handler,
             )
             .context("Failed to create WebView2 environment")?;
@@ -229,6 +350,30 @@ impl WebViewManager {
         }
 
         log::info!("Message handler registered successfully");
+        log::info!("Registering image scheme handler");
+        let mut resource_token = EventRegistrationToken::default();
+        let pending_images = Rc::new(RefCell::new(PendingImages::default()));
+        let resource_handler = {
+            let service_manager = service_manager.clone();
+            let pending_images = pending_images.clone();
+            let post: Arc<dyn Fn(UiTask) + Send + Sync> = Arc::new(window::ui_poster(hwnd));
+            WebResourceRequestedEventHandler::create(Box::new(move |_sender, args| {
+                if let Some(args) = args {
+                    if let Err(e) = serve_image(&service_manager, &pending_images, &post, &args) {
+                        log::error!("Image scheme handler failed: {}", e);
+                    }
+                }
+                Ok(())
+            }))
+        };
+
+        unsafe {
+            webview.AddWebResourceRequestedFilter(
+                &HSTRING::from(format!("{}://*", images::SCHEME)),
+                COREWEBVIEW2_WEB_RESOURCE_CONTEXT_ALL,
+            )?;
+            webview.add_WebResourceRequested(&resource_handler, &mut resource_token)?;
+        }
         log::info!("Setting initial bounds");
 
         // Set initial bounds
@@ -250,9 +395,32 @@ impl WebViewManager {
             webview,
             _message_token: message_token,
             _handler: handler,
+            _resource_token: resource_token,
+            _resource_handler: resource_handler,
+            environment,
+            pending_images,
         })
     }
 
+    /// Answers a deferred image request with what the image cache returned.
+    fn complete_image(&self, id: u64, uri: &str, result: Result<images::CachedImage>) {
+        let Some(pending) = self.pending_images.borrow_mut().requests.remove(&id) else {
+            return;
+        };
+        if let Err(e) = respond_with_image(&self.environment, &pending.args, uri, result) {
+            log::error!("Image scheme handler failed: {}", e);
+        }
+        if let Err(e) = unsafe { pending.deferral.Complete() } {
+            log::error!("Completing image request {} failed: {:?}", uri, e);
+        }
+    }
+
+    /// Sends a JSON message to the page outside of any request.
+    pub fn post_message(&self, json: &str) -> Result<()> {
+        unsafe { self.webview.PostWebMessageAsJson(&HSTRING::from(json)) }
+            .context("PostWebMessageAsJson failed")
+    }
+
     pub fn resize(&self, width: i32, height: i32) -> Result<()> {
         let bounds = RECT {
             left: 0,
@@ -269,6 +437,7 @@ impl Drop for WebViewManager {
         log::info!("Dropping WebViewManager");
         unsafe {
             let _ = self.webview.remove_WebMessageReceived(self._message_token);
+            let _ = self.webview.remove_WebResourceRequested(self._resource_token);
             let _ = self._controller.Close();
             if IsWindow(Some(self.hwnd)).as_bool() {
                 SetWindowLongPtrW(self.hwnd, GWLP_USERDATA, 0);
@@ -11,6 +11,11 @@ use windows::{
 
 use crate::webview::manager;
 
+/// Carries a boxed JSON `String` to post to the page; see `event_poster`.
+pub(super) const WM_POST_EVENT: u32 = WM_USER + 1;
+/// Carries a boxed `UiTask` to run with the WebView; see `ui_poster`.
+pub(super) const WM_RUN_ON_UI: u32 = WM_USER + 2;
+
 pub(super) unsafe fn create_window_instance(title: &str, width: i32, height: i32) -> Result<HWND> {
     let hinstance = GetModuleHandleW(None)?;
     let class_name = w!("StremioWindowClass");
@@ -77,11 +82,29 @@ extern "system" fn wndproc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM)
             }
             LRESULT(0)
         }
-        val if val == WM_USER + 1 => {
+        WM_POST_EVENT => {
             // Handle web messages safely
-            log::debug!("Received WM_USER+1 message");
-            let response = unsafe { Box::from_raw(lparam.0 as *mut String) };
-            log::debug!("Web response content: {}", response);
+            let message = unsafe { Box::from_raw(lparam.0 as *mut String) };
+            log::debug!("Posting event to page: {}", message);
+            unsafe {
+                let ptr = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut manager::WebViewManager;
+                if !ptr.is_null() {
+                    let webview = &*ptr;
+                    if let Err(e) = webview.post_message(&message) {
+                        log::error!("Posting event failed: {}", e);
+                    }
+                }
+            }
+            LRESULT(0)
+        }
+        WM_RUN_ON_UI => {
+            let task = unsafe { Box::from_raw(lparam.0 as *mut super::UiTask) };
+            unsafe {
+                let ptr = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut manager::WebViewManager;
+                if !ptr.is_null() {
+                    task(&*ptr);
+                }
+            }
             LRESULT(0)
         }
         _ => unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) },
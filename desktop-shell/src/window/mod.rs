@@ -1,10 +1,11 @@
 use anyhow::{Context, Result};
 use windows::Win32::{
-    Foundation::HWND,
+    Foundation::{HWND, LPARAM, WPARAM},
     UI::WindowsAndMessaging::*,
     Graphics::Gdi::UpdateWindow,
 };
 use crate::config::WindowConfig;
+use crate::webview::manager::WebViewManager;
 
 
 mod styling;
@@ -28,6 +29,58 @@ pub fn create_window(config: &WindowConfig) -> Result<HWND> {
     Ok(hwnd)
 }
 
+/// Returns a callback that queues a JSON message for the page from any
+/// thread. `wndproc` delivers it on the UI thread, the only one WebView2 may
+/// be called from.
+pub fn event_poster(hwnd: HWND) -> impl Fn(String) + Send + Sync + 'static {
+    // HWND is not Send; the raw handle value is.
+    let raw = hwnd.0 as isize;
+    move |message: String| {
+        let hwnd = HWND(raw as *mut _);
+        let message = Box::into_raw(Box::new(message));
+        let posted = unsafe {
+            PostMessageW(
+                Some(hwnd),
+                messaging::WM_POST_EVENT,
+                WPARAM(0),
+                LPARAM(message as isize),
+            )
+        };
+        if let Err(e) = posted {
+            log::warn!("Failed to queue event for the page: {}", e);
+            // Reclaim the message the window will never receive.
+            drop(unsafe { Box::from_raw(message) });
+        }
+    }
+}
+
+/// Work for the UI thread that needs the WebView, e.g. finishing a request
+/// answered on the runtime.
+pub type UiTask = Box<dyn FnOnce(&WebViewManager) + Send>;
+
+/// Returns a callback that queues a task to run on the UI thread from any
+/// thread. Tasks queued before the WebView exists, or after it is gone, are
+/// dropped.
+pub fn ui_poster(hwnd: HWND) -> impl Fn(UiTask) + Send + Sync + 'static {
+    let raw = hwnd.0 as isize;
+    move |task: UiTask| {
+        let hwnd = HWND(raw as *mut _);
+        let task = Box::into_raw(Box::new(task));
+        let posted = unsafe {
+            PostMessageW(
+                Some(hwnd),
+                messaging::WM_RUN_ON_UI,
+                WPARAM(0),
+                LPARAM(task as isize),
+            )
+        };
+        if let Err(e) = posted {
+            log::warn!("Failed to queue a task for the UI thread: {}", e);
+            drop(unsafe { Box::from_raw(task) });
+        }
+    }
+}
+
 pub fn run_message_loop(_hwnd: HWND) -> Result<()> {
     let mut msg = MSG::default();
     while unsafe { GetMessageW(&mut msg, None, 0, 0) }.into() {
@@ -1,8 +1,8 @@
 // src/app/page.tsx
 "use client";
-import { useEffect, useState, useCallback } from "react";
+import { useEffect, useRef, useState, useCallback } from "react";
 import Image from "next/image";
-import { rustBridge } from "@/lib/rust";
+import { cachedImageUrl, rustBridge } from "@/lib/rust";
 
 type MediaItem = {
   id: string;
@@ -11,16 +11,34 @@ type MediaItem = {
   poster: string;
 };
 
+type CatalogPage = {
+  items: MediaItem[];
+  skip: number;
+  total: number;
+  hasMore: boolean;
+  nextSkip: number | null;
+};
+
+const PAGE_SIZE = 50;
+
 export default function Home() {
   const [catalog, setCatalog] = useState<MediaItem[]>([]);
+  const [nextSkip, setNextSkip] = useState<number | null>(0);
   const [loading, setLoading] = useState(true);
   const [error, setError] = useState<string | null>(null);
+  const sentinel = useRef<HTMLDivElement>(null);
 
-  const loadInitialData = useCallback(async () => {
+  const loadPage = useCallback(async (skip: number) => {
     try {
       setLoading(true);
-      const data = await rustBridge.invoke<MediaItem[]>("getCatalog");
-      setCatalog(data);
+      const page = await rustBridge.invoke<CatalogPage>("getCatalog", {
+        skip,
+        limit: PAGE_SIZE,
+      });
+      setCatalog((items) =>
+        skip === 0 ? page.items : [...items, ...page.items],
+      );
+      setNextSkip(page.nextSkip);
       setError(null);
     } catch (error) {
       setError(
@@ -32,22 +50,44 @@ export default function Home() {
   }, []);
 
   useEffect(() => {
-    void loadInitialData();
-  }, [loadInitialData]);
+    void loadPage(0);
+  }, [loadPage]);
+
+  // Fetch the next page when the bottom of the grid scrolls into view. A
+  // failed load waits for the retry button rather than firing again.
+  useEffect(() => {
+    const target = sentinel.current;
+    if (!target || loading || error || nextSkip === null || nextSkip === 0)
+      return;
+
+    const observer = new IntersectionObserver((entries) => {
+      if (entries.some((entry) => entry.isIntersecting)) {
+        void loadPage(nextSkip);
+      }
+    });
+    observer.observe(target);
+    return () => observer.disconnect();
+  }, [loadPage, loading, error, nextSkip]);
 
   return (
     <div className="mx-auto max-w-4xl">
       {error && (
-        <div className="mb-4 rounded bg-red-100 p-4 text-red-700">
-          Error: {error}
+        <div className="mb-4 flex items-center justify-between gap-4 rounded bg-red-100 p-4 text-red-700">
+          <span>Error: {error}</span>
+          <button
+            type="button"
+            disabled={loading}
+            onClick={() => void loadPage(nextSkip ?? 0)}
+            className="rounded border border-red-700 px-3 py-1 hover:bg-red-200 disabled:opacity-50"
+          >
+            Retry
+          </button>
         </div>
       )}
 
-      {loading ? (
-        <div className="p-4 text-gray-500">Loading...</div>
-      ) : (
-        <MediaGrid items={catalog} />
-      )}
+      <MediaGrid items={catalog} />
+      {loading && <div className="p-4 text-gray-500">Loading...</div>}
+      <div ref={sentinel} />
     </div>
   );
 }
@@ -62,7 +102,8 @@ function MediaGrid({ items }: { items: MediaItem[] }) {
         >
           <Image
             priority
-            src={item.poster}
+            unoptimized
+            src={cachedImageUrl(item.poster, 400)}
             alt={item.title}
             width={400}
             height={600}
@@ -30,21 +30,39 @@ declare global {
   }
 }
 
+export type InvokeOptions = {
+  requestId?: string;
+  /**
+   * How long to wait for the reply. `null` waits for as long as the shell
+   * takes, for commands it answers only once their work is done.
+   */
+  timeoutMs?: number | null;
+};
+
+const DEFAULT_TIMEOUT_MS = 5000;
+
 export const rustBridge = {
-  invoke: async <T>(command: string, args?: unknown): Promise<T> => {
+  invoke: async <T>(
+    command: string,
+    args?: unknown,
+    {
+      requestId = crypto.randomUUID(),
+      timeoutMs = DEFAULT_TIMEOUT_MS,
+    }: InvokeOptions = {},
+  ): Promise<T> => {
     return new Promise((resolve, reject) => {
-      if (!window.chrome?.webview) {
+      const webview = window.chrome?.webview;
+      if (!webview) {
         reject(new Error("WebView bridge not available"));
         return;
       }
 
-      const requestId = crypto.randomUUID();
-      const abortController = new AbortController();
+      let timeoutId: number | undefined;
 
-      const timeoutId = window.setTimeout(() => {
-        abortController.abort();
-        reject(new Error(`Request timed out after 5000ms (${command})`));
-      }, 5000);
+      const cleanup = () => {
+        window.clearTimeout(timeoutId);
+        webview.removeEventListener("message", messageHandler);
+      };
 
       const messageHandler = (event: { data: string | object }) => {
         try {
@@ -68,11 +86,7 @@ export const rustBridge = {
 
           if (response.requestId !== requestId) return;
 
-          window.clearTimeout(timeoutId);
-          window.chrome?.webview?.removeEventListener(
-            "message",
-            messageHandler,
-          );
+          cleanup();
 
           if (!response.success || !response.data) {
             throw new Error(response.error ?? "Request failed");
@@ -81,18 +95,21 @@ export const rustBridge = {
           // Directly use the already-parsed data
           resolve(response.data);
         } catch (error) {
-          window.clearTimeout(timeoutId);
-          window.chrome?.webview?.removeEventListener(
-            "message",
-            messageHandler,
-          );
+          cleanup();
           reject(error instanceof Error ? error : new Error("Unknown error"));
         }
       };
 
-      window.chrome.webview.addEventListener("message", messageHandler, {
-        signal: abortController.signal,
-      });
+      webview.addEventListener("message", messageHandler);
+
+      if (timeoutMs !== null) {
+        timeoutId = window.setTimeout(() => {
+          cleanup();
+          reject(
+            new Error(`Request timed out after ${timeoutMs}ms (${command})`),
+          );
+        }, timeoutMs);
+      }
 
       try {
         const request: RustRequest = {
@@ -100,9 +117,9 @@ export const rustBridge = {
           cmd: command,
           args: args ?? null,
         };
-        window.chrome.webview.postMessage(JSON.stringify(request));
+        webview.postMessage(JSON.stringify(request));
       } catch (error) {
-        abortController.abort();
+        cleanup();
         reject(
           error instanceof Error ? error : new Error("Failed to send message"),
         );
@@ -110,3 +127,100 @@ export const rustBridge = {
     });
   },
 };
+
+const IMAGE_SCHEME = "westream-img";
+
+/**
+ * Routes a remote image through the shell's local image cache. `width`
+ * requests a resized copy; the shell snaps it to its nearest thumbnail size.
+ */
+export const cachedImageUrl = (src: string, width?: number): string => {
+  if (!src || !window.chrome?.webview) return src;
+  const params = new URLSearchParams({ src });
+  if (width) params.set("w", String(width));
+  return `${IMAGE_SCHEME}://image/?${params.toString()}`;
+};
+
+type RustEvent<T = unknown> = {
+  event: string;
+  data: T;
+};
+
+/**
+ * Subscribes to events the shell pushes without a request, such as
+ * `newEpisode`. Returns a function that unsubscribes.
+ */
+export const onRustEvent = <T>(
+  name: string,
+  handler: (data: T) => void,
+): (() => void) => {
+  const webview = window.chrome?.webview;
+  if (!webview) return () => {};
+
+  const listener = (event: { data: string | object }) => {
+    const message = (
+      typeof event.data === "string" ? JSON.parse(event.data) : event.data
+    ) as Partial<RustEvent<T>>;
+    if (message.event === name) handler(message.data as T);
+  };
+  webview.addEventListener("message", listener);
+  return () => webview.removeEventListener("message", listener);
+};
+
+export type AddonRequest = {
+  resource: string;
+  type: string;
+  id: string;
+  extra?: [string, string][];
+};
+
+export type AddonResult<T = unknown> = {
+  requestId: string;
+  transportUrl: string;
+  name: string;
+  response: T;
+};
+
+export type AddonResultsSummary = {
+  requestId: string;
+  succeeded: string[];
+  failed: { transportUrl: string; name: string; error: string }[];
+  elapsedMs: number;
+};
+
+/**
+ * Asks every addon that handles `request` at once. `onResult` runs for each
+ * addon's answer as it arrives; resolves with the summary once every addon
+ * has answered, failed or timed out.
+ */
+export const queryAddons = <T>(
+  request: AddonRequest,
+  onResult: (result: AddonResult<T>) => void,
+): Promise<AddonResultsSummary> => {
+  const requestId = crypto.randomUUID();
+  return new Promise((resolve, reject) => {
+    const unsubscribeResult = onRustEvent<AddonResult<T>>(
+      "addonResult",
+      (result) => {
+        if (result.requestId === requestId) onResult(result);
+      },
+    );
+    const unsubscribeDone = onRustEvent<AddonResultsSummary>(
+      "addonResultsDone",
+      (summary) => {
+        if (summary.requestId !== requestId) return;
+        unsubscribeResult();
+        unsubscribeDone();
+        resolve(summary);
+      },
+    );
+    // Routing can wait on id lookups; the summary event bounds the query.
+    rustBridge
+      .invoke("addonResource", request, { requestId, timeoutMs: null })
+      .catch((error) => {
+        unsubscribeResult();
+        unsubscribeDone();
+        reject(error instanceof Error ? error : new Error("Request failed"));
+      });
+  });
+};